@@ -411,8 +411,11 @@ impl<Block: BlockT> blockchain::Backend<Block> for Blockchain<Block> {
 		Ok(self.storage.read().leaves.hashes())
 	}
 
-	fn children(&self, _parent_hash: Block::Hash) -> sp_blockchain::Result<Vec<Block::Hash>> {
-		unimplemented!()
+	fn children(&self, parent_hash: Block::Hash) -> sp_blockchain::Result<Vec<Block::Hash>> {
+		Ok(self.storage.read().blocks.iter()
+			.filter(|(_, block)| *block.header().parent_hash() == parent_hash)
+			.map(|(hash, _)| hash.clone())
+			.collect())
 	}
 
 	fn indexed_transaction(
@@ -866,4 +869,26 @@ mod tests {
 			Err(sp_blockchain::Error::BadJustification(_)),
 		));
 	}
+
+	#[test]
+	fn children_returns_all_blocks_with_the_given_parent() {
+		let blockchain = test_blockchain();
+		let fork = Header::new(
+			2,
+			H256::from_low_u64_be(1),
+			H256::from_low_u64_be(0),
+			header(1).hash(),
+			Default::default(),
+		);
+		blockchain.insert(fork.hash(), fork.clone(), None, None, NewBlockState::Normal).unwrap();
+
+		let mut children = blockchain.children(header(1).hash()).unwrap();
+		children.sort();
+		let mut expected = vec![header(2).hash(), fork.hash()];
+		expected.sort();
+		assert_eq!(children, expected);
+
+		assert_eq!(blockchain.children(header(2).hash()).unwrap(), vec![header(3).hash()]);
+		assert!(blockchain.children(header(3).hash()).unwrap().is_empty());
+	}
 }
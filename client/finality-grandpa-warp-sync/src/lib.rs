@@ -30,8 +30,10 @@ use sc_service::{SpawnTaskHandle, config::{Configuration, Role}};
 use sc_finality_grandpa::SharedAuthoritySet;
 
 mod proof;
+mod provider;
 
 pub use proof::{WarpSyncFragment, WarpSyncProof};
+pub use provider::{FinalityProofWarpAdapter, WarpSyncFragmentProvider};
 
 /// Generates the appropriate [`RequestResponseConfig`] for a given chain configuration.
 pub fn request_response_config_for_chain<TBlock: BlockT, TBackend: Backend<TBlock> + 'static>(
@@ -167,6 +169,7 @@ pub enum HandleRequestError {
 	#[display(fmt = "Failed to decode block hash: {}.", _0)]
 	DecodeScale(codec::Error),
 	Client(sp_blockchain::Error),
+	FinalityProof(sc_finality_grandpa::FinalityProofError),
 	#[from(ignore)]
 	InvalidRequest(String),
 	#[from(ignore)]
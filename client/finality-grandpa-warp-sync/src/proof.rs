@@ -214,7 +214,7 @@ impl<Block: BlockT> WarpSyncProof<Block> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
 	use crate::WarpSyncProof;
 	use codec::Encode;
 	use rand::prelude::*;
@@ -222,7 +222,7 @@ mod tests {
 	use sc_finality_grandpa::{AuthoritySetChanges, GrandpaJustification};
 	use sp_blockchain::HeaderBackend;
 	use sp_consensus::BlockOrigin;
-	use sp_finality_grandpa::GRANDPA_ENGINE_ID;
+	use sp_finality_grandpa::{AuthorityList, SetId, GRANDPA_ENGINE_ID};
 	use sp_keyring::Ed25519Keyring;
 	use sp_runtime::{generic::BlockId, traits::Header as _};
 	use std::sync::Arc;
@@ -231,8 +231,17 @@ mod tests {
 		TestClientBuilderExt,
 	};
 
-	#[test]
-	fn warp_sync_proof_generate_verify() {
+	/// A test chain of 100 blocks, with an authority set change every 10 blocks.
+	pub(crate) struct TestChain {
+		pub(crate) client: Arc<substrate_test_runtime_client::TestClient>,
+		pub(crate) backend: Arc<substrate_test_runtime_client::Backend>,
+		pub(crate) authority_set_changes: AuthoritySetChanges<u64>,
+		pub(crate) genesis_authorities: AuthorityList,
+		pub(crate) current_set_id: SetId,
+		pub(crate) current_authorities: Vec<Ed25519Keyring>,
+	}
+
+	pub(crate) fn test_chain() -> TestChain {
 		let mut rng = rand::rngs::StdRng::from_seed([0; 32]);
 		let builder = TestClientBuilder::new();
 		let backend = builder.backend();
@@ -337,7 +346,26 @@ mod tests {
 			}
 		}
 
-		let authority_set_changes = AuthoritySetChanges::from(authority_set_changes);
+		TestChain {
+			client,
+			backend,
+			authority_set_changes: AuthoritySetChanges::from(authority_set_changes),
+			genesis_authorities,
+			current_set_id,
+			current_authorities,
+		}
+	}
+
+	#[test]
+	fn warp_sync_proof_generate_verify() {
+		let TestChain {
+			client,
+			backend,
+			authority_set_changes,
+			genesis_authorities,
+			current_set_id,
+			current_authorities,
+		} = test_chain();
 
 		// generate a warp sync proof
 		let genesis_hash = client.hash(0).unwrap().unwrap();
@@ -0,0 +1,105 @@
+// Copyright 2021 Parity Technologies (UK) Ltd.
+// This file is part of Substrate.
+
+// Substrate is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Substrate is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Substrate.  If not, see <http://www.gnu.org/licenses/>.
+
+use codec::Decode;
+
+use sc_client_api::Backend as ClientBackend;
+use sc_finality_grandpa::{BlockNumberOps, FinalityProofProvider, GrandpaJustification};
+use sp_blockchain::HeaderBackend;
+use sp_runtime::{
+	generic::BlockId,
+	traits::{Block as BlockT, NumberFor},
+};
+use std::sync::Arc;
+
+use crate::{HandleRequestError, WarpSyncFragment};
+
+/// Provides the warp sync fragments proving the finality of checkpoint blocks.
+pub trait WarpSyncFragmentProvider<Block: BlockT>: Send + Sync {
+	/// Generate the fragment proving finality of the given checkpoint block. Returns `None` if
+	/// there is no justification that could prove it.
+	fn fragment(
+		&self,
+		checkpoint: NumberFor<Block>,
+	) -> Result<Option<WarpSyncFragment<Block>>, HandleRequestError>;
+}
+
+/// A [`WarpSyncFragmentProvider`] backed by a [`FinalityProofProvider`].
+///
+/// Checkpoint requests are translated into finality proof requests, and the fragment is made of
+/// the justification of the proof together with the header of the justified block.
+pub struct FinalityProofWarpAdapter<Backend, Block: BlockT> {
+	backend: Arc<Backend>,
+	provider: Arc<FinalityProofProvider<Backend, Block>>,
+}
+
+impl<Backend, Block: BlockT> FinalityProofWarpAdapter<Backend, Block> {
+	/// Create a new [`FinalityProofWarpAdapter`].
+	pub fn new(
+		backend: Arc<Backend>,
+		provider: Arc<FinalityProofProvider<Backend, Block>>,
+	) -> Self {
+		FinalityProofWarpAdapter { backend, provider }
+	}
+}
+
+impl<Backend, Block> WarpSyncFragmentProvider<Block> for FinalityProofWarpAdapter<Backend, Block>
+where
+	Block: BlockT,
+	NumberFor<Block>: BlockNumberOps,
+	Backend: ClientBackend<Block> + Send + Sync + 'static,
+{
+	fn fragment(
+		&self,
+		checkpoint: NumberFor<Block>,
+	) -> Result<Option<WarpSyncFragment<Block>>, HandleRequestError> {
+		let proof = match self.provider.prove_finality_detailed(checkpoint)? {
+			Some(proof) => proof.proof,
+			None => return Ok(None),
+		};
+
+		let justification = GrandpaJustification::<Block>::decode(&mut &proof.justification[..])?;
+		let header = self.backend.blockchain().header(BlockId::Hash(proof.block))?.ok_or_else(|| {
+			HandleRequestError::InvalidProof("Missing header of the justified block".to_string())
+		})?;
+
+		Ok(Some(WarpSyncFragment { header, justification }))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::proof::tests::{test_chain, TestChain};
+	use sp_runtime::traits::Header as _;
+
+	#[test]
+	fn adapter_generates_valid_fragment_for_checkpoint() {
+		let TestChain { backend, authority_set_changes, genesis_authorities, .. } = test_chain();
+
+		let provider = Arc::new(FinalityProofProvider::from_changes_snapshot(
+			backend.clone(),
+			authority_set_changes,
+		));
+		let adapter = FinalityProofWarpAdapter::new(backend, provider);
+
+		// Block 5 is in the genesis set, which ended at block 10.
+		let fragment = adapter.fragment(5).unwrap().unwrap();
+		assert_eq!(*fragment.header.number(), 10);
+		assert_eq!(fragment.justification.target().1, fragment.header.hash());
+		fragment.justification.verify(0, &genesis_authorities).unwrap();
+	}
+}
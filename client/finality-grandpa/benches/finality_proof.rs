@@ -0,0 +1,153 @@
+// This file is part of Substrate.
+
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use std::sync::Arc;
+
+use parity_scale_codec::Encode;
+use sc_client_api::{
+	in_mem::{Backend as InMemoryBackend, Blockchain as InMemoryBlockchain},
+	Backend, NewBlockState,
+};
+use sc_finality_grandpa::{
+	check_finality_proof_with_config, AuthoritySetChanges, FinalityProof,
+	FinalityProofCheckConfig, FinalityProofConfig, FinalityProofProvider, GrandpaJustification,
+};
+use sp_core::{ed25519, Pair};
+use sp_finality_grandpa::{AuthorityId, AuthorityList, GRANDPA_ENGINE_ID};
+use sp_runtime::{traits::Header as _, Justifications};
+use substrate_test_runtime_client::runtime::{Block, Header, H256};
+
+const CHAIN_LENGTH: u64 = 10_000;
+const AUTHORITIES: u8 = 100;
+
+fn test_backend() -> Arc<InMemoryBackend<Block>> {
+	let backend = Arc::new(InMemoryBackend::<Block>::new());
+	let mut parent_hash = Default::default();
+
+	for number in 0..=CHAIN_LENGTH {
+		let header = Header::new(
+			number,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			parent_hash,
+			Default::default(),
+		);
+		let justifications = if number == CHAIN_LENGTH {
+			Some(Justifications::from((GRANDPA_ENGINE_ID, vec![42])))
+		} else {
+			None
+		};
+
+		parent_hash = header.hash();
+		backend
+			.blockchain()
+			.insert(header.hash(), header, justifications, None, NewBlockState::Final)
+			.unwrap();
+	}
+
+	backend
+}
+
+fn collect_headers(c: &mut Criterion) {
+	let backend = test_backend();
+	let authority_set_changes = AuthoritySetChanges::from(vec![(0, CHAIN_LENGTH)]);
+
+	let sequential = FinalityProofProvider::<_, Block>::from_changes_snapshot(
+		backend.clone(),
+		authority_set_changes.clone(),
+	);
+	let parallel = FinalityProofProvider::<_, Block>::from_changes_snapshot(
+		backend,
+		authority_set_changes,
+	)
+	.with_config(FinalityProofConfig {
+		parallel_header_collection_threshold: Some(64),
+		..Default::default()
+	});
+
+	let mut group = c.benchmark_group("finality_proof_collect_headers");
+	group.bench_function("sequential", |b| b.iter(|| sequential.prove_finality(1).unwrap()));
+	group.bench_function("parallel", |b| b.iter(|| parallel.prove_finality(1).unwrap()));
+	group.finish();
+}
+
+fn signed_proof() -> (AuthorityList, Vec<u8>) {
+	let header = Header::new(
+		1,
+		H256::from_low_u64_be(0),
+		H256::from_low_u64_be(0),
+		Default::default(),
+		Default::default(),
+	);
+	let precommit = finality_grandpa::Precommit { target_hash: header.hash(), target_number: 1 };
+	let message = finality_grandpa::Message::Precommit(precommit.clone());
+	let payload = sp_finality_grandpa::localized_payload(1, 0, &message);
+
+	let pairs = (0..AUTHORITIES).map(|i| ed25519::Pair::from_seed(&[i; 32])).collect::<Vec<_>>();
+	let precommits = pairs
+		.iter()
+		.map(|pair| finality_grandpa::SignedPrecommit {
+			precommit: precommit.clone(),
+			signature: pair.sign(&payload).into(),
+			id: pair.public().into(),
+		})
+		.collect();
+	let commit = finality_grandpa::Commit {
+		target_hash: header.hash(),
+		target_number: 1,
+		precommits,
+	};
+	let justification = GrandpaJustification::<Block>::from_commit(
+		&Arc::new(InMemoryBlockchain::<Block>::new()),
+		1,
+		commit,
+	)
+	.unwrap();
+
+	let authorities = pairs.iter().map(|pair| (AuthorityId::from(pair.public()), 1)).collect();
+	let proof = FinalityProof {
+		block: header.hash(),
+		justification: justification.encode(),
+		unknown_headers: vec![header],
+	};
+
+	(authorities, proof.encode())
+}
+
+fn verify_signatures(c: &mut Criterion) {
+	let (authorities, proof) = signed_proof();
+	let check = |verify_signatures_in_parallel| {
+		check_finality_proof_with_config::<Header, GrandpaJustification<Block>>(
+			0,
+			authorities.clone(),
+			proof.clone(),
+			&FinalityProofCheckConfig { verify_signatures_in_parallel, ..Default::default() },
+		)
+		.unwrap()
+	};
+
+	let mut group = c.benchmark_group("finality_proof_verify_signatures");
+	group.bench_function("sequential", |b| b.iter(|| check(false)));
+	group.bench_function("parallel", |b| b.iter(|| check(true)));
+	group.finish();
+}
+
+criterion_group!(benches, collect_headers, verify_signatures);
+criterion_main!(benches);
@@ -678,6 +678,18 @@ impl<H, N: Add<Output=N> + Clone> PendingChange<H, N> {
 	}
 }
 
+/// The outcome of looking up which authority set a given block belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AuthoritySetChangeId<N> {
+	/// The requested block is in the latest authority set.
+	Latest,
+	/// Tuple containing the set id and the last block number of that set.
+	Set(u64, N),
+	/// We don't know which set id the request block belongs to (this can only happen due to
+	/// missing data).
+	Unknown,
+}
+
 /// Tracks historical authority set changes. We store the block numbers for the last block
 /// of each authority set, once they have been finalized. These blocks are guaranteed to
 /// have a justification unless they were triggered by a forced change.
@@ -699,7 +711,15 @@ impl<N: Ord + Clone> AuthoritySetChanges<N> {
 		self.0.push((set_id, block_number));
 	}
 
-	pub(crate) fn get_set_id(&self, block_number: N) -> Option<(u64, N)> {
+	pub(crate) fn get_set_id(&self, block_number: N) -> AuthoritySetChangeId<N> {
+		if self.0
+			.last()
+			.map(|last_auth_change| last_auth_change.1 < block_number)
+			.unwrap_or(false)
+		{
+			return AuthoritySetChangeId::Latest;
+		}
+
 		let idx = self.0
 			.binary_search_by_key(&block_number, |(_, n)| n.clone())
 			.unwrap_or_else(|b| b);
@@ -711,19 +731,40 @@ impl<N: Ord + Clone> AuthoritySetChanges<N> {
 				let (prev_set_id, _) = self.0[idx - 1usize];
 				if set_id != prev_set_id + 1u64 {
 					// Without the preceding set_id we don't have a well-defined start.
-					return None;
+					return AuthoritySetChangeId::Unknown;
 				}
 			} else if set_id != 0 {
 				// If this is the first index, yet not the first set id then it's not well-defined
 				// that we are in the right set id.
-				return None;
+				return AuthoritySetChangeId::Unknown;
 			}
-			Some((set_id, block_number))
+			AuthoritySetChangeId::Set(set_id, block_number)
 		} else {
-			None
+			AuthoritySetChangeId::Unknown
 		}
 	}
 
+	/// Returns the set changes surrounding the given block number, i.e. the last change strictly
+	/// before it and the first change at or after it. These are the entries considered when
+	/// looking up the set id of the block.
+	pub(crate) fn boundaries_around(
+		&self,
+		block_number: N,
+	) -> (Option<(u64, N)>, Option<(u64, N)>) {
+		let idx = self.0
+			.binary_search_by_key(&block_number, |(_, n)| n.clone())
+			.unwrap_or_else(|b| b);
+
+		let previous = if idx > 0 { self.0.get(idx - 1).cloned() } else { None };
+		(previous, self.0.get(idx).cloned())
+	}
+
+	/// Returns an iterator over all historical authority set changes. The iterator yields a tuple
+	/// representing the set id and the block number of the last block in that set.
+	pub(crate) fn iter(&self) -> impl Iterator<Item = &(u64, N)> {
+		self.0.iter()
+	}
+
 	/// Returns an iterator over all historical authority set changes starting at the given block
 	/// number (excluded). The iterator yields a tuple representing the set id and the block number
 	/// of the last block in that set.
@@ -1660,11 +1701,11 @@ mod tests {
 		authority_set_changes.append(1, 81);
 		authority_set_changes.append(2, 121);
 
-		assert_eq!(authority_set_changes.get_set_id(20), Some((0, 41)));
-		assert_eq!(authority_set_changes.get_set_id(40), Some((0, 41)));
-		assert_eq!(authority_set_changes.get_set_id(41), Some((0, 41)));
-		assert_eq!(authority_set_changes.get_set_id(42), Some((1, 81)));
-		assert_eq!(authority_set_changes.get_set_id(141), None);
+		assert_eq!(authority_set_changes.get_set_id(20), AuthoritySetChangeId::Set(0, 41));
+		assert_eq!(authority_set_changes.get_set_id(40), AuthoritySetChangeId::Set(0, 41));
+		assert_eq!(authority_set_changes.get_set_id(41), AuthoritySetChangeId::Set(0, 41));
+		assert_eq!(authority_set_changes.get_set_id(42), AuthoritySetChangeId::Set(1, 81));
+		assert_eq!(authority_set_changes.get_set_id(141), AuthoritySetChangeId::Latest);
 	}
 
 	#[test]
@@ -1674,11 +1715,26 @@ mod tests {
 		authority_set_changes.append(3, 81);
 		authority_set_changes.append(4, 121);
 
-		assert_eq!(authority_set_changes.get_set_id(20), None);
-		assert_eq!(authority_set_changes.get_set_id(40), None);
-		assert_eq!(authority_set_changes.get_set_id(41), None);
-		assert_eq!(authority_set_changes.get_set_id(42), Some((3, 81)));
-		assert_eq!(authority_set_changes.get_set_id(141), None);
+		assert_eq!(authority_set_changes.get_set_id(20), AuthoritySetChangeId::Unknown);
+		assert_eq!(authority_set_changes.get_set_id(40), AuthoritySetChangeId::Unknown);
+		assert_eq!(authority_set_changes.get_set_id(41), AuthoritySetChangeId::Unknown);
+		assert_eq!(authority_set_changes.get_set_id(42), AuthoritySetChangeId::Set(3, 81));
+		assert_eq!(authority_set_changes.get_set_id(141), AuthoritySetChangeId::Latest);
+	}
+
+	#[test]
+	fn authority_set_changes_boundaries_around() {
+		let mut authority_set_changes = AuthoritySetChanges::empty();
+		assert_eq!(authority_set_changes.boundaries_around(20), (None, None));
+
+		authority_set_changes.append(1, 41);
+		authority_set_changes.append(2, 81);
+
+		assert_eq!(authority_set_changes.boundaries_around(20), (None, Some((1, 41))));
+		assert_eq!(authority_set_changes.boundaries_around(41), (None, Some((1, 41))));
+		assert_eq!(authority_set_changes.boundaries_around(42), (Some((1, 41)), Some((2, 81))));
+		assert_eq!(authority_set_changes.boundaries_around(81), (Some((1, 41)), Some((2, 81))));
+		assert_eq!(authority_set_changes.boundaries_around(141), (Some((2, 81)), None));
 	}
 
 	#[test]
@@ -43,6 +43,7 @@ const SET_STATE_KEY: &[u8] = b"grandpa_completed_round";
 const CONCLUDED_ROUNDS: &[u8] = b"grandpa_concluded_rounds";
 const AUTHORITY_SET_KEY: &[u8] = b"grandpa_voters";
 const BEST_JUSTIFICATION: &[u8] = b"grandpa_best_justification";
+const BOUNDARY_PROOFS: &[u8] = b"grandpa_boundary_proofs";
 
 const CURRENT_VERSION: u32 = 3;
 
@@ -545,6 +546,36 @@ pub(crate) fn write_concluded_round<Block: BlockT, B: AuxStore>(
 	backend.insert_aux(&[(&key[..], round_data.encode().as_slice())], &[])
 }
 
+/// The finality proof of the last block of an authority set, as persisted by the finality proof
+/// provider: the hash of the block, the number and hash of the justified block, and the encoded
+/// proof.
+pub(crate) type BoundaryProof<Block> =
+	(<Block as BlockT>::Hash, (NumberFor<Block>, <Block as BlockT>::Hash), Vec<u8>);
+
+fn boundary_proof_key<Block: BlockT>(number: NumberFor<Block>) -> Vec<u8> {
+	let mut key = BOUNDARY_PROOFS.to_vec();
+	number.using_encoded(|n| key.extend(n));
+	key
+}
+
+/// Write the finality proof of the set boundary at the given block.
+pub(crate) fn write_boundary_proof<Block: BlockT, B: AuxStore>(
+	backend: &B,
+	number: NumberFor<Block>,
+	proof: &BoundaryProof<Block>,
+) -> ClientResult<()> {
+	let key = boundary_proof_key::<Block>(number);
+	backend.insert_aux(&[(&key[..], proof.encode().as_slice())], &[])
+}
+
+/// Fetch the finality proof of the set boundary at the given block, if it was persisted.
+pub(crate) fn load_boundary_proof<Block: BlockT, B: AuxStore>(
+	backend: &B,
+	number: NumberFor<Block>,
+) -> ClientResult<Option<BoundaryProof<Block>>> {
+	load_decode::<_, BoundaryProof<Block>>(backend, &boundary_proof_key::<Block>(number))
+}
+
 #[cfg(test)]
 pub(crate) fn load_authorities<B: AuxStore, H: Decode, N: Decode + Clone + Ord>(
 	backend: &B,
@@ -36,30 +36,307 @@
 //! finality proof (that finalizes some block C that is ancestor of the B and descendant
 //! of the U) could be returned.
 
-use log::trace;
-use std::sync::Arc;
+use bitvec::vec::BitVec;
+use futures::Stream;
+use log::{trace, warn};
+use parking_lot::Mutex;
+use std::{
+	collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+	future::Future,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 use finality_grandpa::BlockNumberOps;
-use parity_scale_codec::{Encode, Decode};
+use parity_scale_codec::{Compact, Encode, Decode};
 use sp_blockchain::{Backend as BlockchainBackend, Error as ClientError, Result as ClientResult};
 use sp_runtime::{
-	EncodedJustification, generic::BlockId,
-	traits::{NumberFor, Block as BlockT, Header as HeaderT, One},
+	ConsensusEngineId, EncodedJustification, SaturatedConversion,
+	generic::{BlockId, OpaqueDigestItemId},
+	traits::{HashFor, NumberFor, Block as BlockT, Header as HeaderT, One, Saturating, Zero},
+};
+use prometheus_endpoint::{register, Gauge, PrometheusError, Registry, U64};
+use sc_client_api::{backend::Backend, BlockImportNotification, FinalityNotification};
+use sp_api::ProvideRuntimeApi;
+use sp_core::{ed25519, hashing::blake2_256, hexdisplay::HexDisplay, Pair};
+use sp_trie::TrieConfiguration;
+use sp_finality_grandpa::{
+	AuthorityId, ConsensusLog, GrandpaApi, ScheduledChange, SetId, GRANDPA_ENGINE_ID,
 };
-use sc_client_api::backend::Backend;
-use sp_finality_grandpa::{AuthorityId, GRANDPA_ENGINE_ID};
 
-use crate::authorities::AuthoritySetChanges;
+use crate::authorities::{AuthoritySetChangeId, AuthoritySetChanges};
 use crate::justification::GrandpaJustification;
+use crate::notification::GrandpaJustificationStream;
 use crate::SharedAuthoritySet;
 use crate::VoterSet;
 
 const MAX_UNKNOWN_HEADERS: usize = 100_000;
 
+/// Default maximum number of blocks searched for a stored justification when truncating a proof
+/// to the nearest justified ancestor. Justifications are stored at least every
+/// `justification_period` blocks, which is a fraction of this.
+const MAX_JUSTIFICATION_LOOKUPS: usize = 4096;
+
+/// Default maximum number of finalized headers searched for a requested state root.
+const MAX_STATE_ROOT_SEARCH_DEPTH: usize = 4096;
+
+/// Default maximum number of blocks whose justifications are listed at once.
+const MAX_JUSTIFICATION_RANGE: usize = 4096;
+
+/// Configuration of the finality proof generation.
+#[derive(Debug, Clone)]
+pub struct FinalityProofConfig<Block: BlockT> {
+	/// The maximum number of headers included in a single proof. If the (B; F] range is longer
+	/// the headers closest to F are dropped.
+	pub max_unknown_headers: usize,
+	/// The maximum distance between the requested block and the block whose justification would
+	/// be used to prove its finality. Requests exceeding it are rejected before doing any work.
+	pub max_gap: Option<NumberFor<Block>>,
+	/// The finality gadget whose stored justifications are served.
+	pub finality_source: FinalitySource,
+	/// Collect the headers of a proof in parallel when there are more than this many of them.
+	/// This can reduce the wall-clock time of proof generation on backends with high read
+	/// latency, the resulting headers are the same as when collected sequentially. Requires the
+	/// `parallel` feature, the headers are always collected sequentially otherwise.
+	pub parallel_header_collection_threshold: Option<usize>,
+	/// What to do when a block in the latest authority set is requested but no best
+	/// justification is stored.
+	pub no_justification_policy: NoJustificationPolicy,
+	/// What to do when a proof would include more than `max_unknown_headers` headers.
+	pub truncation_strategy: TruncationStrategy,
+	/// The maximum number of blocks searched for a stored justification with
+	/// [`TruncationStrategy::NearestJustifiedAncestor`].
+	pub max_justification_lookups: usize,
+	/// Include the hashes of the known siblings of the justified block in [`FinalityProofV2`]
+	/// proofs, allowing clients to detect that they are on a different fork.
+	pub include_sibling_hints: bool,
+	/// Regenerate cached proofs of blocks of the latest authority set older than this, so that a
+	/// newer best justification is eventually served for them. Proofs ending at a set boundary
+	/// never change and are cached indefinitely. See also
+	/// [`FinalityProofProvider::with_proof_cache`].
+	pub max_cached_proof_age: Option<Duration>,
+	/// Log the blake2-256 hash of each served proof together with the requested block, e.g. to
+	/// correlate identical proofs served to many peers.
+	pub log_served_proof_hashes: bool,
+	/// Embed the authority set change signalled by the justified block in [`FinalityProofV2`]
+	/// proofs, sparing clients from parsing its digest. Requires
+	/// [`Self::include_justified_header`], since clients check the change against F's header.
+	pub embed_set_change: bool,
+	/// Embed the genesis hash of the chain in [`FinalityProofV2`] proofs, allowing clients to
+	/// reject proofs of another chain, see [`check_finality_proof_v2_for_chain`].
+	pub embed_genesis_hash: bool,
+	/// What to do when a block in the latest authority set is requested but the best
+	/// justification doesn't cover it yet.
+	pub uncovered_block_policy: UncoveredBlockPolicy,
+	/// Trim the served GRANDPA justifications to the first precommits reaching the threshold of
+	/// the authority set, reducing the size of proofs of justifications with more precommits
+	/// than necessary. Justifications of sets whose authorities aren't known are served as is.
+	pub minimize_quorum: bool,
+	/// The maximum size in bytes of the headers of a proof. Proofs exceeding it fail with
+	/// [`FinalityProofError::ProofTooLarge`], and requests estimated to exceed it are rejected by
+	/// [`FinalityProofProvider::validate_request`] before any header is collected.
+	pub max_proof_size: Option<usize>,
+	/// The maximum number of finalized headers searched, starting from the finalized head, for the
+	/// block with a requested state root, see
+	/// [`FinalityProofProvider::prove_finality_by_state_root`].
+	pub max_state_root_search_depth: usize,
+	/// The maximum number of finalized blocks whose justifications are listed at once, see
+	/// [`FinalityProofProvider::justifications_in_range`].
+	pub max_justification_range: usize,
+	/// Include the header of the justified block F as the last header of the proofs, i.e. the
+	/// headers (B; F] rather than (B; F). Clients need it to check the number of F or the
+	/// authority set change it signals, e.g. with [`FinalityProofCheckConfig::reject_set_changes`]
+	/// or [`Self::embed_set_change`]. Clients that don't expect it would import F's header as an
+	/// unknown header, so it is only included if configured to.
+	pub include_justified_header: bool,
+}
+
+impl<Block: BlockT> Default for FinalityProofConfig<Block> {
+	fn default() -> Self {
+		FinalityProofConfig {
+			max_unknown_headers: MAX_UNKNOWN_HEADERS,
+			max_gap: None,
+			finality_source: FinalitySource::Grandpa,
+			parallel_header_collection_threshold: None,
+			no_justification_policy: NoJustificationPolicy::ReturnNone,
+			truncation_strategy: TruncationStrategy::TruncateHigh,
+			max_justification_lookups: MAX_JUSTIFICATION_LOOKUPS,
+			include_sibling_hints: false,
+			max_cached_proof_age: None,
+			log_served_proof_hashes: false,
+			embed_set_change: false,
+			embed_genesis_hash: false,
+			uncovered_block_policy: UncoveredBlockPolicy::ReturnNotYetProvable,
+			minimize_quorum: false,
+			max_proof_size: None,
+			max_state_root_search_depth: MAX_STATE_ROOT_SEARCH_DEPTH,
+			max_justification_range: MAX_JUSTIFICATION_RANGE,
+			include_justified_header: false,
+		}
+	}
+}
+
+impl<Block: BlockT> FinalityProofConfig<Block> {
+	/// The number of the last header of a proof whose justified block is `just_block`.
+	fn last_proof_header(&self, just_block: NumberFor<Block>) -> NumberFor<Block> {
+		if self.include_justified_header {
+			just_block
+		} else {
+			just_block.saturating_sub(One::one())
+		}
+	}
+}
+
+/// The finality gadget whose justifications are used to prove finality.
+///
+/// The proof format and the way the justified block is picked are independent of the gadget
+/// that produced the justification, the verifier is expected to use the matching
+/// [`ProvableJustification`] implementation to check it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FinalitySource {
+	/// GRANDPA justifications.
+	Grandpa,
+	/// Justifications of another finality gadget, identified by its consensus engine id.
+	Other(ConsensusEngineId),
+}
+
+impl FinalitySource {
+	/// The consensus engine id the justifications are stored under.
+	pub fn engine_id(&self) -> ConsensusEngineId {
+		match self {
+			FinalitySource::Grandpa => GRANDPA_ENGINE_ID,
+			FinalitySource::Other(engine_id) => *engine_id,
+		}
+	}
+}
+
+/// A monotonic source of the current time, allowing tests to control time deterministically.
+pub trait Clock: Send + Sync {
+	/// The current time.
+	fn now(&self) -> Instant;
+}
+
+/// The monotonic clock of the system, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+}
+
 /// Finality proof provider for serving network requests.
 pub struct FinalityProofProvider<BE, Block: BlockT> {
 	backend: Arc<BE>,
 	shared_authority_set: Option<SharedAuthoritySet<Block::Hash, NumberFor<Block>>>,
+	authority_set_changes_snapshot: Option<AuthoritySetChanges<NumberFor<Block>>>,
+	config: FinalityProofConfig<Block>,
+	metrics: Option<Metrics>,
+	signing_key: Option<ed25519::Pair>,
+	proof_cache: Option<Mutex<ProofCache<Block>>>,
+	header_codec: Option<Box<dyn HeaderCodec<Block::Header>>>,
+	clock: Box<dyn Clock>,
+	justification_stream: Option<GrandpaJustificationStream<Block>>,
+}
+
+/// A cache of the encoded finality proofs served for each block number.
+struct ProofCache<Block: BlockT> {
+	capacity: usize,
+	entries: BTreeMap<NumberFor<Block>, CachedProof<Block>>,
+}
+
+/// An encoded finality proof in the [`ProofCache`].
+struct CachedProof<Block: BlockT> {
+	/// The hash of the block at the requested height.
+	hash: Block::Hash,
+	/// The number and hash of the justified block F.
+	justified: (NumberFor<Block>, Block::Hash),
+	/// Whether the proof uses the best justification of the latest authority set.
+	from_best_justification: bool,
+	/// The time when the proof expires, if it does.
+	expires_at: Option<Instant>,
+	proof: Vec<u8>,
+}
+
+impl<Block: BlockT> ProofCache<Block> {
+	fn new(capacity: usize) -> Self {
+		ProofCache { capacity, entries: BTreeMap::new() }
+	}
+
+	/// Get the cached proof for the given block together with the number and hash of its
+	/// justified block, evicting it if it was generated for a different block at that height or
+	/// it has expired.
+	fn get(
+		&mut self,
+		number: NumberFor<Block>,
+		hash: Block::Hash,
+		now: Instant,
+	) -> Option<(Vec<u8>, (NumberFor<Block>, Block::Hash))> {
+		let is_fresh = |expires_at: &Option<Instant>| match expires_at {
+			Some(expires_at) => now <= *expires_at,
+			None => true,
+		};
+
+		match self.entries.get(&number) {
+			Some(cached) if cached.hash == hash && is_fresh(&cached.expires_at) =>
+				Some((cached.proof.clone(), cached.justified)),
+			Some(_) => {
+				self.entries.remove(&number);
+				None
+			},
+			None => None,
+		}
+	}
+
+	fn insert(&mut self, number: NumberFor<Block>, cached: CachedProof<Block>) {
+		if self.capacity == 0 {
+			return;
+		}
+
+		while self.entries.len() >= self.capacity {
+			let lowest = *self.entries.keys().next().expect("capacity is non-zero; qed");
+			self.entries.remove(&lowest);
+		}
+
+		self.entries.insert(number, cached);
+	}
+
+	fn remove(&mut self, number: NumberFor<Block>) {
+		self.entries.remove(&number);
+	}
+
+	/// Evict the proofs of the given blocks and the proofs justified by them.
+	fn evict(&mut self, retracted: &[Block::Hash]) {
+		self.entries.retain(|_, cached| {
+			!retracted.contains(&cached.hash) && !retracted.contains(&cached.justified.1)
+		});
+	}
+
+	/// Evict the proofs using the best justification of the latest authority set.
+	fn evict_best_justification_proofs(&mut self) {
+		self.entries.retain(|_, cached| !cached.from_best_justification);
+	}
+}
+
+/// Prometheus metrics of the finality proof provider.
+struct Metrics {
+	justification_age: Gauge<U64>,
+}
+
+impl Metrics {
+	fn register(registry: &Registry) -> Result<Self, PrometheusError> {
+		Ok(Self {
+			justification_age: register(
+				Gauge::new(
+					"finality_grandpa_proof_justification_age",
+					"Number of blocks between the finalized head and the block justified by the \
+					best justification, as of the latest served finality proof.",
+				)?,
+				registry,
+			)?,
+		})
+	}
 }
 
 impl<B, Block: BlockT> FinalityProofProvider<B, Block>
@@ -78,6 +355,138 @@ where
 		FinalityProofProvider {
 			backend,
 			shared_authority_set,
+			authority_set_changes_snapshot: None,
+			config: Default::default(),
+			metrics: None,
+			signing_key: None,
+			proof_cache: None,
+			header_codec: None,
+			clock: Box::new(SystemClock),
+			justification_stream: None,
+		}
+	}
+
+	/// Create new finality proof provider using a snapshot of the authority set changes, e.g.
+	/// one that was persisted separately and loaded from disk, instead of the shared authority
+	/// set of a running GRANDPA voter.
+	///
+	/// The snapshot is not updated as new authority set changes are finalized, therefore only
+	/// blocks covered by it can be proven.
+	pub fn from_changes_snapshot(
+		backend: Arc<B>,
+		authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
+	) -> Self {
+		FinalityProofProvider {
+			backend,
+			shared_authority_set: None,
+			authority_set_changes_snapshot: Some(authority_set_changes),
+			config: Default::default(),
+			metrics: None,
+			signing_key: None,
+			proof_cache: None,
+			header_codec: None,
+			clock: Box::new(SystemClock),
+			justification_stream: None,
+		}
+	}
+
+	/// Use the given configuration when generating finality proofs.
+	pub fn with_config(mut self, config: FinalityProofConfig<Block>) -> Self {
+		self.config = config;
+		self
+	}
+
+	/// Register the finality proof provider metrics in the given prometheus registry.
+	pub fn with_prometheus_registry(
+		mut self,
+		registry: &Registry,
+	) -> Result<Self, PrometheusError> {
+		self.metrics = Some(Metrics::register(registry)?);
+		Ok(self)
+	}
+
+	/// Sign the finality proofs served by [`Self::prove_finality_envelope`] with the given node
+	/// key, wrapping them in a [`SignedFinalityProof`].
+	///
+	/// This allows clients on trust-on-first-use transports to pin which node produced a proof.
+	/// The signature is advisory only, the proof must still be checked as usual.
+	pub fn with_signing_key(mut self, key: ed25519::Pair) -> Self {
+		self.signing_key = Some(key);
+		self
+	}
+
+	/// Cache up to `capacity` served finality proofs.
+	///
+	/// A cached proof is only served while the block it was generated for is still the one at
+	/// the requested height, see also [`Self::note_reorg`].
+	pub fn with_proof_cache(mut self, capacity: usize) -> Self {
+		self.proof_cache = Some(Mutex::new(ProofCache::new(capacity)));
+		self
+	}
+
+	/// Serialize the headers of the finality proofs served by [`Self::prove_finality_envelope`]
+	/// with the given codec, wrapping them in a [`CodecFinalityProof`]. The justification keeps
+	/// its canonical encoding.
+	pub fn with_header_codec(mut self, codec: impl HeaderCodec<Block::Header> + 'static) -> Self {
+		self.header_codec = Some(Box::new(codec));
+		self
+	}
+
+	/// Use the given clock instead of the system clock, e.g. to control the age of cached proofs
+	/// in tests.
+	pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+		self.clock = Box::new(clock);
+		self
+	}
+
+	/// Subscribe to the given stream of justifications to wait for a justification covering the
+	/// requested block in [`Self::prove_finality_async`], see [`UncoveredBlockPolicy::Wait`].
+	pub fn with_justification_stream(mut self, stream: GrandpaJustificationStream<Block>) -> Self {
+		self.justification_stream = Some(stream);
+		self
+	}
+
+	/// Evict the cached proofs of the blocks retracted by a reorg, e.g. as reported by the
+	/// tree route of a block import notification, and the proofs justified by them.
+	pub fn note_reorg(&self, retracted: &[Block::Hash]) {
+		if let Some(cache) = &self.proof_cache {
+			cache.lock().evict(retracted);
+		}
+	}
+
+	/// Keep the proof cache up to date with the given notification streams of the client, until
+	/// both of them end. This is meant to be spawned as a background task.
+	///
+	/// The proofs of the blocks retracted by a reorg are evicted when it is imported, see
+	/// [`Self::note_reorg`]. The proofs using the best justification of the latest set are
+	/// evicted when a block is finalized, since a newer justification is then available.
+	pub async fn maintain_proof_cache(
+		&self,
+		imports: impl Stream<Item = BlockImportNotification<Block>> + Unpin,
+		finality: impl Stream<Item = FinalityNotification<Block>> + Unpin,
+	) {
+		use futures::{future, StreamExt};
+
+		let cache = match &self.proof_cache {
+			Some(cache) => cache,
+			None => return,
+		};
+
+		// The hashes of the retracted blocks on import, nothing on finality.
+		let retracted = imports.filter_map(|notification| {
+			future::ready(notification.tree_route.map(|tree_route| {
+				Some(tree_route.retracted().iter().map(|block| block.hash).collect::<Vec<_>>())
+			}))
+		});
+		let finalized = finality.map(|_| None);
+
+		let mut events = futures::stream::select(retracted, finalized);
+		while let Some(event) = events.next().await {
+			let mut cache = cache.lock();
+			match event {
+				Some(retracted) => cache.evict(&retracted),
+				None => cache.evict_best_justification_proofs(),
+			}
 		}
 	}
 
@@ -106,389 +515,8797 @@ where
 		&self,
 		block: NumberFor<Block>
 	) -> Result<Option<Vec<u8>>, FinalityProofError> {
-		let authority_set_changes = if let Some(changes) = self
-			.shared_authority_set
-			.as_ref()
-			.map(SharedAuthoritySet::authority_set_changes)
-		{
-			changes
-		} else {
-			return Ok(None);
-		};
-
-		prove_finality::<_, _, GrandpaJustification<Block>>(
-			&*self.backend.blockchain(),
-			authority_set_changes,
-			block,
-		)
+		self.prove_finality_with_parent_span(block, None)
 	}
-}
-
-/// Finality for block B is proved by providing:
-/// 1) the justification for the descendant block F;
-/// 2) headers sub-chain (B; F] if B != F;
-#[derive(Debug, PartialEq, Encode, Decode, Clone)]
-pub struct FinalityProof<Header: HeaderT> {
-	/// The hash of block F for which justification is provided.
-	pub block: Header::Hash,
-	/// Justification of the block F.
-	pub justification: Vec<u8>,
-	/// The set of headers in the range (B; F] that we believe are unknown to the caller. Ordered.
-	pub unknown_headers: Vec<Header>,
-}
-
-/// Errors occurring when trying to prove finality
-#[derive(Debug, derive_more::Display, derive_more::From)]
-pub enum FinalityProofError {
-	/// The requested block has not yet been finalized.
-	#[display(fmt = "Block not yet finalized")]
-	BlockNotYetFinalized,
-	/// The requested block is not covered by authority set changes. Likely this means the block is
-	/// in the latest authority set, and the subscription API is more appropriate.
-	#[display(fmt = "Block not covered by authority set changes")]
-	BlockNotInAuthoritySetChanges,
-	/// Errors originating from the client.
-	Client(sp_blockchain::Error),
-}
 
-fn prove_finality<Block, B, J>(
-	blockchain: &B,
-	authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
-	block: NumberFor<Block>,
-) -> Result<Option<Vec<u8>>, FinalityProofError>
-where
-	Block: BlockT,
-	B: BlockchainBackend<Block>,
-	J: ProvableJustification<Block::Header>,
-{
-	// Early-return if we sure that there are no blocks finalized AFTER begin block
-	let info = blockchain.info();
-	if info.finalized_number <= block {
-		let err = format!(
-			"Requested finality proof for descendant of #{} while we only have finalized #{}.",
-			block,
-			info.finalized_number,
-		);
-		trace!(target: "afg", "{}", &err);
-		return Err(FinalityProofError::BlockNotYetFinalized);
+	/// Prove finality for the given block number like [`Self::prove_finality`], returning the
+	/// encoded proof together with its blake2-256 hash, which callers can compare against the
+	/// hash of the bytes they received to check the integrity of the transfer.
+	pub fn prove_finality_with_content_hash(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<(Vec<u8>, [u8; 32])>, FinalityProofError> {
+		Ok(self.prove_finality(block)?.map(|proof| {
+			let hash = blake2_256(&proof);
+			(proof, hash)
+		}))
 	}
 
-	// Get set_id the block belongs to, and the last block of the set which should contain a
-	// Justification we can use to prove the requested block.
-	let (_, last_block_for_set) = if let Some(id) = authority_set_changes.get_set_id(block) {
-		id
-	} else {
-		trace!(
-			target: "afg",
-			"AuthoritySetChanges does not cover the requested block #{}. \
-			Maybe the subscription API is more appropriate.",
-			block,
-		);
-		return Err(FinalityProofError::BlockNotInAuthoritySetChanges);
-	};
+	/// Prove finality for the given block number like [`Self::prove_finality`].
+	///
+	/// If the block is in the latest authority set but not covered by the best justification yet,
+	/// and the provider is configured with [`UncoveredBlockPolicy::Wait`] and a justification
+	/// stream, this waits for a new justification covering it instead of failing with
+	/// [`FinalityProofError::NotYetProvable`].
+	///
+	/// The proof generation span is recorded as a child of the given `parent` span. This allows
+	/// request handlers that extracted a (distributed) span context from an incoming request to
+	/// have the proof generation show up under that request's trace. If no parent is given the
+	/// span is attached to the current span context, as in [`Self::prove_finality`].
+	pub async fn prove_finality_async(
+		&self,
+		block: NumberFor<Block>,
+		parent: Option<&tracing::Span>,
+	) -> Result<Option<Vec<u8>>, FinalityProofError> {
+		use futures::StreamExt;
 
-	// Get the Justification stored at the last block of the set
-	let last_block_for_set_id = BlockId::Number(last_block_for_set);
-	let justification =
-		if let Some(grandpa_justification) = blockchain.justifications(last_block_for_set_id)?
-			.and_then(|justifications| justifications.into_justification(GRANDPA_ENGINE_ID))
-		{
-			grandpa_justification
-		} else {
-			trace!(
-				target: "afg",
-				"No justification found when making finality proof for {}. Returning empty proof.",
-				block,
-			);
-			return Ok(None);
+		let policy = self.config.uncovered_block_policy;
+		let mut justifications = match (&self.justification_stream, policy) {
+			(Some(stream), UncoveredBlockPolicy::Wait) => stream.subscribe(),
+			_ => return self.prove_finality_with_parent_span(block, parent),
 		};
 
-	// Collect all headers from the requested block until the last block of the set
-	let unknown_headers = {
-		let mut headers = Vec::new();
-		let mut current = block + One::one();
 		loop {
-			if current >= last_block_for_set || headers.len() >= MAX_UNKNOWN_HEADERS {
-				break;
+			match self.prove_finality_with_parent_span(block, parent) {
+				Err(FinalityProofError::NotYetProvable(best_provable)) => {
+					trace!(
+						target: "afg",
+						"Waiting for a justification covering #{}, the best provable block is #{}.",
+						block,
+						best_provable,
+					);
+					if justifications.next().await.is_none() {
+						return Err(FinalityProofError::NotYetProvable(best_provable));
+					}
+				},
+				result => return result,
 			}
-			headers.push(blockchain.expect_header(BlockId::Number(current))?);
-			current += One::one();
 		}
-		headers
-	};
+	}
 
-	Ok(Some(
-		FinalityProof {
-			block: blockchain.expect_block_hash_from_id(&last_block_for_set_id)?,
-			justification,
-			unknown_headers,
-		}
-		.encode(),
-	))
-}
+	/// Prove finality for the given block number, recording the proof generation span as a child
+	/// of the given `parent` span, or of the current span context if none is given.
+	fn prove_finality_with_parent_span(
+		&self,
+		block: NumberFor<Block>,
+		parent: Option<&tracing::Span>,
+	) -> Result<Option<Vec<u8>>, FinalityProofError> {
+		let span = match parent {
+			Some(parent) => tracing::debug_span!(
+				target: "afg",
+				parent: parent,
+				"prove_finality",
+				block = %block
+			),
+			None => tracing::debug_span!(target: "afg", "prove_finality", block = %block),
+		};
+		let _enter = span.enter();
 
-/// Check GRANDPA proof-of-finality for the given block.
-///
-/// Returns the vector of headers that MUST be validated + imported
-/// AND if at least one of those headers is invalid, all other MUST be considered invalid.
-///
-/// This is currently not used, and exists primarily as an example of how to check finality proofs.
-#[cfg(test)]
-fn check_finality_proof<Header: HeaderT, J>(
-	current_set_id: u64,
-	current_authorities: sp_finality_grandpa::AuthorityList,
-	remote_proof: Vec<u8>,
-) -> ClientResult<FinalityProof<Header>>
-where
-	J: ProvableJustification<Header>,
-{
-	let proof = FinalityProof::<Header>::decode(&mut &remote_proof[..])
-		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+		let block_hash = match &self.proof_cache {
+			Some(cache) => match self.backend.blockchain().hash(block)? {
+				Some(hash) => {
+					let cached = cache.lock().get(block, hash, self.clock.now());
+					if let Some((proof, (justified_number, justified_hash))) = cached {
+						// The justified block may have been retracted without the block itself.
+						let blockchain = self.backend.blockchain();
+						if blockchain.hash(justified_number)? == Some(justified_hash) {
+							self.log_served_proof(block, &proof);
+							return Ok(Some(proof));
+						}
+						cache.lock().remove(block);
+					}
+					Some(hash)
+				},
+				None => None,
+			},
+			None => None,
+		};
 
-	let justification: J = Decode::decode(&mut &proof.justification[..])
-		.map_err(|_| ClientError::JustificationDecode)?;
-	justification.verify(current_set_id, &current_authorities)?;
+		let proof = match self.prove_finality_detailed(block)? {
+			Some(proof) => proof,
+			None => return Ok(None),
+		};
 
-	Ok(proof)
-}
+		// Only proofs from the best justification of the latest set can go stale.
+		let expires_at = match self.config.max_cached_proof_age {
+			Some(max_age) if proof.from_best_justification => Some(self.clock.now() + max_age),
+			_ => None,
+		};
 
-/// Justification used to prove block finality.
-pub trait ProvableJustification<Header: HeaderT>: Encode + Decode {
-	/// Verify justification with respect to authorities set and authorities set id.
-	fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()>;
+		let justified = (proof.justified_number, proof.proof.block);
+		let from_best_justification = proof.from_best_justification;
+		let proof = proof.proof.encode();
 
-	/// Decode and verify justification.
-	fn decode_and_verify(
-		justification: &EncodedJustification,
-		set_id: u64,
-		authorities: &[(AuthorityId, u64)],
-	) -> ClientResult<Self> {
-		let justification =
-			Self::decode(&mut &**justification).map_err(|_| ClientError::JustificationDecode)?;
-		justification.verify(set_id, authorities)?;
-		Ok(justification)
+		if let (Some(cache), Some(hash)) = (&self.proof_cache, block_hash) {
+			cache.lock().insert(block, CachedProof {
+				hash,
+				justified,
+				from_best_justification,
+				expires_at,
+				proof: proof.clone(),
+			});
+		}
+
+		self.log_served_proof(block, &proof);
+		Ok(Some(proof))
 	}
-}
 
-impl<Block: BlockT> ProvableJustification<Block::Header> for GrandpaJustification<Block>
-where
-	NumberFor<Block>: BlockNumberOps,
-{
-	fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
-		let authorities = VoterSet::new(authorities.iter().cloned()).ok_or(
-			ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet),
-		)?;
+	/// Prove finality for the given block number like [`Self::prove_finality`], wrapped in a
+	/// [`FinalityProofEnvelope`] telling the format of the proof. The headers are serialized with
+	/// the header codec of the provider and the proof is signed with its signing key, if any, see
+	/// [`Self::with_header_codec`] and [`Self::with_signing_key`].
+	pub fn prove_finality_envelope(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<FinalityProofEnvelope<Block::Hash>>, FinalityProofError> {
+		let proof = match self.prove_finality(block)? {
+			Some(proof) => proof,
+			None => return Ok(None),
+		};
 
-		GrandpaJustification::verify_with_voter_set(self, set_id, &authorities)
+		let envelope = match &self.header_codec {
+			Some(codec) => {
+				let proof = FinalityProof::<Block::Header>::decode(&mut &proof[..])
+					.expect("proof was encoded by `prove_finality`; qed");
+				FinalityProofEnvelope::WithHeaderCodec(CodecFinalityProof::new(proof, &**codec))
+			},
+			None => FinalityProofEnvelope::Plain(proof),
+		};
+
+		Ok(Some(match &self.signing_key {
+			Some(key) => {
+				FinalityProofEnvelope::Signed(SignedFinalityProof::new(envelope.encode(), key))
+			},
+			None => envelope,
+		}))
 	}
-}
 
-#[cfg(test)]
-pub(crate) mod tests {
-	use super::*;
-	use crate::authorities::AuthoritySetChanges;
-	use sp_core::crypto::Public;
-	use sp_runtime::Justifications;
-	use sp_finality_grandpa::AuthorityList;
-	use sc_client_api::NewBlockState;
-	use sc_client_api::in_mem::Blockchain as InMemoryBlockchain;
-	use substrate_test_runtime_client::runtime::{Block, Header, H256};
+	fn log_served_proof(&self, block: NumberFor<Block>, proof: &[u8]) {
+		if self.config.log_served_proof_hashes {
+			tracing::debug!(
+				target: "afg",
+				block = %block,
+				proof_hash = %HexDisplay::from(&blake2_256(proof)),
+				"Served finality proof"
+			);
+		}
+	}
 
-	pub(crate) type FinalityProof = super::FinalityProof<Header>;
+	/// Populate the proof cache with the proofs of the last blocks of the authority sets, e.g. on
+	/// startup of an archive node serving historical proofs, so that they are served instantly.
+	///
+	/// The proofs are persisted in the auxiliary storage of the backend, so that they are loaded
+	/// instead of built again on the next startup. Only the first `max_sets` sets are considered
+	/// if given, and no more sets than the cache can hold. Boundaries whose proof can't be built
+	/// or persisted are skipped and reported as failed, nothing is built if the provider has no
+	/// proof cache.
+	pub fn prebuild_boundary_cache(&self, max_sets: Option<usize>) -> BoundaryCacheReport {
+		let (cache, authority_set_changes) = match (&self.proof_cache, self.authority_set_changes())
+		{
+			(Some(cache), Some(changes)) => (cache, changes),
+			_ => return BoundaryCacheReport::default(),
+		};
 
-	#[derive(Debug, PartialEq, Encode, Decode)]
-	pub struct TestJustification(pub (u64, AuthorityList), pub Vec<u8>);
+		let max_sets = max_sets.unwrap_or(usize::MAX).min(cache.lock().capacity);
+		let boundaries = authority_set_changes
+			.iter()
+			.take(max_sets)
+			.map(|(_, last_block)| *last_block)
+			.collect::<Vec<_>>();
 
-	impl ProvableJustification<Header> for TestJustification {
+		let mut report = BoundaryCacheReport::default();
+		for last_block in &boundaries {
+			if let Err(err) = self.prebuild_boundary_proof(cache, *last_block) {
+				warn!(
+					target: "afg",
+					"Failed to prebuild the finality proof of set boundary #{}: {}",
+					last_block,
+					err,
+				);
+				report.failed += 1;
+			}
+		}
+
+		let cache = cache.lock();
+		report.cached =
+			boundaries.iter().filter(|last_block| cache.entries.contains_key(last_block)).count();
+		report
+	}
+
+	/// Cache the proof of the given set boundary, loading it from the auxiliary storage if it was
+	/// persisted for the block still at that height, or building and persisting it otherwise.
+	fn prebuild_boundary_proof(
+		&self,
+		cache: &Mutex<ProofCache<Block>>,
+		last_block: NumberFor<Block>,
+	) -> Result<(), FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let persisted =
+			crate::aux_schema::load_boundary_proof::<Block, _>(&*self.backend, last_block)?;
+		if let Some((hash, justified, proof)) = persisted {
+			if blockchain.hash(last_block)? == Some(hash) &&
+				blockchain.hash(justified.0)? == Some(justified.1)
+			{
+				cache.lock().insert(last_block, CachedProof {
+					hash,
+					justified,
+					from_best_justification: false,
+					expires_at: None,
+					proof,
+				});
+				return Ok(());
+			}
+		}
+
+		self.prove_finality(last_block)?;
+		let built = cache
+			.lock()
+			.entries
+			.get(&last_block)
+			.map(|cached| (cached.hash, cached.justified, cached.proof.clone()));
+		if let Some(built) = built {
+			crate::aux_schema::write_boundary_proof::<Block, _>(
+				&*self.backend,
+				last_block,
+				&built,
+			)?;
+		}
+
+		Ok(())
+	}
+
+	/// Prove finality for the given block number, returning the decoded proof together with
+	/// information about how it should be verified.
+	pub fn prove_finality_detailed(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		self.prove_finality_detailed_on_fork(block, None)
+	}
+
+	/// Prove finality for the given block number, leaving out the headers the caller already
+	/// knows, given by number. See [`check_sparse_finality_proof`] for checking the proof, which
+	/// needs the header of the justified block, see
+	/// [`FinalityProofConfig::include_justified_header`].
+	pub fn prove_finality_sparse(
+		&self,
+		block: NumberFor<Block>,
+		known: &BTreeSet<NumberFor<Block>>,
+	) -> Result<Option<FinalityProof<Block::Header>>, FinalityProofError> {
+		Ok(self.prove_finality_detailed(block)?.map(|proof| {
+			let mut proof = proof.proof;
+			proof.unknown_headers.retain(|header| !known.contains(header.number()));
+			proof
+		}))
+	}
+
+	/// Returns the header of the justified block F whose justification would be used to prove
+	/// finality of the given block, without collecting the headers of the proof.
+	pub fn justified_header(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<Block::Header>, FinalityProofError> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok(None);
+		};
+
+		match select_justification(&*self.backend, authority_set_changes, block, &self.config)? {
+			Some((_, _, just_block, _)) =>
+				Ok(Some(self.backend.blockchain().expect_header(BlockId::Number(just_block))?)),
+			None => Ok(None),
+		}
+	}
+
+	/// Returns the id of the set covering the given block and the number of the justified block
+	/// whose justification would be used to prove its finality, if the block can be proven.
+	pub fn justified_block_for(
+		&self,
+		block: NumberFor<Block>,
+	) -> Option<(SetId, NumberFor<Block>)> {
+		let authority_set_changes = self.authority_set_changes()?;
+
+		select_justification(&*self.backend, authority_set_changes, block, &self.config)
+			.ok()
+			.flatten()
+			.map(|(set_id, _, just_block, _)| (set_id, just_block))
+	}
+
+	/// Returns the commit of the best justification, i.e. the justification of the latest block
+	/// finalized by GRANDPA, if one is stored.
+	pub fn best_commit(&self) -> Option<crate::Commit<Block>> {
+		crate::aux_schema::best_justification::<_, Block>(&*self.backend)
+			.ok()
+			.flatten()
+			.map(|justification| justification.commit)
+	}
+
+	/// Returns the number of blocks between the last block the caller knows to be finalized and
+	/// the block whose justification would be used to prove the finality of the given block,
+	/// i.e. the number of headers of an incremental proof, if the block can be proven.
+	pub fn gap_to_proof(
+		&self,
+		block: NumberFor<Block>,
+		known_finalized: NumberFor<Block>,
+	) -> Option<NumberFor<Block>> {
+		self.justified_block_for(block)
+			.map(|(_, just_block)| just_block.saturating_sub(known_finalized))
+	}
+
+	/// Returns whether the given block is finalized, together with the block whose justification
+	/// covers it, for clients that trust this node and don't need a proof.
+	///
+	/// Unlike a proof this only looks up the finalized number and the authority set changes.
+	pub fn finality_status(&self, block: NumberFor<Block>) -> FinalityStatus<NumberFor<Block>> {
+		let finalized = block <= self.backend.blockchain().info().finalized_number;
+		let covering_block = match self.authority_set_changes().map(|c| c.get_set_id(block)) {
+			Some(AuthoritySetChangeId::Set(_, last_block_for_set)) => Some(last_block_for_set),
+			_ => None,
+		};
+
+		FinalityStatus { finalized, covering_block }
+	}
+
+	/// Prove finality for the given block number, returning the proof together with the
+	/// authority set it must be verified against.
+	///
+	/// This allows clients that don't track the authority sets to verify the proof, as long as
+	/// they trust this node to provide the right authorities. Fails with
+	/// [`FinalityProofError::AuthoritiesNotFound`] if the authorities of the set are not known.
+	pub fn prove_finality_with_trust_root(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<(FinalityProof<Block::Header>, TrustRoot)>, FinalityProofError> {
+		let proof = match self.prove_finality_detailed(block)? {
+			Some(proof) => proof,
+			None => return Ok(None),
+		};
+
+		let authorities = self
+			.authorities_of_set(proof.requires_set_id)?
+			.ok_or(FinalityProofError::AuthoritiesNotFound)?;
+
+		Ok(Some((proof.proof, TrustRoot { set_id: proof.requires_set_id, authorities })))
+	}
+
+	/// Prove the finality of the last block of the given authority set, which signals the change to
+	/// the next set, together with a proof of the block `followup` blocks into the next set if it
+	/// is finalized. The follow-up proof is signed by the next set: if the next set ended before
+	/// the follow-up block, its last block is proven instead.
+	///
+	/// Returns `None` if the last block of the set has no justification.
+	pub fn prove_set_change_with_followup(
+		&self,
+		set_id: SetId,
+		followup: u32,
+	) -> Result<
+		Option<(
+			DetailedFinalityProof<Block::Header>,
+			Option<DetailedFinalityProof<Block::Header>>,
+		)>,
+		FinalityProofError,
+	> {
+		let last_block_of = |set_id| {
+			self.authority_set_changes().and_then(|changes| {
+				changes.iter().find(|(id, _)| *id == set_id).map(|(_, last_block)| *last_block)
+			})
+		};
+		let last_block =
+			last_block_of(set_id).ok_or(FinalityProofError::BlockNotInAuthoritySetChanges)?;
+
+		let justification = self
+			.backend
+			.blockchain()
+			.justifications(BlockId::Number(last_block))?
+			.and_then(|justifications| {
+				justifications.into_justification(self.config.finality_source.engine_id())
+			});
+		let set_change_proof = match justification {
+			Some(justification) => self.set_boundary_proof(set_id, last_block, justification)?,
+			None => return Ok(None),
+		};
+
+		let followup_proof = if followup > 0 {
+			let mut followup_block = last_block.saturating_add(NumberFor::<Block>::from(followup));
+			if let Some(next_last_block) = last_block_of(set_id + 1) {
+				followup_block = followup_block.min(next_last_block);
+			}
+			match self.prove_finality_detailed(followup_block) {
+				Ok(proof) => proof,
+				Err(FinalityProofError::BlockNotYetFinalized) => None,
+				Err(err) => return Err(err),
+			}
+		} else {
+			None
+		};
+
+		Ok(Some((set_change_proof, followup_proof)))
+	}
+
+	/// Prove finality for the block with the given hash, collecting the headers of the proof by
+	/// following the parent links of the justified block instead of looking them up by number.
+	///
+	/// This makes sure that the headers in the proof lead to the requested block, failing with
+	/// [`FinalityProofError::BlockNotAncestorOfJustified`] if it isn't on the finalized chain.
+	pub fn prove_finality_for_hash(
+		&self,
+		block_hash: Block::Hash,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let block = self.backend.blockchain().number(block_hash)?.ok_or_else(|| {
+			ClientError::UnknownBlock(format!("Unknown block {:?}", block_hash))
+		})?;
+
+		self.prove_finality_detailed_on_fork(block, Some(block_hash))
+	}
+
+	/// Prove finality for the given block number, bundled with a proof of inclusion of the
+	/// extrinsic at the given index in the justified block F.
+	///
+	/// The inclusion proof is checked against the extrinsics root of F's header, which is only
+	/// part of the finality proof with [`FinalityProofConfig::include_justified_header`], and
+	/// unless the requested block is F itself.
+	pub fn prove_finality_with_extrinsic(
+		&self,
+		block: NumberFor<Block>,
+		extrinsic_index: u32,
+	) -> Result<Option<FinalityProofWithExtrinsic<Block::Header>>, FinalityProofError> {
+		let proof = match self.prove_finality_detailed(block)? {
+			Some(proof) => proof,
+			None => return Ok(None),
+		};
+
+		let extrinsic = prove_extrinsic_inclusion(
+			self.backend.blockchain(),
+			proof.proof.block,
+			extrinsic_index,
+		)?;
+
+		Ok(Some(FinalityProofWithExtrinsic { proof, extrinsic }))
+	}
+
+	/// Prove finality for the finalized block with the given state root, for clients that track
+	/// state roots rather than block hashes. If several finalized blocks have the state root, the
+	/// highest one is proven.
+	///
+	/// There is no index of state roots, so the finalized chain is searched from its head, down
+	/// to [`FinalityProofConfig::max_state_root_search_depth`] headers. Fails with
+	/// [`FinalityProofError::StateRootNotFound`] if none of them has the state root.
+	pub fn prove_finality_by_state_root(
+		&self,
+		state_root: Block::Hash,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let mut header = blockchain.expect_header(BlockId::Hash(blockchain.info().finalized_hash))?;
+		let mut searched = 1;
+		while *header.state_root() != state_root {
+			if header.number().is_zero() || searched >= self.config.max_state_root_search_depth {
+				return Err(FinalityProofError::StateRootNotFound);
+			}
+			header = blockchain.expect_header(BlockId::Hash(*header.parent_hash()))?;
+			searched += 1;
+		}
+
+		self.prove_finality_detailed_on_fork(*header.number(), Some(header.hash()))
+	}
+
+	/// Prove finality for the given block number to a peer that reported its finalized block
+	/// number, leaving out the headers up to it which the peer already knows.
+	///
+	/// Fails with [`FinalityProofError::BlockNotAncestorOfJustified`] if the peer's finalized
+	/// block is above the justified block.
+	pub fn prove_finality_for_peer(
+		&self,
+		requested: NumberFor<Block>,
+		peer_known_finalized: NumberFor<Block>,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		self.prove_finality_above(requested, |just_block| {
+			if peer_known_finalized > just_block {
+				return Err(FinalityProofError::BlockNotAncestorOfJustified);
+			}
+			Ok(peer_known_finalized)
+		})
+	}
+
+	/// Prove finality for the given block number to a peer that reported the hash of its head,
+	/// leaving out the headers up to the highest finalized block the peer's head descends from,
+	/// which the peer already knows.
+	///
+	/// If the peer's head is unknown, or too far from a finalized block, all the headers of the
+	/// proof are included.
+	pub fn prove_finality_from_peer_head(
+		&self,
+		requested: NumberFor<Block>,
+		peer_head_hash: Block::Hash,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let common_ancestor = self.highest_finalized_ancestor(peer_head_hash)?;
+		self.prove_finality_above(requested, |just_block| {
+			Ok(common_ancestor.map_or(requested, |ancestor| ancestor.min(just_block)))
+		})
+	}
+
+	/// Resume the transfer of a proof for the given block number to a peer that received its
+	/// headers up to the one with the given hash, collecting the headers from its child onwards.
+	///
+	/// Fails with [`FinalityProofError::BlockNotAncestorOfJustified`] if the last received header
+	/// isn't the justified block or one of its ancestors.
+	pub fn resume_proof(
+		&self,
+		requested: NumberFor<Block>,
+		last_received_hash: Block::Hash,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let last_received = match blockchain.number(last_received_hash)? {
+			Some(number) if blockchain.hash(number)? == Some(last_received_hash) => number,
+			_ => return Err(FinalityProofError::BlockNotAncestorOfJustified),
+		};
+
+		self.prove_finality_above(requested, |just_block| {
+			if last_received > just_block {
+				return Err(FinalityProofError::BlockNotAncestorOfJustified);
+			}
+			Ok(last_received)
+		})
+	}
+
+	/// Prove finality for the given block number, including only the headers collected within the
+	/// given latency budget, so that the response can be sent quickly for large requests.
+	///
+	/// If not all headers fit, the proof carries a continuation for requesting the remaining ones
+	/// with [`Self::continue_proof`]. At least one header is included in each batch.
+	///
+	/// The headers are collected in chunks of doubling size, so that the headers of small
+	/// requests are collected at once while the batches of large ones stay close to the budget.
+	/// The proof is subject to the same limits and truncation as [`Self::prove_finality`], with
+	/// its size estimated from the size of F's header.
+	pub fn prove_finality_batched(
+		&self,
+		block: NumberFor<Block>,
+		latency_budget: Duration,
+	) -> Result<Option<BatchedFinalityProof<Block::Header>>, FinalityProofError> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok(None);
+		};
+
+		let selected =
+			select_justification(&*self.backend, authority_set_changes, block, &self.config)?;
+		let (_, justification, just_block, _) = match selected {
+			Some(selected) => selected,
+			None => return Ok(None),
+		};
+
+		let blockchain = self.backend.blockchain();
+		let just_header = blockchain.expect_header(BlockId::Number(just_block))?;
+		let max_headers: NumberFor<Block> = self.config.max_unknown_headers.saturated_into();
+		let last_header =
+			self.config.last_proof_header(just_block).min(block.saturating_add(max_headers));
+
+		if let Some(budget) = self.config.max_proof_size {
+			let headers: usize = last_header.saturating_sub(block).saturated_into();
+			let estimated_size = headers.saturating_mul(just_header.encoded_size());
+			if estimated_size > budget {
+				trace!(
+					target: "afg",
+					"Requested batched finality proof for #{} has an estimated {} bytes of \
+					headers, exceeding the maximum of {}.",
+					block,
+					estimated_size,
+					budget,
+				);
+				return Err(FinalityProofError::ProofTooLarge);
+			}
+		}
+
+		let just_hash = just_header.hash();
+		let first = ProofContinuation {
+			justified_hash: just_hash,
+			next: block + One::one(),
+			last: just_block,
+			last_header,
+		};
+		let batch = self.collect_header_batch(first, latency_budget)?;
+
+		Ok(Some(BatchedFinalityProof {
+			proof: FinalityProof {
+				block: just_hash,
+				justification,
+				unknown_headers: batch.headers,
+			},
+			continuation: batch.continuation,
+		}))
+	}
+
+	/// Continue a proof returned by [`Self::prove_finality_batched`], returning the next batch of
+	/// its headers collected within the given latency budget.
+	///
+	/// Fails with [`FinalityProofError::BlockNotAncestorOfJustified`] if the justified block of
+	/// the proof is no longer on the canonical chain.
+	pub fn continue_proof(
+		&self,
+		continuation: ProofContinuation<Block::Hash, NumberFor<Block>>,
+		latency_budget: Duration,
+	) -> Result<HeaderBatch<Block::Header>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		if blockchain.hash(continuation.last)? != Some(continuation.justified_hash) {
+			return Err(FinalityProofError::BlockNotAncestorOfJustified);
+		}
+
+		self.collect_header_batch(continuation, latency_budget)
+	}
+
+	/// Collect the headers starting at `continuation.next`, in chunks of doubling size, until the
+	/// latency budget is exhausted or the last header of the proof is reached.
+	fn collect_header_batch(
+		&self,
+		continuation: ProofContinuation<Block::Hash, NumberFor<Block>>,
+		latency_budget: Duration,
+	) -> Result<HeaderBatch<Block::Header>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let start = self.clock.now();
+		let mut headers = Vec::new();
+		// The continuation may come from a peer, it can't extend the proof past F.
+		let last = continuation.last_header.min(self.config.last_proof_header(continuation.last));
+		let mut next = continuation.next;
+		let mut chunk_size = 1usize;
+		while next <= last {
+			let remaining: usize = (last - next).saturated_into::<usize>().saturating_add(1);
+			let config = FinalityProofConfig {
+				max_unknown_headers: chunk_size.min(remaining),
+				..self.config.clone()
+			};
+			let chunk =
+				collect_unknown_headers(blockchain, next - One::one(), continuation.last, &config)?;
+			next += chunk.len().saturated_into::<NumberFor<Block>>();
+			headers.extend(chunk);
+			chunk_size = chunk_size.saturating_mul(2);
+
+			if self.clock.now().saturating_duration_since(start) >= latency_budget {
+				break;
+			}
+		}
+
+		let continuation = if next <= last {
+			Some(ProofContinuation { next, ..continuation })
+		} else {
+			None
+		};
+
+		Ok(HeaderBatch { headers, continuation })
+	}
+
+	/// Prove finality for the given block number, collecting headers until the given deadline,
+	/// as measured by the provider's [`Clock`].
+	///
+	/// If the deadline passes before the justified block is reached, collection stops at the
+	/// highest block with a stored justification collected so far (or the requested block itself
+	/// if it has one), proving the requested block with it. Collection only continues past the
+	/// deadline until such a block is found, which bounds the time spent on a single request.
+	pub fn prove_finality_by_deadline(
+		&self,
+		block: NumberFor<Block>,
+		deadline: Instant,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok(None);
+		};
+
+		let selected =
+			select_justification(&*self.backend, authority_set_changes, block, &self.config)?;
+		let (set_id, justification, just_block, from_best_justification) = match selected {
+			Some(selected) => selected,
+			None => return Ok(None),
+		};
+
+		let blockchain = self.backend.blockchain();
+		let engine_id = self.config.finality_source.engine_id();
+		let stored_justification = |number| -> Result<_, FinalityProofError> {
+			Ok(blockchain
+				.justifications(BlockId::Number(number))?
+				.and_then(|justifications| justifications.into_justification(engine_id)))
+		};
+
+		let mut justification = Some(justification);
+		let mut headers = Vec::new();
+		// The highest block with a justification, its justification and the number of headers
+		// up to it.
+		let mut nearest = if block == just_block {
+			justification.take().map(|justification| (block, justification, 0))
+		} else {
+			stored_justification(block)?.map(|justification| (block, justification, 0))
+		};
+
+		let mut next = block + One::one();
+		while next <= just_block {
+			// Only read the clock once there is a block to fall back to.
+			if nearest.is_some() && self.clock.now() >= deadline {
+				break;
+			}
+
+			headers.push(blockchain.expect_header(BlockId::Number(next))?);
+			let next_justification = if next == just_block {
+				justification.take()
+			} else {
+				stored_justification(next)?
+			};
+			if let Some(next_justification) = next_justification {
+				nearest = Some((next, next_justification, headers.len()));
+			}
+			next += One::one();
+		}
+
+		let (justified_number, justification, len) =
+			nearest.expect("the justified block is always collected last; qed");
+		// The header of the justified block itself is only included if configured to.
+		let len = if self.config.include_justified_header { len } else { len.saturating_sub(1) };
+		headers.truncate(len);
+
+		Ok(Some(DetailedFinalityProof {
+			proof: FinalityProof {
+				block: blockchain.expect_block_hash_from_id(&BlockId::Number(justified_number))?,
+				justification,
+				unknown_headers: headers,
+			},
+			requires_set_id: set_id,
+			justified_number,
+			from_best_justification: from_best_justification && justified_number == just_block,
+		}))
+	}
+
+	/// Returns the number of the highest finalized block the given block descends from (or is),
+	/// if the block is known and that block is reached within
+	/// [`FinalityProofConfig::max_unknown_headers`] ancestors, which bounds the headers read for
+	/// a block reported by a peer.
+	fn highest_finalized_ancestor(
+		&self,
+		hash: Block::Hash,
+	) -> Result<Option<NumberFor<Block>>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let finalized_number = blockchain.info().finalized_number;
+		let mut current = match blockchain.header(BlockId::Hash(hash))? {
+			Some(header) => header,
+			None => return Ok(None),
+		};
+
+		let mut ancestors = 0;
+		loop {
+			let number = *current.number();
+			if number <= finalized_number && blockchain.hash(number)? == Some(current.hash()) {
+				return Ok(Some(number));
+			}
+			if ancestors >= self.config.max_unknown_headers {
+				return Ok(None);
+			}
+			current = blockchain.expect_header(BlockId::Hash(*current.parent_hash()))?;
+			ancestors += 1;
+		}
+	}
+
+	/// Prove finality for the given block number, leaving out the headers up to the base returned
+	/// by `base` for the number of the justified block.
+	fn prove_finality_above(
+		&self,
+		requested: NumberFor<Block>,
+		base: impl FnOnce(NumberFor<Block>) -> Result<NumberFor<Block>, FinalityProofError>,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok(None);
+		};
+
+		let selected =
+			select_justification(&*self.backend, authority_set_changes, requested, &self.config)?;
+		let (set_id, justification, just_block, from_best_justification) = match selected {
+			Some(selected) => selected,
+			None => return Ok(None),
+		};
+
+		let base = requested.max(base(just_block)?);
+		let blockchain = self.backend.blockchain();
+		let just_hash = blockchain.expect_block_hash_from_id(&BlockId::Number(just_block))?;
+		let unknown_headers = collect_unknown_headers(blockchain, base, just_block, &self.config)?;
+
+		Ok(Some(DetailedFinalityProof {
+			proof: FinalityProof {
+				block: just_hash,
+				justification,
+				unknown_headers,
+			},
+			requires_set_id: set_id,
+			justified_number: just_block,
+			from_best_justification,
+		}))
+	}
+
+	fn prove_finality_detailed_on_fork(
+		&self,
+		block: NumberFor<Block>,
+		block_hash: Option<Block::Hash>,
+	) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok(None);
+		};
+
+		let mut proof = prove_finality_detailed_on_fork::<_, _, GrandpaJustification<Block>>(
+			&*self.backend,
+			authority_set_changes,
+			block,
+			block_hash,
+			&self.config,
+		)?;
+
+		if let Some(proof) = proof.as_mut().filter(|_| self.config.minimize_quorum) {
+			self.minimize_quorum(proof)?;
+		}
+
+		if let (Some(metrics), Some(_)) = (&self.metrics, &proof) {
+			self.report_justification_age(metrics)?;
+		}
+
+		Ok(proof)
+	}
+
+	/// Set the justification age gauge from the best justification stored in the database.
+	fn report_justification_age(&self, metrics: &Metrics) -> ClientResult<()> {
+		if let Some(justification) =
+			crate::aux_schema::best_justification::<_, Block>(&*self.backend)?
+		{
+			let finalized_number = self.backend.blockchain().info().finalized_number;
+			metrics.justification_age.set(
+				finalized_number.saturating_sub(justification.target().0).saturated_into(),
+			);
+		}
+
+		Ok(())
+	}
+
+	/// Prove finality for the given block number in the [`FinalityProofV2`] format, including the
+	/// hashes of the siblings of the justified block if configured to.
+	pub fn prove_finality_v2(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<Option<FinalityProofV2<Block::Header>>, FinalityProofError> {
+		self.prove_finality_v2_inner(block, None)
+	}
+
+	/// Prove finality for the given block number in the [`FinalityProofV2`] format for a client
+	/// that trusts the authority set with the given id, stating in
+	/// [`FinalityProofV2::prerequisite_sets`] how many authority set changes it must verify
+	/// before it can check the proof.
+	pub fn prove_finality_v2_for_trusted_set(
+		&self,
+		block: NumberFor<Block>,
+		trusted_set_id: SetId,
+	) -> Result<Option<FinalityProofV2<Block::Header>>, FinalityProofError> {
+		self.prove_finality_v2_inner(block, Some(trusted_set_id))
+	}
+
+	fn prove_finality_v2_inner(
+		&self,
+		block: NumberFor<Block>,
+		trusted_set_id: Option<SetId>,
+	) -> Result<Option<FinalityProofV2<Block::Header>>, FinalityProofError> {
+		let (proof, requires_set_id) = match self.prove_finality_detailed(block)? {
+			Some(proof) => (proof.proof, proof.requires_set_id),
+			None => return Ok(None),
+		};
+
+		let prerequisite_sets = trusted_set_id
+			.map(|trusted_set_id| requires_set_id.saturating_sub(trusted_set_id).saturated_into());
+
+		let sibling_hints = if self.config.include_sibling_hints {
+			let blockchain = self.backend.blockchain();
+			let parent_hash = *blockchain.expect_header(BlockId::Hash(proof.block))?.parent_hash();
+			blockchain
+				.children(parent_hash)?
+				.into_iter()
+				.filter(|hash| *hash != proof.block)
+				.collect()
+		} else {
+			Vec::new()
+		};
+
+		let genesis_hash = if self.config.embed_genesis_hash {
+			Some(self.backend.blockchain().info().genesis_hash)
+		} else {
+			None
+		};
+
+		let proof = FinalityProofV2 {
+			sibling_hints,
+			prerequisite_sets,
+			server_version: Some(SERVER_VERSION.into()),
+			genesis_hash,
+			..FinalityProofV2::from(proof)
+		};
+		if self.config.embed_set_change {
+			Ok(Some(proof.with_embedded_set_change()))
+		} else {
+			Ok(Some(proof))
+		}
+	}
+
+	/// Explain how a finality proof for the given block would be produced, or why it can't be.
+	///
+	/// This is meant for diagnosing failing requests, it goes through the same steps as
+	/// [`Self::prove_finality`] without updating the metrics.
+	pub fn prove_finality_explain(&self, block: NumberFor<Block>) -> ProofPlan<NumberFor<Block>> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return ProofPlan::Unprovable {
+				block,
+				reason: "no authority set changes available".into(),
+			};
+		};
+
+		let proof = prove_finality_detailed::<_, _, GrandpaJustification<Block>>(
+			&*self.backend,
+			authority_set_changes,
+			block,
+			&self.config,
+		);
+
+		match proof {
+			Ok(Some(proof)) => ProofPlan::Provable {
+				block,
+				justified: proof.justified_number,
+				requires_set_id: proof.requires_set_id,
+				headers: proof.proof.unknown_headers.len(),
+			},
+			Ok(None) => ProofPlan::NoJustification { block },
+			Err(err) => ProofPlan::Unprovable { block, reason: err.to_string() },
+		}
+	}
+
+	/// Check a request for the finality proof of the given block against the configured limits,
+	/// before collecting any of the headers of the proof.
+	///
+	/// The justification proving the block is selected exactly like when generating the proof.
+	/// The size of the proof is estimated from the size of the header of the justified block.
+	pub fn validate_request(
+		&self,
+		block: NumberFor<Block>,
+	) -> Result<RequestValidation<NumberFor<Block>>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let finalized_number = blockchain.info().finalized_number;
+		if block > finalized_number {
+			return Ok(RequestValidation::NotYetFinalized { finalized: finalized_number });
+		}
+
+		let authority_set_changes = match self.authority_set_changes() {
+			Some(changes) => changes,
+			None => return Ok(RequestValidation::NotCovered),
+		};
+
+		// The limits are checked below instead, to report which of them is exceeded.
+		let config = FinalityProofConfig {
+			max_gap: None,
+			truncation_strategy: match self.config.truncation_strategy {
+				TruncationStrategy::Fail => TruncationStrategy::TruncateHigh,
+				strategy => strategy,
+			},
+			..self.config.clone()
+		};
+		let just_block =
+			match select_justification(&*self.backend, authority_set_changes, block, &config) {
+				Ok(Some((_, _, just_block, _))) => just_block,
+				Ok(None) | Err(FinalityProofError::JustificationNotFound) =>
+					return Ok(RequestValidation::NoJustification),
+				Err(FinalityProofError::BlockNotInAuthoritySetChanges) =>
+					return Ok(RequestValidation::NotCovered),
+				Err(FinalityProofError::NotYetProvable(provable)) =>
+					return Ok(RequestValidation::NotYetProvable {
+						provable: provable.saturated_into(),
+					}),
+				Err(err) => return Err(err),
+			};
+
+		let gap = just_block.saturating_sub(block);
+		if let Some(max_gap) = self.config.max_gap.filter(|max_gap| gap > *max_gap) {
+			return Ok(RequestValidation::ExceedsMaxGap { gap, max_gap });
+		}
+
+		let headers: usize =
+			self.config.last_proof_header(just_block).saturating_sub(block).saturated_into();
+		let max_headers = self.config.max_unknown_headers;
+		if headers > max_headers && self.config.truncation_strategy == TruncationStrategy::Fail {
+			return Ok(RequestValidation::ExceedsMaxHeaders { headers, max_headers });
+		}
+
+		if let Some(budget) = self.config.max_proof_size {
+			let header_size = blockchain
+				.header(BlockId::Number(just_block))?
+				.map(|header| header.encoded_size())
+				.unwrap_or_default();
+			let estimated_size = headers.min(max_headers).saturating_mul(header_size);
+			if estimated_size > budget {
+				return Ok(RequestValidation::ExceedsByteBudget { estimated_size, budget });
+			}
+		}
+
+		Ok(RequestValidation::Valid { justified: just_block })
+	}
+
+	/// Explain the finality proofs of all the given blocks, see [`Self::prove_finality_explain`].
+	pub fn explain_many(&self, blocks: &[NumberFor<Block>]) -> Vec<ProofPlan<NumberFor<Block>>> {
+		blocks.iter().map(|block| self.prove_finality_explain(*block)).collect()
+	}
+
+	/// Export the finality proofs of all authority set change boundaries up to (and including)
+	/// the given block number to the given writer, e.g. to distribute a trusted-setup bundle
+	/// offline. Returns the number of exported proofs.
+	///
+	/// Each proof proves the last block of a set and includes its header, which signals the
+	/// authority set change. It is written as a little-endian `u32` length followed by the
+	/// encoded [`DetailedFinalityProof`]. See [`import_archive`] for reading the archive back.
+	/// Boundaries without a justification are skipped.
+	pub fn export_archive(
+		&self,
+		up_to: NumberFor<Block>,
+		mut writer: impl std::io::Write,
+	) -> Result<usize, FinalityProofError> {
+		let (justifications, warnings) = self.all_set_boundary_justifications(up_to)?;
+		for warning in warnings {
+			warn!(
+				target: "afg",
+				"Skipping set boundary when exporting finality proofs: {}",
+				warning,
+			);
+		}
+
+		for (set_id, last_block, justification) in &justifications {
+			let proof = self.set_boundary_proof(*set_id, *last_block, justification.clone())?;
+			write_archive_entry(&mut writer, &proof)?;
+		}
+
+		Ok(justifications.len())
+	}
+
+	/// Prove finality for each of the given block numbers, including each justification only once
+	/// even if it proves several of the blocks, e.g. blocks of the same authority set.
+	pub fn prove_finality_multi(
+		&self,
+		blocks: &[NumberFor<Block>],
+	) -> Result<MultiFinalityProof<Block::Header>, FinalityProofError> {
+		let mut justifications = Vec::new();
+		let mut justification_indices = HashMap::new();
+		let mut proofs = Vec::with_capacity(blocks.len());
+
+		for number in blocks {
+			let FinalityProof { block, justification, unknown_headers } =
+				match self.prove_finality_detailed(*number)? {
+					Some(proof) => proof.proof,
+					None => {
+						proofs.push(None);
+						continue;
+					},
+				};
+
+			let justification_index = *justification_indices.entry(block).or_insert_with(|| {
+				justifications.push(justification);
+				justifications.len() as u32 - 1
+			});
+
+			proofs.push(Some(MultiFinalityProofEntry {
+				block,
+				justification_index,
+				unknown_headers,
+			}));
+		}
+
+		Ok(MultiFinalityProof { justifications, proofs })
+	}
+
+	/// Prove the finality of the last block of each authority set that ended after the given
+	/// block number, returning at most `max_fragments` proofs.
+	///
+	/// Each proof includes the header of the proven block, which signals the authority set
+	/// change. If more proofs are available a continuation block number is returned, which
+	/// should be passed as `begin` to get the rest. Boundaries without a justification are
+	/// skipped. No proofs and no continuation are returned if `max_fragments` is zero.
+	pub fn prove_finality_range(
+		&self,
+		begin: NumberFor<Block>,
+		max_fragments: usize,
+	) -> Result<FinalityProofRange<Block::Header>, FinalityProofError> {
+		let authority_set_changes = match self.authority_set_changes() {
+			Some(changes) if max_fragments > 0 => changes,
+			_ => return Ok(FinalityProofRange { fragments: Vec::new(), continuation: None }),
+		};
+
+		let blockchain = self.backend.blockchain();
+		let mut fragments: Vec<DetailedFinalityProof<Block::Header>> = Vec::new();
+		let mut continuation = None;
+
+		for (set_id, last_block) in authority_set_changes.iter_from(begin) {
+			if let Some(last) = fragments.last().filter(|_| fragments.len() >= max_fragments) {
+				continuation = Some(last.justified_number);
+				break;
+			}
+
+			let justification = blockchain
+				.justifications(BlockId::Number(*last_block))?
+				.and_then(|justifications| {
+					justifications.into_justification(self.config.finality_source.engine_id())
+				});
+
+			match justification {
+				Some(justification) =>
+					fragments.push(self.set_boundary_proof(*set_id, *last_block, justification)?),
+				None => trace!(
+					target: "afg",
+					"No justification found for the last block #{} of authority set {}.",
+					last_block,
+					set_id,
+				),
+			}
+		}
+
+		Ok(FinalityProofRange { fragments, continuation })
+	}
+
+	/// Returns the set id, last block number and justification of every authority set change
+	/// boundary up to (and including) the given block number, e.g. to seed the trusted set
+	/// history of a bridge relayer. The justifications of the configured
+	/// [`FinalityProofConfig::finality_source`] are returned.
+	///
+	/// Boundaries without a stored justification (e.g. those enacted by a forced change) are
+	/// skipped and reported in the returned list of warnings instead.
+	pub fn all_set_boundary_justifications(
+		&self,
+		up_to: NumberFor<Block>,
+	) -> ClientResult<(Vec<(SetId, NumberFor<Block>, Vec<u8>)>, Vec<String>)> {
+		let authority_set_changes = if let Some(changes) = self.authority_set_changes() {
+			changes
+		} else {
+			return Ok((Vec::new(), Vec::new()));
+		};
+
+		let blockchain = self.backend.blockchain();
+		let mut justifications = Vec::new();
+		let mut warnings = Vec::new();
+
+		for (set_id, last_block) in authority_set_changes.iter().take_while(|(_, n)| *n <= up_to) {
+			let justification = blockchain
+				.justifications(BlockId::Number(*last_block))?
+				.and_then(|justifications| {
+					justifications.into_justification(self.config.finality_source.engine_id())
+				});
+
+			match justification {
+				Some(justification) => justifications.push((*set_id, *last_block, justification)),
+				None => warnings.push(format!(
+					"No justification found for the last block #{} of authority set {}.",
+					last_block,
+					set_id,
+				)),
+			}
+		}
+
+		Ok((justifications, warnings))
+	}
+
+	/// Whether the authority set changes are populated enough to serve finality proofs, e.g. so
+	/// that the network layer can defer advertising the proof service while they are still being
+	/// loaded on startup.
+	pub fn is_ready(&self) -> bool {
+		self.authority_set_changes().map_or(false, |changes| changes.iter().next().is_some())
+	}
+
+	/// Returns a bitmap marking which of the authority sets `from_set..=to_set` have a
+	/// justification stored at their last block, a compact form of
+	/// [`Self::all_set_boundary_justifications`] suited to auditing large ranges.
+	///
+	/// Sets whose last block isn't known (e.g. the latest set) are marked as not covered. The
+	/// range is clamped to the known sets, i.e. it doesn't extend past the latest set.
+	pub fn justification_coverage_bitmap(
+		&self,
+		from_set: SetId,
+		to_set: SetId,
+	) -> ClientResult<BitVec> {
+		let changes = match self.authority_set_changes() {
+			Some(changes) => changes,
+			None => return Ok(BitVec::new()),
+		};
+		let latest_set = match changes.iter().last() {
+			Some((set_id, _)) => set_id.saturating_add(1),
+			None => return Ok(BitVec::new()),
+		};
+		let to_set = to_set.min(latest_set);
+		let boundaries = changes
+			.iter()
+			.filter(|(set_id, _)| (from_set..=to_set).contains(set_id))
+			.cloned()
+			.collect::<BTreeMap<_, _>>();
+
+		let blockchain = self.backend.blockchain();
+		let mut bitmap = BitVec::new();
+		for set_id in from_set..=to_set {
+			let covered = match boundaries.get(&set_id) {
+				Some(last_block) => blockchain
+					.justifications(BlockId::Number(*last_block))?
+					.and_then(|justifications| {
+						justifications.into_justification(self.config.finality_source.engine_id())
+					})
+					.is_some(),
+				None => false,
+			};
+			bitmap.push(covered);
+		}
+
+		Ok(bitmap)
+	}
+
+	/// Returns the justifications of the configured [`FinalityProofConfig::finality_source`]
+	/// stored at the finalized blocks `from..=to`, with the numbers of their blocks, e.g. for
+	/// operators to monitor the density of justifications.
+	///
+	/// Blocks without a justification are skipped, as are those above the last finalized block.
+	/// Fails with [`FinalityProofError::RangeTooLarge`] if the remaining range has more than
+	/// [`FinalityProofConfig::max_justification_range`] blocks.
+	pub fn justifications_in_range(
+		&self,
+		from: NumberFor<Block>,
+		to: NumberFor<Block>,
+	) -> Result<Vec<(NumberFor<Block>, Vec<u8>)>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let to = to.min(blockchain.info().finalized_number);
+		if from <= to {
+			let blocks = (to - from).saturated_into::<usize>().saturating_add(1);
+			if blocks > self.config.max_justification_range {
+				return Err(FinalityProofError::RangeTooLarge);
+			}
+		}
+
+		let mut justifications = Vec::new();
+		let mut number = from;
+		while number <= to {
+			if let Some(justification) = blockchain
+				.justifications(BlockId::Number(number))?
+				.and_then(|justifications| {
+					justifications.into_justification(self.config.finality_source.engine_id())
+				})
+			{
+				justifications.push((number, justification));
+			}
+			number += One::one();
+		}
+
+		Ok(justifications)
+	}
+
+	/// Returns the authorities of the given set, either signalled by the last block of the
+	/// previous set or, for the current set, from the shared authority set.
+	///
+	/// Only a standard change without delay signalled by the last block of the previous set is
+	/// the change enacted there. The signals of delayed and forced changes precede the block
+	/// that enacts them, so the authorities they enact are unknown here unless the set is the
+	/// current one.
+	fn authorities_of_set(
+		&self,
+		set_id: SetId,
+	) -> ClientResult<Option<sp_finality_grandpa::AuthorityList>> {
+		let previous_boundary = set_id.checked_sub(1).and_then(|previous_set_id| {
+			self.authority_set_changes()?
+				.iter()
+				.find(|(id, _)| *id == previous_set_id)
+				.map(|(_, last_block)| *last_block)
+		});
+
+		if let Some(last_block) = previous_boundary {
+			let header = self.backend.blockchain().expect_header(BlockId::Number(last_block))?;
+			let change = crate::import::find_scheduled_change::<Block>(&header)
+				.filter(|change| change.delay.is_zero());
+			if let Some(change) = change {
+				return Ok(Some(change.next_authorities));
+			}
+		}
+
+		Ok(self.shared_authority_set.as_ref().and_then(|shared| {
+			let authority_set = shared.inner();
+			let (current_set_id, authorities) = authority_set.current();
+			if current_set_id == set_id {
+				Some(authorities.to_vec())
+			} else {
+				None
+			}
+		}))
+	}
+
+	/// Rebuild the authority set changes from the set changes signalled in the finalized chain,
+	/// e.g. after they were lost while the blocks and their justifications are intact.
+	///
+	/// The finalized chain is assumed to start with set 0. A standard change ends its set at the
+	/// block that enacts it, a forced change at the median last finalized block it signals.
+	///
+	/// Since no change can be signalled while another one is pending, the walk jumps from each
+	/// signal straight to the set boundary it enacts, skipping the headers in between. The
+	/// boundary of a standard change must carry a justification, otherwise
+	/// [`FinalityProofError::JustificationNotFound`] is returned.
+	pub fn rebuild_authority_set_changes(
+		&self,
+	) -> Result<AuthoritySetChanges<NumberFor<Block>>, FinalityProofError> {
+		let blockchain = self.backend.blockchain();
+		let finalized_number = blockchain.info().finalized_number;
+
+		let mut authority_set_changes = AuthoritySetChanges::empty();
+		let mut set_id = 0;
+		let mut number = NumberFor::<Block>::one();
+		while number <= finalized_number {
+			let header = blockchain.expect_header(BlockId::Number(number))?;
+			let (last_block, enacted_at, forced) = match find_set_change(&header) {
+				Some(ProofSetChange::Scheduled(change)) => {
+					let last_block = number + change.delay;
+					(last_block, last_block, false)
+				},
+				Some(ProofSetChange::Forced(median, change)) =>
+					(median, number + change.delay, true),
+				None => {
+					number = number + One::one();
+					continue
+				},
+			};
+
+			// Changes that aren't enacted yet don't end the current set.
+			if enacted_at > finalized_number {
+				break
+			}
+
+			if !forced {
+				blockchain
+					.justifications(BlockId::Number(last_block))?
+					.and_then(|justifications| {
+						justifications.into_justification(self.config.finality_source.engine_id())
+					})
+					.ok_or(FinalityProofError::JustificationNotFound)?;
+			}
+
+			authority_set_changes.append(set_id, last_block);
+			set_id += 1;
+			number = enacted_at + One::one();
+		}
+
+		Ok(authority_set_changes)
+	}
+
+	/// Trim the justification of the given proof to a minimal quorum of its authority set, see
+	/// [`FinalityProofConfig::minimize_quorum`].
+	fn minimize_quorum(
+		&self,
+		proof: &mut DetailedFinalityProof<Block::Header>,
+	) -> Result<(), FinalityProofError> {
+		if self.config.finality_source != FinalitySource::Grandpa {
+			return Ok(());
+		}
+
+		let authorities = match self.authorities_of_set(proof.requires_set_id)? {
+			Some(authorities) => authorities,
+			None => {
+				trace!(
+					target: "afg",
+					"Authorities of set {} unknown, serving the full justification.",
+					proof.requires_set_id,
+				);
+				return Ok(());
+			},
+		};
+
+		let justification =
+			GrandpaJustification::<Block>::decode(&mut &proof.proof.justification[..])
+				.map_err(|_| ClientError::JustificationDecode)?;
+		match justification.minimize_quorum(&authorities) {
+			Ok(justification) => proof.proof.justification = justification.encode(),
+			Err(err) => warn!(
+				target: "afg",
+				"Failed to minimize the quorum of the justification of #{}: {}",
+				proof.justified_number,
+				err,
+			),
+		}
+
+		Ok(())
+	}
+
+	fn set_boundary_proof(
+		&self,
+		set_id: SetId,
+		last_block: NumberFor<Block>,
+		justification: Vec<u8>,
+	) -> ClientResult<DetailedFinalityProof<Block::Header>> {
+		let header = self.backend.blockchain().expect_header(BlockId::Number(last_block))?;
+		Ok(DetailedFinalityProof {
+			proof: FinalityProof {
+				block: header.hash(),
+				justification,
+				unknown_headers: vec![header],
+			},
+			requires_set_id: set_id,
+			justified_number: last_block,
+			from_best_justification: false,
+		})
+	}
+
+	fn authority_set_changes(&self) -> Option<AuthoritySetChanges<NumberFor<Block>>> {
+		if let Some(changes) = &self.authority_set_changes_snapshot {
+			return Some(changes.clone());
+		}
+
+		self.shared_authority_set
+			.as_ref()
+			.map(SharedAuthoritySet::authority_set_changes)
+	}
+}
+
+/// Finality for block B is proved by providing:
+/// 1) the justification for the descendant block F;
+/// 2) headers sub-chain (B; F] if B != F;
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct FinalityProof<Header: HeaderT> {
+	/// The hash of block F for which justification is provided.
+	pub block: Header::Hash,
+	/// Justification of the block F.
+	pub justification: Vec<u8>,
+	/// The set of headers in the range (B; F] that we believe are unknown to the caller. Ordered.
+	pub unknown_headers: Vec<Header>,
+}
+
+impl<Header: HeaderT> FinalityProof<Header> {
+	/// The authority set change signalled in the digest of F's header, parsed according to the
+	/// digest layout selected by the given configuration. Returns `None` if the proof doesn't
+	/// include F's header.
+	///
+	/// The proof should be checked first, see [`check_finality_proof_with_config`].
+	pub fn signalled_set_change(
+		&self,
+		config: &FinalityProofCheckConfig,
+	) -> Option<ProofSetChange<Header::Number>> {
+		self.unknown_headers
+			.last()
+			.filter(|header| header.hash() == self.block)
+			.and_then(|header| find_set_change_with_layout(header, config.legacy_digest))
+	}
+}
+
+/// The behavior when proving a block of the latest authority set while no best justification is
+/// stored, e.g. right after a restart on a database that predates it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoJustificationPolicy {
+	/// Return an empty proof.
+	ReturnNone,
+	/// Fail with [`FinalityProofError::JustificationNotFound`].
+	ReturnError,
+	/// Serve the justification stored at the finalized head, if there is one, and return an
+	/// empty proof otherwise.
+	TryFinalizedHead,
+}
+
+/// The behavior when proving a block of the latest authority set that the best justification
+/// doesn't cover yet, i.e. a block finalized after it by a justification that wasn't stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UncoveredBlockPolicy {
+	/// Fail with [`FinalityProofError::NotYetProvable`].
+	ReturnNotYetProvable,
+	/// Wait for a justification covering the block in
+	/// [`FinalityProofProvider::prove_finality_async`]. The other methods behave as with
+	/// [`UncoveredBlockPolicy::ReturnNotYetProvable`].
+	Wait,
+}
+
+/// How a finality proof for a block would be produced, as returned by
+/// [`FinalityProofProvider::prove_finality_explain`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofPlan<N> {
+	/// The block is provable.
+	Provable {
+		/// The requested block.
+		block: N,
+		/// The number of the justified block F.
+		justified: N,
+		/// The id of the authority set that signed the justification.
+		requires_set_id: SetId,
+		/// The number of headers included in the proof.
+		headers: usize,
+	},
+	/// No justification is stored that could prove the block.
+	NoJustification {
+		/// The requested block.
+		block: N,
+	},
+	/// The block can't be proven.
+	Unprovable {
+		/// The requested block.
+		block: N,
+		/// Why the block can't be proven.
+		reason: String,
+	},
+}
+
+/// The outcome of checking a finality proof request against the limits of the provider, as
+/// returned by [`FinalityProofProvider::validate_request`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestValidation<N> {
+	/// The request is within the limits.
+	Valid {
+		/// The number of the block whose justification would prove the requested one, at most.
+		justified: N,
+	},
+	/// The requested block is not finalized yet.
+	NotYetFinalized {
+		/// The number of the last finalized block.
+		finalized: N,
+	},
+	/// The requested block is not covered by the known authority set changes.
+	NotCovered,
+	/// No justification is available to prove the requested block.
+	NoJustification,
+	/// The requested block is in the latest authority set but the best justification doesn't
+	/// cover it yet.
+	NotYetProvable {
+		/// The number of the best provable block.
+		provable: N,
+	},
+	/// The requested block is too far from the justified block.
+	ExceedsMaxGap {
+		/// The distance between the requested and the justified block.
+		gap: N,
+		/// The configured maximum gap.
+		max_gap: N,
+	},
+	/// The proof would include more headers than allowed, and the provider is configured not to
+	/// truncate it.
+	ExceedsMaxHeaders {
+		/// The number of headers the proof would include.
+		headers: usize,
+		/// The configured maximum number of headers.
+		max_headers: usize,
+	},
+	/// The headers of the proof would exceed the configured size budget.
+	ExceedsByteBudget {
+		/// The estimated size of the headers of the proof.
+		estimated_size: usize,
+		/// The configured size budget.
+		budget: usize,
+	},
+}
+
+/// The behavior when a finality proof would include more headers than allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TruncationStrategy {
+	/// Fail with [`FinalityProofError::ProofTooLarge`].
+	Fail,
+	/// Drop the headers closest to the justified block F. The resulting proof is incomplete, the
+	/// caller must obtain the missing headers by other means.
+	TruncateHigh,
+	/// Use the justification of the highest block below F for which the headers fit instead,
+	/// failing with [`FinalityProofError::ProofTooLarge`] if there is none. The resulting proof
+	/// is complete, but may finalize fewer blocks.
+	NearestJustifiedAncestor,
+}
+
+/// The position within the headers of a proof returned in batches, see
+/// [`FinalityProofProvider::prove_finality_batched`].
+#[derive(Debug, PartialEq, Eq, Encode, Decode, Clone)]
+pub struct ProofContinuation<H, N> {
+	/// The hash of the justified block F of the proof.
+	pub justified_hash: H,
+	/// The number of the next header to return.
+	pub next: N,
+	/// The number of the justified block F of the proof.
+	pub last: N,
+	/// The number of the last header of the proof, below F if the proof is truncated or doesn't
+	/// include F's header.
+	pub last_header: N,
+}
+
+/// A finality proof with the first batch of its headers, and the continuation for requesting
+/// the remaining ones if there are any.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct BatchedFinalityProof<Header: HeaderT> {
+	/// The proof, with only the headers of the first batch.
+	pub proof: FinalityProof<Header>,
+	/// The continuation for requesting the next batch of headers.
+	pub continuation: Option<ProofContinuation<Header::Hash, Header::Number>>,
+}
+
+/// A batch of the headers of a proof returned in batches, see
+/// [`FinalityProofProvider::continue_proof`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct HeaderBatch<Header: HeaderT> {
+	/// The headers of the batch. Ordered.
+	pub headers: Vec<Header>,
+	/// The continuation for requesting the next batch of headers, if there are any left.
+	pub continuation: Option<ProofContinuation<Header::Hash, Header::Number>>,
+}
+
+/// A finality proof served by [`FinalityProofProvider::prove_finality_envelope`]. The variant is
+/// encoded first, so that clients can tell the format of the proof apart.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub enum FinalityProofEnvelope<Hash> {
+	/// An encoded [`FinalityProof`].
+	#[codec(index = 0)]
+	Plain(Vec<u8>),
+	/// A proof signed by the node that served it, over the encoding of the unsigned envelope.
+	#[codec(index = 1)]
+	Signed(SignedFinalityProof),
+	/// A proof whose headers are serialized with a [`HeaderCodec`].
+	#[codec(index = 2)]
+	WithHeaderCodec(CodecFinalityProof<Hash>),
+}
+
+/// An encoded finality proof signed by the node that served it.
+///
+/// The signature only attests which node produced the proof, it does not replace checking the
+/// proof itself.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct SignedFinalityProof {
+	/// The encoded [`FinalityProofEnvelope`] of the unsigned proof.
+	pub proof: Vec<u8>,
+	/// The public key of the node that served the proof.
+	pub signer: ed25519::Public,
+	/// The signature of the node over the encoded proof.
+	pub signature: ed25519::Signature,
+}
+
+impl SignedFinalityProof {
+	fn new(proof: Vec<u8>, key: &ed25519::Pair) -> Self {
+		let signature = key.sign(&proof);
+		SignedFinalityProof { proof, signer: key.public(), signature }
+	}
+
+	/// Check that the signature over the proof was made by the signer.
+	pub fn verify_signature(&self) -> bool {
+		ed25519::Pair::verify(&self.signature, &self.proof, &self.signer)
+	}
+}
+
+/// A proof that an extrinsic is included in a block, checked against the extrinsics root of its
+/// header.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct ExtrinsicInclusionProof {
+	/// The index of the extrinsic in the block.
+	pub index: u32,
+	/// The encoded extrinsic.
+	pub extrinsic: Vec<u8>,
+	/// The nodes of the extrinsics trie proving the inclusion.
+	pub trie_nodes: Vec<Vec<u8>>,
+}
+
+impl ExtrinsicInclusionProof {
+	/// Check that the extrinsic is included at its index in the block with the given header.
+	pub fn verify<Header: HeaderT>(&self, header: &Header) -> bool {
+		let key = sp_trie::Layout::<Header::Hashing>::encode_index(self.index);
+		sp_trie::verify_trie_proof::<sp_trie::Layout<Header::Hashing>, _, _, _>(
+			header.extrinsics_root(),
+			&self.trie_nodes,
+			&[(key, Some(&self.extrinsic))],
+		)
+		.is_ok()
+	}
+}
+
+/// Prove the inclusion of the extrinsic at the given index in the block with the given hash.
+fn prove_extrinsic_inclusion<Block: BlockT>(
+	blockchain: &impl BlockchainBackend<Block>,
+	hash: Block::Hash,
+	index: u32,
+) -> ClientResult<ExtrinsicInclusionProof> {
+	use sp_trie::{Layout, MemoryDB, TrieDBMut, TrieMut};
+
+	let extrinsics = blockchain.body(BlockId::Hash(hash))?.ok_or_else(|| {
+		ClientError::UnknownBlock(format!("Missing body of block {:?}", hash))
+	})?;
+	let extrinsic = extrinsics.get(index as usize).map(Encode::encode).ok_or_else(|| {
+		ClientError::UnknownBlock(format!("Missing extrinsic #{} of block {:?}", index, hash))
+	})?;
+
+	let trie_error =
+		|err| ClientError::Backend(format!("Failed to prove extrinsic inclusion: {:?}", err));
+	let mut db = MemoryDB::<HashFor<Block>>::default();
+	let mut root = Default::default();
+	{
+		let mut trie = TrieDBMut::<Layout<HashFor<Block>>>::new(&mut db, &mut root);
+		for (i, extrinsic) in extrinsics.iter().enumerate() {
+			let key = Layout::<HashFor<Block>>::encode_index(i as u32);
+			trie.insert(&key, &extrinsic.encode()).map_err(trie_error)?;
+		}
+	}
+
+	let key = Layout::<HashFor<Block>>::encode_index(index);
+	let trie_nodes =
+		sp_trie::generate_trie_proof::<Layout<HashFor<Block>>, _, _, _>(&db, root, &[key])
+			.map_err(trie_error)?;
+
+	Ok(ExtrinsicInclusionProof { index, extrinsic, trie_nodes })
+}
+
+/// A finality proof bundled with the proof of inclusion of an extrinsic in the justified block,
+/// see [`FinalityProofProvider::prove_finality_with_extrinsic`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct FinalityProofWithExtrinsic<Header: HeaderT> {
+	/// The finality proof.
+	pub proof: DetailedFinalityProof<Header>,
+	/// The proof of inclusion of the extrinsic in the justified block.
+	pub extrinsic: ExtrinsicInclusionProof,
+}
+
+/// Finality proofs of consecutive authority set changes, see
+/// [`FinalityProofProvider::prove_finality_range`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct FinalityProofRange<Header: HeaderT> {
+	/// The proofs, ordered by set id.
+	pub fragments: Vec<DetailedFinalityProof<Header>>,
+	/// The block number to continue from if the range was capped, `None` if it is complete.
+	pub continuation: Option<Header::Number>,
+}
+
+/// Finality proofs of several blocks, each justification shared by several proofs being
+/// included only once, see [`FinalityProofProvider::prove_finality_multi`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct MultiFinalityProof<Header: HeaderT> {
+	/// The distinct justifications used by the proofs.
+	pub justifications: Vec<Vec<u8>>,
+	/// The proof of each requested block in the order requested, `None` if it can't be proven.
+	pub proofs: Vec<Option<MultiFinalityProofEntry<Header>>>,
+}
+
+/// The finality proof of a single block of a [`MultiFinalityProof`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct MultiFinalityProofEntry<Header: HeaderT> {
+	/// The hash of block F for which justification is provided.
+	pub block: Header::Hash,
+	/// The index of the justification of the block F in
+	/// [`MultiFinalityProof::justifications`].
+	pub justification_index: u32,
+	/// The set of headers in the range (B; F] that we believe are unknown to the caller. Ordered.
+	pub unknown_headers: Vec<Header>,
+}
+
+impl<Header: HeaderT> MultiFinalityProof<Header> {
+	/// Returns the self-contained [`FinalityProof`] of the block at the given index of the
+	/// request, if it was proven and its justification index is valid.
+	pub fn proof(&self, index: usize) -> Option<FinalityProof<Header>> {
+		let entry = self.proofs.get(index)?.as_ref()?;
+		let justification = self.justifications.get(entry.justification_index as usize)?;
+
+		Some(FinalityProof {
+			block: entry.block,
+			justification: justification.clone(),
+			unknown_headers: entry.unknown_headers.clone(),
+		})
+	}
+}
+
+/// Serialization of the headers of a finality proof, for interop with clients that consume
+/// headers in a format other than SCALE.
+pub trait HeaderCodec<Header>: Send + Sync {
+	/// Encode the given header.
+	fn encode_header(&self, header: &Header) -> Vec<u8>;
+
+	/// Decode a header encoded with [`Self::encode_header`].
+	fn decode_header(&self, encoded: &[u8]) -> Option<Header>;
+}
+
+/// A finality proof whose headers are serialized with a [`HeaderCodec`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct CodecFinalityProof<Hash> {
+	/// The hash of block F for which justification is provided.
+	pub block: Hash,
+	/// Justification of the block F.
+	pub justification: Vec<u8>,
+	/// The headers (B; F], each serialized with the codec.
+	pub unknown_headers: Vec<Vec<u8>>,
+}
+
+impl<Hash> CodecFinalityProof<Hash> {
+	/// Serialize the headers of the given finality proof with the given codec.
+	pub fn new<Header: HeaderT<Hash = Hash>>(
+		proof: FinalityProof<Header>,
+		codec: &dyn HeaderCodec<Header>,
+	) -> Self {
+		CodecFinalityProof {
+			block: proof.block,
+			justification: proof.justification,
+			unknown_headers: proof.unknown_headers.iter().map(|h| codec.encode_header(h)).collect(),
+		}
+	}
+
+	/// Decode the headers with the given codec, returning the finality proof they were
+	/// serialized from.
+	pub fn into_finality_proof<Header: HeaderT<Hash = Hash>>(
+		self,
+		codec: &dyn HeaderCodec<Header>,
+	) -> ClientResult<FinalityProof<Header>> {
+		let unknown_headers = self
+			.unknown_headers
+			.iter()
+			.map(|encoded| codec.decode_header(encoded))
+			.collect::<Option<Vec<_>>>()
+			.ok_or_else(|| {
+				ClientError::BadJustification("failed to decode finality proof header".into())
+			})?;
+
+		Ok(FinalityProof { block: self.block, justification: self.justification, unknown_headers })
+	}
+}
+
+/// The authority set a finality proof must be verified against, see
+/// [`FinalityProofProvider::prove_finality_with_trust_root`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct TrustRoot {
+	/// The id of the authority set.
+	pub set_id: SetId,
+	/// The authorities of the set.
+	pub authorities: sp_finality_grandpa::AuthorityList,
+}
+
+/// Whether a block is finalized, see [`FinalityProofProvider::finality_status`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FinalityStatus<N> {
+	/// Whether the block is finalized.
+	pub finalized: bool,
+	/// The last block of the completed authority set covering the block, whose justification
+	/// proves its finality, if the block belongs to a completed set.
+	pub covering_block: Option<N>,
+}
+
+/// The outcome of [`FinalityProofProvider::prebuild_boundary_cache`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct BoundaryCacheReport {
+	/// The number of boundary proofs retained by the proof cache once built.
+	pub cached: usize,
+	/// The number of boundaries skipped because their proof couldn't be loaded, built or
+	/// persisted.
+	pub failed: usize,
+}
+
+/// A finality proof together with the information needed to schedule its verification.
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub struct DetailedFinalityProof<Header: HeaderT> {
+	/// The finality proof.
+	pub proof: FinalityProof<Header>,
+	/// The id of the authority set that signed the justification. The verifier must already
+	/// hold this set (i.e. have verified all fragments proving the preceding set changes) before
+	/// it is able to check this proof.
+	pub requires_set_id: SetId,
+	/// The number of the justified block F.
+	pub justified_number: Header::Number,
+	/// Whether the justification is the best justification of the latest authority set, rather
+	/// than the one stored at the last block of a completed set.
+	pub from_best_justification: bool,
+}
+
+impl<Header: HeaderT> DetailedFinalityProof<Header> {
+	/// Merge consecutive fragments signed by the same authority set into a single one.
+	///
+	/// The merged fragment uses the justification of the last of them, with the headers of all of
+	/// them, and therefore proves the finality of the blocks proven by any of them.
+	pub fn compact(fragments: Vec<Self>) -> Vec<Self> {
+		let mut compacted: Vec<Self> = Vec::with_capacity(fragments.len());
+		for mut fragment in fragments {
+			match compacted.last_mut() {
+				Some(last) if last.requires_set_id == fragment.requires_set_id => {
+					let mut unknown_headers = std::mem::take(&mut last.proof.unknown_headers);
+					unknown_headers.append(&mut fragment.proof.unknown_headers);
+					fragment.proof.unknown_headers = unknown_headers;
+					*last = fragment;
+				},
+				_ => compacted.push(fragment),
+			}
+		}
+
+		compacted
+	}
+}
+
+/// Errors occurring when trying to prove finality
+#[derive(Debug, derive_more::Display, derive_more::From)]
+pub enum FinalityProofError {
+	/// The requested block has not yet been finalized.
+	#[display(fmt = "Block not yet finalized")]
+	BlockNotYetFinalized,
+	/// The requested block is not covered by authority set changes. Likely this means the block is
+	/// in the latest authority set, and the subscription API is more appropriate.
+	#[display(fmt = "Block not covered by authority set changes")]
+	BlockNotInAuthoritySetChanges,
+	/// The requested block is not an ancestor of the justified block, i.e. it is on a fork that
+	/// wasn't finalized.
+	#[display(fmt = "Block is not an ancestor of the justified block")]
+	BlockNotAncestorOfJustified,
+	/// The proof for the requested block would exceed the configured limits.
+	#[display(fmt = "Finality proof exceeds the configured limits")]
+	ProofTooLarge,
+	/// No justification is available to prove the requested block.
+	#[display(fmt = "No justification available to prove the requested block")]
+	JustificationNotFound,
+	/// The requested block is in the latest authority set but the best justification doesn't
+	/// cover it yet. Contains the number of the best provable block.
+	#[display(fmt = "Block not yet provable, the best provable block is #{}", _0)]
+	#[from(ignore)]
+	NotYetProvable(u64),
+	/// The authorities of the set that signed the proof are not known.
+	#[display(fmt = "Authorities of the authority set not found")]
+	AuthoritiesNotFound,
+	/// No finalized block within the search depth has the requested state root.
+	#[display(fmt = "No finalized block with the requested state root")]
+	StateRootNotFound,
+	/// The requested range of blocks exceeds the configured maximum.
+	#[display(fmt = "Requested range of blocks exceeds the configured maximum")]
+	RangeTooLarge,
+	/// Errors originating from the client.
+	Client(sp_blockchain::Error),
+	/// Errors reading or writing a finality proof archive.
+	Io(std::io::Error),
+}
+
+#[cfg(test)]
+fn prove_finality<Block, B, J>(
+	backend: &B,
+	authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
+	block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> Result<Option<Vec<u8>>, FinalityProofError>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+	J: ProvableJustification<Block::Header>,
+{
+	prove_finality_detailed::<_, _, J>(backend, authority_set_changes, block, config)
+		.map(|proof| proof.map(|proof| proof.proof.encode()))
+}
+
+fn prove_finality_detailed<Block, B, J>(
+	backend: &B,
+	authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
+	block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+	J: ProvableJustification<Block::Header>,
+{
+	prove_finality_detailed_on_fork::<_, _, J>(backend, authority_set_changes, block, None, config)
+}
+
+/// Prove finality of the given block. If its hash is given the headers of the proof are collected
+/// by following the parent links from the justified block down to it, otherwise they are looked
+/// up by number on the canonical chain.
+fn prove_finality_detailed_on_fork<Block, B, J>(
+	backend: &B,
+	authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
+	block: NumberFor<Block>,
+	block_hash: Option<Block::Hash>,
+	config: &FinalityProofConfig<Block>,
+) -> Result<Option<DetailedFinalityProof<Block::Header>>, FinalityProofError>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+	J: ProvableJustification<Block::Header>,
+{
+	let (set_id, justification, just_block, from_best_justification) =
+		match select_justification(backend, authority_set_changes, block, config)? {
+			Some(selected) => selected,
+			None => return Ok(None),
+		};
+
+	// Collect all headers from the requested block until the justified block
+	let blockchain = backend.blockchain();
+	let just_hash = blockchain.expect_block_hash_from_id(&BlockId::Number(just_block))?;
+	let unknown_headers = match block_hash {
+		Some(block_hash) => collect_headers_on_fork(blockchain, block_hash, just_hash, config)?,
+		None => collect_unknown_headers(blockchain, block, just_block, config)?,
+	};
+
+	if let Some(budget) = config.max_proof_size {
+		let size = unknown_headers.iter().map(Encode::encoded_size).sum::<usize>();
+		if size > budget {
+			trace!(
+				target: "afg",
+				"Requested finality proof for #{} has {} bytes of headers, exceeding the \
+				maximum of {}.",
+				block,
+				size,
+				budget,
+			);
+			return Err(FinalityProofError::ProofTooLarge);
+		}
+	}
+
+	Ok(Some(DetailedFinalityProof {
+		proof: FinalityProof {
+			block: just_hash,
+			justification,
+			unknown_headers,
+		},
+		requires_set_id: set_id,
+		justified_number: just_block,
+		from_best_justification,
+	}))
+}
+
+/// Select the justification used to prove finality of the given block, returning the id of the
+/// set that signed it, the justification, the number of the justified block and whether it is the
+/// best justification of the latest set.
+fn select_justification<Block, B>(
+	backend: &B,
+	authority_set_changes: AuthoritySetChanges<NumberFor<Block>>,
+	block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> Result<Option<(SetId, Vec<u8>, NumberFor<Block>, bool)>, FinalityProofError>
+where
+	Block: BlockT,
+	B: Backend<Block>,
+{
+	let blockchain = backend.blockchain();
+
+	// Early-return if we sure that there are no blocks finalized AFTER begin block
+	let info = blockchain.info();
+	if info.finalized_number < block {
+		let err = format!(
+			"Requested finality proof for descendant of #{} while we only have finalized #{}.",
+			block,
+			info.finalized_number,
+		);
+		trace!(target: "afg", "{}", &err);
+		return Err(FinalityProofError::BlockNotYetFinalized);
+	}
+
+	// Get set_id the block belongs to, and the last block of the set which should contain a
+	// Justification we can use to prove the requested block.
+	let set_change_id = authority_set_changes.get_set_id(block);
+	let (previous_boundary, next_boundary) = authority_set_changes.boundaries_around(block);
+	tracing::debug!(
+		target: "afg",
+		block = %block,
+		previous_boundary = ?previous_boundary,
+		next_boundary = ?next_boundary,
+		outcome = ?set_change_id,
+		"Looked up authority set changes for finality proof"
+	);
+
+	let (set_id, justification, just_block, from_best_justification) = match set_change_id {
+		AuthoritySetChangeId::Set(set_id, last_block_for_set) => {
+			// Reject pathological requests up front, before reading anything from the database.
+			ensure_within_max_gap(block, last_block_for_set, config)?;
+
+			// Get the Justification stored at the last block of the set
+			let justification = if let Some(justification) = blockchain
+				.justifications(BlockId::Number(last_block_for_set))?
+				.and_then(|justifications| {
+					justifications.into_justification(config.finality_source.engine_id())
+				})
+			{
+				justification
+			} else {
+				trace!(
+					target: "afg",
+					"No justification found when making finality proof for {}. \
+					Returning empty proof.",
+					block,
+				);
+				return Ok(None);
+			};
+
+			(set_id, justification, last_block_for_set, false)
+		},
+		AuthoritySetChangeId::Latest if config.finality_source == FinalitySource::Grandpa => {
+			// The block is in the latest authority set, use the justification of the best block
+			// finalized by GRANDPA.
+			let best_justification = crate::aux_schema::best_justification::<_, Block>(backend)?;
+			let best_justification = match best_justification {
+				Some(justification) => {
+					let (target_number, target_hash) = justification.target();
+					// The best justification is only served once its target is on the canonical
+					// chain, otherwise it's treated as missing.
+					if blockchain.hash(target_number)? == Some(target_hash) {
+						Some((target_number, justification.encode()))
+					} else {
+						trace!(
+							target: "afg",
+							"Best justification target {:?} is not canonical at #{}, ignoring it.",
+							target_hash,
+							target_number,
+						);
+						None
+					}
+				},
+				None => None,
+			};
+
+			let policy = config.no_justification_policy;
+			let (just_block, justification) = match (best_justification, policy) {
+				(Some(best_justification), _) => best_justification,
+				(None, NoJustificationPolicy::ReturnError) => {
+					trace!(
+						target: "afg",
+						"No best justification found when making finality proof for {}.",
+						block,
+					);
+					return Err(FinalityProofError::JustificationNotFound);
+				},
+				(None, NoJustificationPolicy::TryFinalizedHead) => {
+					match blockchain
+						.justifications(BlockId::Number(info.finalized_number))?
+						.and_then(|justifications| {
+							justifications.into_justification(config.finality_source.engine_id())
+						})
+					{
+						Some(justification) => (info.finalized_number, justification),
+						None => {
+							trace!(
+								target: "afg",
+								"No justification found at the finalized head when making finality \
+								proof for {}. Returning empty proof.",
+								block,
+							);
+							return Ok(None);
+						},
+					}
+				},
+				(None, NoJustificationPolicy::ReturnNone) => {
+					trace!(
+						target: "afg",
+						"No best justification found when making finality proof for {}. \
+						Returning empty proof.",
+						block,
+					);
+					return Ok(None);
+				},
+			};
+
+			// The best justification may lag behind the finalized head, in which case it doesn't
+			// finalize the requested block.
+			if just_block < block {
+				trace!(
+					target: "afg",
+					"Best justification for #{} is below the requested block #{}.",
+					just_block,
+					block,
+				);
+				return Err(FinalityProofError::NotYetProvable(just_block.saturated_into()));
+			}
+
+			ensure_within_max_gap(block, just_block, config)?;
+
+			let set_id = previous_boundary.map(|(set_id, _)| set_id + 1).unwrap_or_default();
+			(set_id, justification, just_block, true)
+		},
+		AuthoritySetChangeId::Latest | AuthoritySetChangeId::Unknown => {
+			trace!(
+				target: "afg",
+				"AuthoritySetChanges does not cover the requested block #{}. \
+				Maybe the subscription API is more appropriate.",
+				block,
+			);
+			return Err(FinalityProofError::BlockNotInAuthoritySetChanges);
+		},
+	};
+
+	let header_count =
+		config.last_proof_header(just_block).saturating_sub(block).saturated_into::<usize>();
+	let (just_block, justification, from_best_justification) =
+		if header_count <= config.max_unknown_headers {
+			(just_block, justification, from_best_justification)
+		} else {
+			match config.truncation_strategy {
+				TruncationStrategy::TruncateHigh =>
+					(just_block, justification, from_best_justification),
+				TruncationStrategy::Fail => {
+					trace!(
+						target: "afg",
+						"Requested finality proof for #{} requires {} headers, exceeding the \
+						maximum of {}.",
+						block,
+						header_count,
+						config.max_unknown_headers,
+					);
+					return Err(FinalityProofError::ProofTooLarge);
+				},
+				TruncationStrategy::NearestJustifiedAncestor => {
+					let (ancestor, justification) =
+						nearest_justified_ancestor(blockchain, block, just_block, config)?
+							.ok_or(FinalityProofError::ProofTooLarge)?;
+					(ancestor, justification, false)
+				},
+			}
+		};
+
+	Ok(Some((set_id, justification, just_block, from_best_justification)))
+}
+
+fn ensure_within_max_gap<Block: BlockT>(
+	block: NumberFor<Block>,
+	just_block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> Result<(), FinalityProofError> {
+	match config.max_gap {
+		Some(max_gap) if just_block.saturating_sub(block) > max_gap => {
+			trace!(
+				target: "afg",
+				"Requested finality proof for #{} requires a justification at #{}, \
+				exceeding the maximum gap of {} blocks.",
+				block,
+				just_block,
+				max_gap,
+			);
+			Err(FinalityProofError::ProofTooLarge)
+		},
+		_ => Ok(()),
+	}
+}
+
+/// Find the highest block below `just_block` with a stored justification for which proving
+/// `block` doesn't exceed the configured limit on the number of headers. At most
+/// `max_justification_lookups` blocks are searched.
+fn nearest_justified_ancestor<Block, B>(
+	blockchain: &B,
+	block: NumberFor<Block>,
+	just_block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> ClientResult<Option<(NumberFor<Block>, Vec<u8>)>>
+where
+	Block: BlockT,
+	B: BlockchainBackend<Block>,
+{
+	let highest: NumberFor<Block> = config.max_unknown_headers.saturated_into();
+	let first = if config.include_justified_header { block } else { block + One::one() };
+	let mut current = first.saturating_add(highest).min(just_block);
+	let lookups: NumberFor<Block> = config.max_justification_lookups.saturated_into();
+	let lowest = current.saturating_sub(lookups).max(block);
+	while current > lowest {
+		let justification = blockchain
+			.justifications(BlockId::Number(current))?
+			.and_then(|justifications| {
+				justifications.into_justification(config.finality_source.engine_id())
+			});
+		if let Some(justification) = justification {
+			return Ok(Some((current, justification)));
+		}
+		current -= One::one();
+	}
+
+	Ok(None)
+}
+
+/// Collect the headers after the block with the given hash up to the given justified block,
+/// including it only if configured to, by following parent links.
+///
+/// At most `max_unknown_headers` headers are read, failing with
+/// [`FinalityProofError::ProofTooLarge`] if the block isn't reached by then: unlike headers looked
+/// up by number, the headers can't be truncated without walking the whole fork first.
+fn collect_headers_on_fork<Block, B>(
+	blockchain: &B,
+	block_hash: Block::Hash,
+	just_hash: Block::Hash,
+	config: &FinalityProofConfig<Block>,
+) -> Result<Vec<Block::Header>, FinalityProofError>
+where
+	Block: BlockT,
+	B: BlockchainBackend<Block>,
+{
+	let block = blockchain.expect_header(BlockId::Hash(block_hash))?;
+
+	let mut headers = Vec::new();
+	let mut current = blockchain.expect_header(BlockId::Hash(just_hash))?;
+	if !config.include_justified_header && current.number() > block.number() {
+		current = blockchain.expect_header(BlockId::Hash(*current.parent_hash()))?;
+	}
+	while current.number() > block.number() {
+		if headers.len() >= config.max_unknown_headers {
+			return Err(FinalityProofError::ProofTooLarge);
+		}
+
+		let parent_hash = *current.parent_hash();
+		headers.push(current);
+		current = blockchain.expect_header(BlockId::Hash(parent_hash))?;
+	}
+
+	if current.hash() != block_hash {
+		return Err(FinalityProofError::BlockNotAncestorOfJustified);
+	}
+
+	headers.reverse();
+	Ok(headers)
+}
+
+/// Collect the headers after `block` up to the given `last_block`, including it only if
+/// configured to, and respecting the configured limit on the number of headers.
+fn collect_unknown_headers<Block, B>(
+	blockchain: &B,
+	block: NumberFor<Block>,
+	last_block: NumberFor<Block>,
+	config: &FinalityProofConfig<Block>,
+) -> ClientResult<Vec<Block::Header>>
+where
+	Block: BlockT,
+	B: BlockchainBackend<Block>,
+{
+	let first = block + One::one();
+	let count = config
+		.last_proof_header(last_block)
+		.saturating_sub(block)
+		.saturated_into::<usize>()
+		.min(config.max_unknown_headers);
+
+	match config.parallel_header_collection_threshold {
+		#[cfg(feature = "parallel")]
+		Some(threshold) if count > threshold => {
+			use rayon::prelude::*;
+
+			// collecting an indexed parallel iterator preserves the order of the offsets.
+			(0..count)
+				.into_par_iter()
+				.map(|offset| {
+					let offset: NumberFor<Block> = offset.saturated_into();
+					blockchain.expect_header(BlockId::Number(first + offset))
+				})
+				.collect()
+		},
+		_ => {
+			let mut headers = Vec::with_capacity(count);
+			let mut current = first;
+			while headers.len() < count {
+				headers.push(blockchain.expect_header(BlockId::Number(current))?);
+				current += One::one();
+			}
+			Ok(headers)
+		},
+	}
+}
+
+/// The version of this crate, declared in the [`FinalityProofV2`] proofs we serve.
+const SERVER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The justification carried by a [`FinalityProofV2`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub enum ProofJustification {
+	/// The encoded justification.
+	Inline(Vec<u8>),
+	/// The blake2-256 hash of the encoded justification, which the verifier resolves from its
+	/// own storage.
+	JustificationRef([u8; 32]),
+}
+
+/// The headers carried by a [`FinalityProofV2`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProofHeaders<Header: HeaderT> {
+	/// The full headers.
+	Full(Vec<Header>),
+	/// The hashes of the headers, which the verifier resolves from its own header store.
+	HashOnly(Vec<Header::Hash>),
+}
+
+/// An authority set change signalled in the digest of the justified block of a
+/// [`FinalityProofV2`].
+#[derive(Debug, PartialEq, Encode, Decode, Clone)]
+pub enum ProofSetChange<N> {
+	/// A standard scheduled change.
+	Scheduled(ScheduledChange<N>),
+	/// A forced change, with the median last finalized block when it was signalled.
+	Forced(N, ScheduledChange<N>),
+}
+
+/// The version of the encoding of [`FinalityProofV2`], which prefixes the encoded proofs.
+const FINALITY_PROOF_V2_VERSION: u8 = 1;
+
+/// The optional fields of an encoded [`FinalityProofV2`], each given by its tag and its encoding.
+type OptionalFields = Vec<(u8, Vec<u8>)>;
+
+/// The tag of the declared threshold in the optional fields of an encoded [`FinalityProofV2`].
+const FIELD_THRESHOLD: u8 = 0;
+
+/// The tag of the sibling hints in the optional fields of an encoded [`FinalityProofV2`].
+const FIELD_SIBLING_HINTS: u8 = 1;
+
+/// The tag of the hashes of hash-only headers in the optional fields of an encoded
+/// [`FinalityProofV2`].
+const FIELD_HEADER_HASHES: u8 = 2;
+
+/// The tag of the embedded set change in the optional fields of an encoded [`FinalityProofV2`].
+const FIELD_SET_CHANGE: u8 = 3;
+
+/// The tag of the prerequisite set count in the optional fields of an encoded
+/// [`FinalityProofV2`].
+const FIELD_PREREQUISITE_SETS: u8 = 4;
+
+/// The tag of the server version in the optional fields of an encoded [`FinalityProofV2`].
+const FIELD_SERVER_VERSION: u8 = 5;
+
+/// The tag of the genesis hash in the optional fields of an encoded [`FinalityProofV2`].
+const FIELD_GENESIS_HASH: u8 = 6;
+
+/// A finality proof that may reference its justification instead of carrying it, for transports
+/// that store justifications separately.
+///
+/// See [`FinalityProof`] for the meaning of the fields. The encoding starts with its version and
+/// the fields every proof carries, followed by the tagged optional fields. Decoding skips the
+/// optional fields it doesn't know and leaves the missing ones unset, so adding an optional field
+/// keeps the proofs encoded before decodable.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FinalityProofV2<Header: HeaderT> {
+	/// The hash of block F for which justification is provided.
+	pub block: Header::Hash,
+	/// Justification of the block F.
+	pub justification: ProofJustification,
+	/// The set of headers in the range (B; F] that we believe are unknown to the caller. Ordered.
+	pub unknown_headers: ProofHeaders<Header>,
+	/// The total weight of the authorities required to finalize F, if it differs from the
+	/// default supermajority of the authority set.
+	pub threshold: Option<u64>,
+	/// The hashes of competing blocks at the height of F known to the prover.
+	pub sibling_hints: Vec<Header::Hash>,
+	/// The authority set change signalled by F, which the verifier checks against the digest of
+	/// F's header.
+	pub set_change: Option<ProofSetChange<Header::Number>>,
+	/// The number of authority set changes the verifier must verify, starting from the set it
+	/// trusts, before it knows the authorities to check this proof against. Allows estimating the
+	/// cost of verification, `None` if the prover doesn't know the trusted set.
+	pub prerequisite_sets: Option<u32>,
+	/// The version of the software of the node that served the proof, if it declared it. Lets
+	/// the verifier notice when it talks to a node of a different version.
+	pub server_version: Option<String>,
+	/// The genesis hash of the chain the proof belongs to, if the prover embedded it.
+	pub genesis_hash: Option<Header::Hash>,
+}
+
+impl<Header: HeaderT> Encode for FinalityProofV2<Header> {
+	fn encode_to<T: parity_scale_codec::Output + ?Sized>(&self, dest: &mut T) {
+		FINALITY_PROOF_V2_VERSION.encode_to(dest);
+		self.block.encode_to(dest);
+		self.justification.encode_to(dest);
+		match &self.unknown_headers {
+			ProofHeaders::Full(headers) => headers.encode_to(dest),
+			// The hashes are carried by an optional field.
+			ProofHeaders::HashOnly(_) => Vec::<Header>::new().encode_to(dest),
+		}
+
+		let mut fields = OptionalFields::new();
+		if let Some(threshold) = self.threshold {
+			fields.push((FIELD_THRESHOLD, threshold.encode()));
+		}
+		if !self.sibling_hints.is_empty() {
+			fields.push((FIELD_SIBLING_HINTS, self.sibling_hints.encode()));
+		}
+		if let ProofHeaders::HashOnly(hashes) = &self.unknown_headers {
+			fields.push((FIELD_HEADER_HASHES, hashes.encode()));
+		}
+		if let Some(set_change) = &self.set_change {
+			fields.push((FIELD_SET_CHANGE, set_change.encode()));
+		}
+		if let Some(prerequisite_sets) = self.prerequisite_sets {
+			fields.push((FIELD_PREREQUISITE_SETS, prerequisite_sets.encode()));
+		}
+		if let Some(server_version) = &self.server_version {
+			fields.push((FIELD_SERVER_VERSION, server_version.encode()));
+		}
+		if let Some(genesis_hash) = &self.genesis_hash {
+			fields.push((FIELD_GENESIS_HASH, genesis_hash.encode()));
+		}
+		fields.encode_to(dest);
+	}
+}
+
+impl<Header: HeaderT> Decode for FinalityProofV2<Header> {
+	fn decode<I: parity_scale_codec::Input>(
+		input: &mut I,
+	) -> Result<Self, parity_scale_codec::Error> {
+		if u8::decode(input)? != FINALITY_PROOF_V2_VERSION {
+			return Err("Unsupported version of the finality proof encoding".into());
+		}
+
+		let mut proof = FinalityProofV2 {
+			block: Decode::decode(input)?,
+			justification: Decode::decode(input)?,
+			unknown_headers: ProofHeaders::Full(Decode::decode(input)?),
+			threshold: None,
+			sibling_hints: Vec::new(),
+			set_change: None,
+			prerequisite_sets: None,
+			server_version: None,
+			genesis_hash: None,
+		};
+		for (tag, field) in OptionalFields::decode(input)? {
+			let field = &mut &field[..];
+			match tag {
+				FIELD_THRESHOLD => proof.threshold = Some(Decode::decode(field)?),
+				FIELD_SIBLING_HINTS => proof.sibling_hints = Decode::decode(field)?,
+				FIELD_HEADER_HASHES => {
+					proof.unknown_headers = ProofHeaders::HashOnly(Decode::decode(field)?)
+				},
+				FIELD_SET_CHANGE => proof.set_change = Some(Decode::decode(field)?),
+				FIELD_PREREQUISITE_SETS => proof.prerequisite_sets = Some(Decode::decode(field)?),
+				FIELD_SERVER_VERSION => proof.server_version = Some(Decode::decode(field)?),
+				FIELD_GENESIS_HASH => proof.genesis_hash = Some(Decode::decode(field)?),
+				// Skip the optional fields added by later versions of the encoding.
+				_ => {},
+			}
+		}
+
+		Ok(proof)
+	}
+}
+
+impl<Header: HeaderT> FinalityProofV2<Header> {
+	/// Convert the given proof, replacing its justification by a reference to it.
+	pub fn with_justification_ref(proof: FinalityProof<Header>) -> Self {
+		FinalityProofV2 {
+			block: proof.block,
+			justification: ProofJustification::JustificationRef(blake2_256(&proof.justification)),
+			unknown_headers: ProofHeaders::Full(proof.unknown_headers),
+			threshold: None,
+			sibling_hints: Vec::new(),
+			set_change: None,
+			prerequisite_sets: None,
+			server_version: None,
+			genesis_hash: None,
+		}
+	}
+
+	/// Embed the authority set change signalled in the digest of F's header, if any. F's header
+	/// must be included in the proof.
+	pub fn with_embedded_set_change(mut self) -> Self {
+		if let ProofHeaders::Full(headers) = &self.unknown_headers {
+			self.set_change = headers
+				.last()
+				.filter(|header| header.hash() == self.block)
+				.and_then(find_set_change);
+		}
+		self
+	}
+
+	/// Replace the headers of the proof by their hashes, for clients that already store the
+	/// headers locally.
+	pub fn with_hash_only_headers(mut self) -> Self {
+		if let ProofHeaders::Full(headers) = &self.unknown_headers {
+			let hashes = headers.iter().map(|header| header.hash()).collect();
+			self.unknown_headers = ProofHeaders::HashOnly(hashes);
+		}
+		self
+	}
+}
+
+impl<Header: HeaderT> FinalityProofV2<Header> {
+	/// Whether the given block, known to the caller at the height of F, is one of the siblings of
+	/// F hinted by the prover, i.e. the caller followed a fork that was not finalized.
+	pub fn is_known_sibling(&self, known_block: &Header::Hash) -> bool {
+		let is_sibling = self.sibling_hints.contains(known_block);
+		if is_sibling {
+			warn!(
+				target: "afg",
+				"Block {:?} is a sibling of the finalized block {:?}, we are on a different fork.",
+				known_block,
+				self.block,
+			);
+		}
+		is_sibling
+	}
+}
+
+impl<Header: HeaderT> From<FinalityProof<Header>> for FinalityProofV2<Header> {
+	fn from(proof: FinalityProof<Header>) -> Self {
+		FinalityProofV2 {
+			block: proof.block,
+			justification: ProofJustification::Inline(proof.justification),
+			unknown_headers: ProofHeaders::Full(proof.unknown_headers),
+			threshold: None,
+			sibling_hints: Vec::new(),
+			set_change: None,
+			prerequisite_sets: None,
+			server_version: None,
+			genesis_hash: None,
+		}
+	}
+}
+
+fn write_archive_entry<Header: HeaderT>(
+	mut writer: impl std::io::Write,
+	proof: &DetailedFinalityProof<Header>,
+) -> std::io::Result<()> {
+	let proof = proof.encode();
+	writer.write_all(&(proof.len() as u32).to_le_bytes())?;
+	writer.write_all(&proof)
+}
+
+/// Errors occurring when verifying a finality proof archive.
+#[derive(Debug, derive_more::Display)]
+pub enum VerifyError {
+	/// The archive could not be read.
+	#[display(fmt = "Failed to read archive: {}", _0)]
+	Archive(FinalityProofError),
+	/// The proof of the fragment at the given index is invalid.
+	#[display(fmt = "Invalid fragment #{}: {}", _0, _1)]
+	InvalidFragment(usize, ClientError),
+	/// The fragment at the given index proves a set other than the one it follows.
+	#[display(fmt = "Fragment #{} does not follow the previous authority set", _0)]
+	UnexpectedSetId(usize),
+	/// The fragment at the given index does not signal an authority set change.
+	#[display(fmt = "Fragment #{} does not signal an authority set change", _0)]
+	MissingAuthoritySetChange(usize),
+	/// The fragment at the given index implies a transition between the given sets, skipping
+	/// some of them.
+	#[display(fmt = "Fragment #{} implies an illegal transition from set {} to set {}", _0, _1, _2)]
+	IllegalSetTransition(usize, SetId, SetId),
+	/// The fragment at the given index doesn't extend the last finalized block.
+	#[display(fmt = "Fragment #{} does not descend from the last finalized block", _0)]
+	NotDescendant(usize),
+	/// The item at the given index was not verified since the verification budget was exhausted.
+	#[display(fmt = "Item #{} was deferred, the verification budget is exhausted", _0)]
+	Deferred(usize),
+}
+
+/// Whether the transition between the given authority sets is legal, i.e. the set id increases
+/// by exactly one.
+pub fn is_legal_set_transition(from: SetId, to: SetId) -> bool {
+	from.checked_add(1) == Some(to)
+}
+
+/// Verify a finality proof archive written by [`FinalityProofProvider::export_archive`] offline,
+/// starting from the given genesis authority set.
+///
+/// Each fragment is checked against the authority set enacted by the previous one, returning
+/// the full timeline of authority sets (starting with the genesis set) or the first failure.
+pub fn verify_archive<Block: BlockT, J>(
+	archive: &[u8],
+	genesis_set_id: SetId,
+	genesis_authorities: sp_finality_grandpa::AuthorityList,
+) -> Result<Vec<(SetId, sp_finality_grandpa::AuthorityList)>, VerifyError>
+where
+	J: ProvableJustification<Block::Header>,
+{
+	let proofs = import_archive::<Block::Header>(archive).map_err(VerifyError::Archive)?;
+
+	let mut timeline = vec![(genesis_set_id, genesis_authorities)];
+	for (index, proof) in proofs.into_iter().enumerate() {
+		let current = timeline.last().cloned().expect("timeline is never empty; qed");
+		timeline.push(verify_archive_fragment::<Block, J>(index, current, proof)?);
+	}
+
+	Ok(timeline)
+}
+
+/// Verify a finality proof archive written by [`FinalityProofProvider::export_archive`] while
+/// reading it from the given reader, one fragment at a time.
+///
+/// Unlike [`verify_archive`], neither the archive nor the timeline of authority sets is kept in
+/// memory, allowing to verify arbitrarily large archives. Returns the authority set enacted by
+/// the last fragment (the genesis set for an empty archive) or the first failure, in which case
+/// the rest of the archive is not read.
+pub fn verify_archive_streaming<Block: BlockT, J>(
+	mut reader: impl std::io::Read,
+	genesis_set_id: SetId,
+	genesis_authorities: sp_finality_grandpa::AuthorityList,
+) -> Result<(SetId, sp_finality_grandpa::AuthorityList), VerifyError>
+where
+	J: ProvableJustification<Block::Header>,
+{
+	let mut current = (genesis_set_id, genesis_authorities);
+	let mut index = 0;
+	while let Some(proof) =
+		read_archive_entry::<Block::Header>(&mut reader).map_err(VerifyError::Archive)?
+	{
+		current = verify_archive_fragment::<Block, J>(index, current, proof)?;
+		index += 1;
+	}
+
+	Ok(current)
+}
+
+/// Verify a sequence of finality proofs of consecutive authority set changes, starting from the
+/// trusted genesis authority set.
+///
+/// The authorities of each subsequent set are derived from the standard or forced change
+/// signalled by the justified header of the previous fragment, no other authorities are needed.
+/// Returns the full timeline of authority sets (starting with the genesis set) or the first
+/// failure.
+pub fn verify_sequence_self_contained<Header: HeaderT, J>(
+	fragments: Vec<FinalityProof<Header>>,
+	genesis_set_id: SetId,
+	genesis_authorities: sp_finality_grandpa::AuthorityList,
+) -> Result<Vec<(SetId, sp_finality_grandpa::AuthorityList)>, VerifyError>
+where
+	J: ProvableJustification<Header>,
+{
+	let mut timeline = vec![(genesis_set_id, genesis_authorities)];
+	for (index, proof) in fragments.into_iter().enumerate() {
+		let (set_id, authorities) = timeline.last().cloned().expect("timeline is never empty; qed");
+		let (proof, _) =
+			check_justification::<Header, J>(set_id, authorities, proof, &Default::default())
+				.map_err(|err| VerifyError::InvalidFragment(index, err))?;
+
+		let next_authorities = match proof.signalled_set_change(&Default::default()) {
+			Some(ProofSetChange::Scheduled(change)) | Some(ProofSetChange::Forced(_, change)) =>
+				change.next_authorities,
+			None => return Err(VerifyError::MissingAuthoritySetChange(index)),
+		};
+
+		timeline.push((set_id + 1, next_authorities));
+	}
+
+	Ok(timeline)
+}
+
+/// Ensure that the fragment at the given index, signed by the set `requires_set_id`, follows the
+/// current set.
+fn ensure_fragment_follows_set(
+	index: usize,
+	set_id: SetId,
+	requires_set_id: SetId,
+) -> Result<(), VerifyError> {
+	// A fragment of a set that was already verified doesn't follow the current set.
+	if requires_set_id < set_id {
+		return Err(VerifyError::UnexpectedSetId(index));
+	}
+
+	// The fragment enacts the set following the one that signed it, which must be the one
+	// following the current set.
+	let enacted_set_id = requires_set_id.saturating_add(1);
+	if !is_legal_set_transition(set_id, enacted_set_id) {
+		return Err(VerifyError::IllegalSetTransition(index, set_id, enacted_set_id));
+	}
+
+	Ok(())
+}
+
+/// A state trusted by a client, i.e. the current authority set and the last finalized block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrustedState<Header: HeaderT> {
+	/// The id of the current authority set.
+	pub set_id: SetId,
+	/// The authorities of the current set.
+	pub authorities: sp_finality_grandpa::AuthorityList,
+	/// The number of the last finalized block.
+	pub finalized_number: Header::Number,
+	/// The hash of the last finalized block.
+	pub finalized_hash: Header::Hash,
+	/// A hash accumulated over all the authority sets trusted so far, see
+	/// [`rolling_authority_hash`].
+	pub rolling_authority_hash: [u8; 32],
+}
+
+impl<Header: HeaderT> TrustedState<Header> {
+	/// Create the genesis state of a client trusting the given authority set, with the rolling
+	/// authority hash accumulated over it.
+	pub fn genesis(
+		set_id: SetId,
+		authorities: sp_finality_grandpa::AuthorityList,
+		finalized_number: Header::Number,
+		finalized_hash: Header::Hash,
+	) -> Self {
+		let rolling_hash = rolling_authority_hash(Default::default(), set_id, &authorities);
+		TrustedState {
+			rolling_authority_hash: rolling_hash,
+			set_id,
+			authorities,
+			finalized_number,
+			finalized_hash,
+		}
+	}
+}
+
+/// Accumulate the given authority set into the rolling hash of the previously trusted sets.
+///
+/// This allows a client to represent its whole trusted set history with a single hash.
+pub fn rolling_authority_hash(
+	previous: [u8; 32],
+	set_id: SetId,
+	authorities: &sp_finality_grandpa::AuthorityList,
+) -> [u8; 32] {
+	blake2_256(&(previous, set_id, authorities).encode())
+}
+
+/// Verify a bootstrap sequence of fragments starting from the given trusted genesis state,
+/// returning the state reached at the end of it.
+///
+/// Each fragment must be signed by the current set, and all fragments but the last one must
+/// enact an authority set change. The headers of each fragment must extend the last finalized
+/// block up to the justified block, e.g. as in the proof of the finality of the last finalized
+/// block. Verification is all or nothing: if any fragment is invalid no state is returned, so
+/// the caller can't end up trusting a part of the sequence. Each enacted authority set is
+/// accumulated into the rolling authority hash of the state.
+pub fn verify_and_commit<Header: HeaderT, J>(
+	fragments: Vec<DetailedFinalityProof<Header>>,
+	genesis_state: TrustedState<Header>,
+) -> Result<TrustedState<Header>, VerifyError>
+where
+	J: ProvableJustification<Header>,
+{
+	let mut state = genesis_state;
+	let mut enacted_change = true;
+	for (index, fragment) in fragments.into_iter().enumerate() {
+		// Only the last fragment may not enact a change, the ones following it can't be checked.
+		if !enacted_change {
+			return Err(VerifyError::MissingAuthoritySetChange(index - 1));
+		}
+
+		ensure_fragment_follows_set(index, state.set_id, fragment.requires_set_id)?;
+		let (proof, justification) = check_justification::<Header, J>(
+			state.set_id,
+			state.authorities.clone(),
+			fragment.proof,
+			&Default::default(),
+		)
+		.map_err(|err| VerifyError::InvalidFragment(index, err))?;
+
+		let justified_header =
+			match (proof.unknown_headers.first(), proof.unknown_headers.last()) {
+				(Some(first), Some(last))
+					if *first.parent_hash() == state.finalized_hash && last.hash() == proof.block =>
+					last,
+				_ => return Err(VerifyError::NotDescendant(index)),
+			};
+
+		state.finalized_number = match justification.target() {
+			Some((number, hash)) if hash == proof.block => number,
+			Some(_) => {
+				let err = ClientError::BadJustification(
+					"justification target does not match the justified block".into(),
+				);
+				return Err(VerifyError::InvalidFragment(index, err));
+			},
+			None => *justified_header.number(),
+		};
+		state.finalized_hash = proof.block;
+		enacted_change = match proof.signalled_set_change(&Default::default()) {
+			Some(ProofSetChange::Scheduled(change)) | Some(ProofSetChange::Forced(_, change)) => {
+				state.set_id += 1;
+				state.authorities = change.next_authorities;
+				state.rolling_authority_hash = rolling_authority_hash(
+					state.rolling_authority_hash,
+					state.set_id,
+					&state.authorities,
+				);
+				true
+			},
+			None => false,
+		};
+	}
+
+	Ok(state)
+}
+
+/// Verify the archive fragment at the given index against the current authority set, returning
+/// the authority set it enacts.
+fn verify_archive_fragment<Block: BlockT, J>(
+	index: usize,
+	(set_id, authorities): (SetId, sp_finality_grandpa::AuthorityList),
+	proof: DetailedFinalityProof<Block::Header>,
+) -> Result<(SetId, sp_finality_grandpa::AuthorityList), VerifyError>
+where
+	J: ProvableJustification<Block::Header>,
+{
+	ensure_fragment_follows_set(index, set_id, proof.requires_set_id)?;
+
+	let (proof, _) = check_justification::<Block::Header, J>(
+		set_id,
+		authorities,
+		proof.proof,
+		&Default::default(),
+	)
+	.map_err(|err| VerifyError::InvalidFragment(index, err))?;
+
+	let next_authorities = proof
+		.unknown_headers
+		.last()
+		.filter(|header| header.hash() == proof.block)
+		.and_then(|header| crate::import::find_scheduled_change::<Block>(header))
+		.ok_or(VerifyError::MissingAuthoritySetChange(index))?
+		.next_authorities;
+
+	Ok((set_id + 1, next_authorities))
+}
+
+/// Read the finality proofs of an archive written by [`FinalityProofProvider::export_archive`].
+///
+/// The proofs are returned in order, each of them can be checked against the authority set
+/// given by its `requires_set_id`.
+pub fn import_archive<Header: HeaderT>(
+	mut reader: impl std::io::Read,
+) -> Result<Vec<DetailedFinalityProof<Header>>, FinalityProofError> {
+	let mut proofs = Vec::new();
+	while let Some(proof) = read_archive_entry(&mut reader)? {
+		proofs.push(proof);
+	}
+
+	Ok(proofs)
+}
+
+/// Read the next finality proof of an archive, returning `None` at the end of the archive.
+fn read_archive_entry<Header: HeaderT>(
+	reader: &mut impl std::io::Read,
+) -> Result<Option<DetailedFinalityProof<Header>>, FinalityProofError> {
+	use std::io::Read;
+
+	let mut len = [0u8; 4];
+	match reader.read_exact(&mut len) {
+		Ok(()) => {},
+		Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+		Err(err) => return Err(err.into()),
+	}
+
+	// Grow the buffer as the proof is read rather than trusting the length prefix, so that a
+	// corrupted prefix can't trigger a huge allocation.
+	let len = u32::from_le_bytes(len) as u64;
+	let mut proof = Vec::new();
+	reader.take(len).read_to_end(&mut proof)?;
+	if (proof.len() as u64) < len {
+		return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+	}
+
+	let proof = DetailedFinalityProof::decode(&mut &proof[..]).map_err(|_| {
+		ClientError::BadJustification("failed to decode archived finality proof".into())
+	})?;
+
+	Ok(Some(proof))
+}
+
+/// Configuration of the finality proof check.
+#[derive(Debug, Clone, Default)]
+pub struct FinalityProofCheckConfig {
+	/// Offset applied to the caller's set id before verifying the justification.
+	///
+	/// Some test networks reset their set id numbering, this allows checking the proofs they
+	/// produce against the authorities tracked under the caller's numbering. This is an interop
+	/// convenience for testing only and **must not be used in production**, since it changes the
+	/// set id that the justification signatures are checked against.
+	pub set_id_offset: i64,
+	/// Reject proofs whose justified block signals an authority set change with
+	/// [`ClientError::UnexpectedSetChange`], e.g. for bridges that only accept proofs within the
+	/// current set. Proofs that don't include the header of the justified block are rejected as
+	/// well, since the change can't be ruled out.
+	pub reject_set_changes: bool,
+	/// The maximum number of headers a proof may include. Larger proofs are rejected while
+	/// decoding, before any of their headers is decoded or validated.
+	pub max_headers: Option<usize>,
+	/// Verify the signatures of the justification in parallel, on the thread pool if the
+	/// `parallel` feature is enabled, see [`ProvableJustification::verify_parallel`].
+	pub verify_signatures_in_parallel: bool,
+	/// Parse the authority set changes signalled in header digests according to the layout of
+	/// older Substrate versions, which logged a bare [`ScheduledChange`] rather than a
+	/// [`ConsensusLog`], e.g. when bridging to a chain that hasn't been upgraded. Forced changes
+	/// are not supported by this layout.
+	pub legacy_digest: bool,
+	/// Reject proofs whose headers are inconsistent with a forced authority set change they
+	/// signal: its median last finalized block must precede the block S signalling it, and the
+	/// proof must not extend past the block S + delay enacting it.
+	pub validate_forced_changes: bool,
+	/// The maximum size of the encoded justification of a proof. Proofs with larger
+	/// justifications are rejected before the justification is decoded.
+	pub max_justification_bytes: Option<usize>,
+	/// Reject justifications in which an authority signed several precommits, see
+	/// [`ProvableJustification::ensure_unique_signers`]. Otherwise the weight of such an
+	/// authority is counted once, which is how the justifications imported with blocks are
+	/// checked, since they may include the precommits of an equivocating authority.
+	pub reject_duplicate_signers: bool,
+}
+
+impl FinalityProofCheckConfig {
+	/// The id of the authority set the justification of a proof is verified against, given the
+	/// caller's `current_set_id`, i.e. with the configured [`Self::set_id_offset`] applied. This is
+	/// useful for callers tracking the set history.
+	pub fn verified_set_id(&self, current_set_id: SetId) -> ClientResult<SetId> {
+		apply_set_id_offset(current_set_id, self.set_id_offset)
+	}
+}
+
+/// Check GRANDPA proof-of-finality for the given block.
+///
+/// Returns the vector of headers that MUST be validated + imported
+/// AND if at least one of those headers is invalid, all other MUST be considered invalid.
+/// Proofs whose headers do not form a chain are rejected with
+/// [`ClientError::BrokenHeaderChain`].
+pub fn check_finality_proof<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	check_finality_proof_with_config::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		&Default::default(),
+	)
+}
+
+/// Check GRANDPA proof-of-finality for the given block using the given configuration.
+///
+/// See [`check_finality_proof`] for details.
+pub fn check_finality_proof_with_config<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	config: &FinalityProofCheckConfig,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	check_finality_proof_and_justification::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		config,
+	)
+	.map(|(proof, _)| proof)
+}
+
+/// Check GRANDPA proof-of-finality for the given block, additionally ensuring that the justified
+/// block F is on the canonical chain known to the caller, e.g. to detect being eclipsed by peers
+/// following a fork.
+///
+/// The `canonical_oracle` tells whether the block with the given hash is on the caller's
+/// canonical chain, proofs of other blocks are rejected with [`ClientError::NonCanonical`]. See
+/// [`check_finality_proof`] for details.
+pub fn check_finality_proof_canonical<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	canonical_oracle: impl Fn(&Header::Hash) -> bool,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	let proof =
+		check_finality_proof::<Header, J>(current_set_id, current_authorities, remote_proof)?;
+
+	if !canonical_oracle(&proof.block) {
+		return Err(ClientError::NonCanonical(format!("{:?}", proof.block)));
+	}
+
+	Ok(proof)
+}
+
+/// An authority of a set, possibly carrying metadata which is irrelevant to finality proofs
+/// (e.g. its network identity) or identified by the raw bytes of its ed25519 public key, see
+/// [`authority_list`].
+pub trait WeightedAuthority {
+	/// The id and voting weight of the authority.
+	fn id_and_weight(&self) -> (AuthorityId, u64);
+}
+
+impl WeightedAuthority for (AuthorityId, u64) {
+	fn id_and_weight(&self) -> (AuthorityId, u64) {
+		self.clone()
+	}
+}
+
+impl<Meta> WeightedAuthority for (AuthorityId, u64, Meta) {
+	fn id_and_weight(&self) -> (AuthorityId, u64) {
+		(self.0.clone(), self.1)
+	}
+}
+
+impl WeightedAuthority for ([u8; 32], u64) {
+	fn id_and_weight(&self) -> (AuthorityId, u64) {
+		(<AuthorityId as sp_core::crypto::Public>::from_slice(&self.0), self.1)
+	}
+}
+
+impl<A: WeightedAuthority> WeightedAuthority for &A {
+	fn id_and_weight(&self) -> (AuthorityId, u64) {
+		(*self).id_and_weight()
+	}
+}
+
+/// Collect the given authorities into the authority list finality proofs are checked against,
+/// e.g. while streaming them from storage, dropping any metadata they carry.
+pub fn authority_list<A: WeightedAuthority>(
+	authorities: impl IntoIterator<Item = A>,
+) -> sp_finality_grandpa::AuthorityList {
+	authorities.into_iter().map(|authority| authority.id_and_weight()).collect()
+}
+
+/// Why a finality proof was accepted or rejected, see [`VerificationReport`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReasonCode {
+	/// The proof is valid.
+	Accepted,
+	/// The proof or its justification could not be decoded.
+	DecodeFailure,
+	/// The headers of the proof don't form a chain.
+	BrokenHeaderChain,
+	/// The authority set to check the proof against is invalid.
+	InvalidAuthorities,
+	/// The justification is invalid, e.g. one of its signatures.
+	InvalidJustification,
+	/// The proof was rejected for another reason.
+	Other,
+}
+
+/// The outcome of checking a finality proof, see [`check_finality_proof_report`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+	/// Whether the proof is valid.
+	pub accepted: bool,
+	/// Why the proof was accepted or rejected.
+	pub reason: ReasonCode,
+	/// A human readable description of the rejection, empty if the proof was accepted.
+	pub details: String,
+}
+
+impl VerificationReport {
+	fn rejected(reason: ReasonCode, details: impl ToString) -> Self {
+		VerificationReport { accepted: false, reason, details: details.to_string() }
+	}
+}
+
+/// Check GRANDPA proof-of-finality for the given block like [`check_finality_proof`], returning
+/// a [`VerificationReport`] with the reason of a rejection instead of an error, e.g. for
+/// dashboards.
+pub fn check_finality_proof_report<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+) -> VerificationReport
+where
+	J: ProvableJustification<Header>,
+{
+	let proof = match FinalityProof::<Header>::decode(&mut &remote_proof[..]) {
+		Ok(proof) => proof,
+		Err(err) => return VerificationReport::rejected(ReasonCode::DecodeFailure, err),
+	};
+
+	match check_justification::<Header, J>(
+		current_set_id,
+		current_authorities,
+		proof,
+		&Default::default(),
+	) {
+		Ok(_) => VerificationReport {
+			accepted: true,
+			reason: ReasonCode::Accepted,
+			details: String::new(),
+		},
+		Err(err) => {
+			let reason = match err {
+				ClientError::JustificationDecode => ReasonCode::DecodeFailure,
+				ClientError::BadJustification(_) => ReasonCode::InvalidJustification,
+				ClientError::BrokenHeaderChain => ReasonCode::BrokenHeaderChain,
+				ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet) =>
+					ReasonCode::InvalidAuthorities,
+				_ => ReasonCode::Other,
+			};
+			VerificationReport::rejected(reason, err)
+		},
+	}
+}
+
+/// The cost of verifying a finality proof, see [`estimate_verification_cost`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct VerificationCost {
+	/// The number of precommit signatures of the justification.
+	pub signatures: usize,
+	/// The number of headers included in the proof.
+	pub headers: usize,
+	/// The number of headers proving the ancestry of the precommit targets.
+	pub ancestry_headers: usize,
+}
+
+/// Estimate the cost of verifying the given GRANDPA proof-of-finality, e.g. to schedule its
+/// verification, by decoding it without checking any of its signatures.
+pub fn estimate_verification_cost<Block: BlockT>(
+	remote_proof: &[u8],
+) -> Result<VerificationCost, parity_scale_codec::Error> {
+	let proof = FinalityProof::<Block::Header>::decode(&mut &remote_proof[..])?;
+	let justification = GrandpaJustification::<Block>::decode(&mut &proof.justification[..])?;
+
+	Ok(VerificationCost {
+		signatures: justification.commit.precommits.len(),
+		headers: proof.unknown_headers.len(),
+		ancestry_headers: justification.votes_ancestries.len(),
+	})
+}
+
+/// Check GRANDPA proof-of-finality for the given block against the authorities of the given
+/// session, for callers that track sessions rather than GRANDPA authority sets.
+///
+/// The `resolver` maps the session index to the id and authorities of the GRANDPA authority set
+/// active during that session.
+pub fn check_finality_proof_for_session<Header: HeaderT, J>(
+	session_index: u32,
+	resolver: impl FnOnce(u32) -> Option<(SetId, sp_finality_grandpa::AuthorityList)>,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	let (set_id, authorities) = resolver(session_index).ok_or_else(|| {
+		ClientError::BadJustification(format!("unknown authorities of session {}", session_index))
+	})?;
+
+	check_finality_proof::<Header, J>(set_id, authorities, remote_proof)
+}
+
+/// Check an encoded [`DetailedFinalityProof`], e.g. a fragment of an archive, against the
+/// authorities of the set it requires, fetched asynchronously (e.g. from the network) with the
+/// given `fetch_authorities`.
+///
+/// See [`check_finality_proof`] for details.
+pub async fn check_finality_proof_async<Header: HeaderT, J, F, Fut>(
+	remote_proof: Vec<u8>,
+	fetch_authorities: F,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+	F: Fn(SetId) -> Fut,
+	Fut: Future<Output = Option<sp_finality_grandpa::AuthorityList>>,
+{
+	let proof = DetailedFinalityProof::<Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	let set_id = proof.requires_set_id;
+	let authorities = fetch_authorities(set_id).await.ok_or_else(|| {
+		ClientError::BadJustification(format!("unknown authorities of set {}", set_id))
+	})?;
+
+	check_justification::<Header, J>(set_id, authorities, proof.proof, &Default::default())
+		.map(|(proof, _)| proof)
+}
+
+/// The commitment to an authority set, i.e. the blake2-256 hash of the encoded set id and
+/// authorities, as stored on a counterparty chain.
+pub fn authority_set_commitment(
+	set_id: SetId,
+	authorities: &sp_finality_grandpa::AuthorityList,
+) -> [u8; 32] {
+	blake2_256(&(set_id, authorities).encode())
+}
+
+/// Check GRANDPA proof-of-finality for the given block against an authority set committed to on
+/// another chain, e.g. for IBC-style verification.
+///
+/// The claimed set id and authorities must match the committed `authority_set_root` (see
+/// [`authority_set_commitment`]) before they are used to verify the justification.
+pub fn check_finality_proof_with_authority_root<Header: HeaderT, J>(
+	authority_set_root: [u8; 32],
+	set_id: SetId,
+	claimed_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	if authority_set_commitment(set_id, &claimed_authorities) != authority_set_root {
+		return Err(ClientError::BadJustification(
+			"authorities don't match the committed authority set root".into(),
+		));
+	}
+
+	check_finality_proof::<Header, J>(set_id, claimed_authorities, remote_proof)
+}
+
+/// Check GRANDPA proof-of-finality for the given block against the authorities stored in the
+/// runtime of the local node, detecting mismatches between the set that signed the proof and the
+/// node's own view of the chain.
+///
+/// The authorities are read at the parent of the justified block F, i.e. the set that was active
+/// when F was authored, since an authority set change enacted at F is already visible in its
+/// state. The proof must therefore include the header of F.
+pub fn verify_against_local_runtime<Block: BlockT, C, J>(
+	runtime_api: &C,
+	set_id: SetId,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityProof<Block::Header>>
+where
+	C: ProvideRuntimeApi<Block>,
+	C::Api: GrandpaApi<Block>,
+	J: ProvableJustification<Block::Header>,
+{
+	let proof = FinalityProof::<Block::Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	let parent_hash = proof
+		.unknown_headers
+		.last()
+		.filter(|header| header.hash() == proof.block)
+		.map(|header| *header.parent_hash())
+		.ok_or_else(|| {
+			ClientError::BadJustification(
+				"the proof doesn't include the header of the justified block".into(),
+			)
+		})?;
+
+	let authorities = runtime_api.runtime_api().grandpa_authorities(&BlockId::Hash(parent_hash))?;
+
+	check_justification::<Block::Header, J>(set_id, authorities, proof, &Default::default())
+		.map(|(proof, _)| proof)
+		.map_err(|err| {
+			ClientError::BadJustification(format!(
+				"proof doesn't match the authorities of the local runtime: {}",
+				err,
+			))
+		})
+}
+
+/// Check the given GRANDPA proofs-of-finality in turn, e.g. the responses of several peers to the
+/// same request, returning the first valid one.
+///
+/// The proofs are checked lazily, i.e. the iterator isn't advanced past the first valid proof.
+pub fn first_valid<Header: HeaderT, J>(
+	proofs: impl IntoIterator<Item = Vec<u8>>,
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+) -> Option<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	proofs.into_iter().find_map(|proof| {
+		check_finality_proof::<Header, J>(current_set_id, current_authorities.clone(), proof)
+			.map_err(|err| trace!(target: "afg", "Discarding invalid finality proof: {}", err))
+			.ok()
+	})
+}
+
+/// The work [`verify_batch`] may spend verifying a batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerificationBudget {
+	/// The maximum number of signature checks, unlimited if `None`.
+	///
+	/// Each item is charged the number of authorities it is checked against, the most signatures
+	/// its justification can carry.
+	pub max_signature_checks: Option<usize>,
+}
+
+/// Check many independent GRANDPA proofs-of-finality, each against its own authority set, e.g.
+/// the proofs of several source chains tracked by a relayer.
+///
+/// The proofs are checked in parallel if the `parallel` feature is enabled. The result of each
+/// item is returned at its index, an invalid proof being reported as
+/// [`VerifyError::InvalidFragment`]. Once the next item doesn't fit in the remaining budget it
+/// and all the following items are reported as [`VerifyError::Deferred`] without being checked.
+pub fn verify_batch<Header: HeaderT, J>(
+	mut items: Vec<(Vec<u8>, SetId, sp_finality_grandpa::AuthorityList)>,
+	budget: VerificationBudget,
+) -> Vec<Result<FinalityProof<Header>, VerifyError>>
+where
+	J: ProvableJustification<Header>,
+{
+	let mut remaining = budget.max_signature_checks.unwrap_or(usize::MAX);
+	let within_budget = items
+		.iter()
+		.take_while(|(_, _, authorities)| match remaining.checked_sub(authorities.len()) {
+			Some(left) => {
+				remaining = left;
+				true
+			},
+			None => false,
+		})
+		.count();
+
+	let check = |(index, (proof, set_id, authorities))| {
+		check_finality_proof::<Header, J>(set_id, authorities, proof)
+			.map_err(|err| VerifyError::InvalidFragment(index, err))
+	};
+
+	let total = items.len();
+	items.truncate(within_budget);
+
+	#[cfg(feature = "parallel")]
+	let mut results = {
+		use rayon::prelude::*;
+		items.into_par_iter().enumerate().map(check).collect::<Vec<_>>()
+	};
+
+	#[cfg(not(feature = "parallel"))]
+	let mut results = items.into_iter().enumerate().map(check).collect::<Vec<_>>();
+
+	results.extend((within_budget..total).map(|index| Err(VerifyError::Deferred(index))));
+	results
+}
+
+/// Check a GRANDPA proof-of-finality for the given block produced by
+/// [`FinalityProofProvider::prove_finality_sparse`], splicing the headers known to the caller
+/// into it.
+///
+/// The headers (B; F] of the proof and of `known_headers` must together form a chain. Returns the
+/// proof with all the headers, see [`check_finality_proof`] for details.
+pub fn check_sparse_finality_proof<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	block: Header::Number,
+	known_headers: &BTreeMap<Header::Number, Header>,
+	remote_proof: Vec<u8>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	let mut proof = FinalityProof::<Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	let mut headers = proof
+		.unknown_headers
+		.drain(..)
+		.map(|header| (*header.number(), header))
+		.collect::<BTreeMap<_, _>>();
+	let justified_number = headers
+		.values()
+		.chain(known_headers.values())
+		.find(|header| header.hash() == proof.block)
+		.map(|header| *header.number())
+		.ok_or_else(|| {
+			ClientError::BadJustification("header of the justified block is unknown".into())
+		})?;
+
+	let mut number = block + One::one();
+	while number <= justified_number {
+		let header = headers
+			.remove(&number)
+			.or_else(|| known_headers.get(&number).cloned())
+			.ok_or_else(|| {
+				ClientError::BadJustification(format!("missing header #{}", number))
+			})?;
+
+		if let Some(previous) = proof.unknown_headers.last() {
+			if *header.parent_hash() != previous.hash() {
+				return Err(ClientError::BadJustification(
+					"finality proof headers do not form a chain".into(),
+				));
+			}
+		}
+
+		proof.unknown_headers.push(header);
+		number = number + One::one();
+	}
+
+	check_justification::<Header, J>(
+		current_set_id,
+		current_authorities,
+		proof,
+		&Default::default(),
+	)
+	.map(|(proof, _)| proof)
+}
+
+/// Check a GRANDPA proof-of-finality in the [`FinalityProofV2`] format, resolving a referenced
+/// justification through the given `resolver`, which is queried by the justification hash.
+///
+/// If the proof declares a threshold, it must be at least the default supermajority threshold of
+/// the authority set and at most its total weight, and the justification must be signed by
+/// authorities with at least that total weight.
+///
+/// Returns the proof with the justification inlined, see [`check_finality_proof`] for details.
+/// Proofs carrying [`ProofHeaders::HashOnly`] headers are rejected, see
+/// [`check_finality_proof_v2_with_header_store`].
+pub fn check_finality_proof_v2<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	resolver: impl Fn(&[u8; 32]) -> Option<Vec<u8>>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	check_finality_proof_v2_with_header_store::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		resolver,
+		|_| None,
+	)
+}
+
+/// Check a GRANDPA proof-of-finality in the [`FinalityProofV2`] format like
+/// [`check_finality_proof_v2`], resolving [`ProofHeaders::HashOnly`] headers through the given
+/// `header_store`.
+///
+/// The resolved headers must form a chain, each being the parent of the next one, and are
+/// returned in the proof with the justification inlined.
+pub fn check_finality_proof_v2_with_header_store<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	resolver: impl Fn(&[u8; 32]) -> Option<Vec<u8>>,
+	header_store: impl Fn(&Header::Hash) -> Option<Header>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	check_finality_proof_v2_inner::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		resolver,
+		header_store,
+		None,
+	)
+}
+
+/// Check a GRANDPA proof-of-finality in the [`FinalityProofV2`] format like
+/// [`check_finality_proof_v2`], rejecting it unless it carries the given genesis hash of the
+/// chain the caller follows. See [`FinalityProofConfig::embed_genesis_hash`].
+pub fn check_finality_proof_v2_for_chain<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	resolver: impl Fn(&[u8; 32]) -> Option<Vec<u8>>,
+	genesis_hash: Header::Hash,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	check_finality_proof_v2_inner::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		resolver,
+		|_| None,
+		Some(genesis_hash),
+	)
+}
+
+fn check_finality_proof_v2_inner<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	resolver: impl Fn(&[u8; 32]) -> Option<Vec<u8>>,
+	header_store: impl Fn(&Header::Hash) -> Option<Header>,
+	expected_genesis_hash: Option<Header::Hash>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	let proof = FinalityProofV2::<Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	if let Some(expected_genesis_hash) = expected_genesis_hash {
+		if proof.genesis_hash != Some(expected_genesis_hash) {
+			return Err(ClientError::BadJustification(format!(
+				"finality proof of chain {:?} while expecting chain {:?}",
+				proof.genesis_hash,
+				expected_genesis_hash,
+			)));
+		}
+	}
+
+	if let Some(server_version) = proof.server_version.as_ref().filter(|v| *v != SERVER_VERSION) {
+		trace!(
+			target: "afg",
+			"Checking finality proof served by version {} while we are running version {}.",
+			server_version,
+			SERVER_VERSION,
+		);
+	}
+
+	let justification = match proof.justification {
+		ProofJustification::Inline(justification) => justification,
+		ProofJustification::JustificationRef(hash) => resolver(&hash)
+			.filter(|justification| blake2_256(justification) == hash)
+			.ok_or_else(|| {
+				ClientError::BadJustification("failed to resolve referenced justification".into())
+			})?,
+	};
+
+	let unknown_headers = match proof.unknown_headers {
+		ProofHeaders::Full(headers) => headers,
+		ProofHeaders::HashOnly(hashes) => resolve_header_chain(&hashes, header_store)?,
+	};
+
+	if let Some(set_change) = proof.set_change {
+		let signalled = unknown_headers
+			.last()
+			.filter(|header| header.hash() == proof.block)
+			.and_then(find_set_change);
+		if signalled != Some(set_change) {
+			return Err(ClientError::BadJustification(
+				"embedded set change doesn't match the digest of the justified block".into(),
+			));
+		}
+	}
+
+	let threshold = proof.threshold;
+	let proof = FinalityProof { block: proof.block, justification, unknown_headers };
+
+	let (proof, justification) = check_justification::<Header, J>(
+		current_set_id,
+		current_authorities.clone(),
+		proof,
+		&Default::default(),
+	)?;
+
+	if let Some(threshold) = threshold {
+		let total_weight = current_authorities.iter().map(|(_, weight)| *weight).sum::<u64>();
+		if threshold < supermajority_threshold(total_weight) || threshold > total_weight {
+			return Err(ClientError::BadJustification(
+				"finality threshold out of bounds".into(),
+			));
+		}
+
+		let signed_weight = justification.signed_weight(&current_authorities).ok_or_else(|| {
+			ClientError::BadJustification("justification does not expose its signers".into())
+		})?;
+		if signed_weight < threshold {
+			return Err(ClientError::BadJustification(
+				"justification does not meet the finality threshold".into(),
+			));
+		}
+	}
+
+	Ok(proof)
+}
+
+/// Resolve the headers with the given hashes, checking that each is the parent of the next one.
+fn resolve_header_chain<Header: HeaderT>(
+	hashes: &[Header::Hash],
+	header_store: impl Fn(&Header::Hash) -> Option<Header>,
+) -> ClientResult<Vec<Header>> {
+	let mut headers: Vec<Header> = Vec::with_capacity(hashes.len());
+	for hash in hashes {
+		let header = header_store(hash).filter(|header| header.hash() == *hash).ok_or_else(|| {
+			ClientError::BadJustification(format!("failed to resolve header {:?}", hash))
+		})?;
+
+		if let Some(previous) = headers.last() {
+			if *header.parent_hash() != previous.hash() {
+				return Err(ClientError::BadJustification(
+					"finality proof headers do not form a chain".into(),
+				));
+			}
+		}
+
+		headers.push(header);
+	}
+
+	Ok(headers)
+}
+
+/// The minimum weight of a supermajority of the given total weight, i.e. the weight of the
+/// authorities required to finalize a block by default.
+fn supermajority_threshold(total_weight: u64) -> u64 {
+	let faulty = total_weight.saturating_sub(1) / 3;
+	total_weight - faulty
+}
+
+/// The outcome of checking a finality proof against the caller's view of the chain.
+#[derive(Debug)]
+pub enum FinalityProofValidity<Header: HeaderT> {
+	/// The proof is valid and finalizes a block above the caller's last known finalized block.
+	Valid(FinalityProof<Header>),
+	/// The proof is valid but does not finalize anything above the caller's last known finalized
+	/// block, e.g. because it has been superseded by a newer finalization.
+	ValidButStale(FinalityProof<Header>),
+	/// The proof is invalid.
+	Invalid(ClientError),
+}
+
+/// Check GRANDPA proof-of-finality for the given block, taking into account the number of the
+/// last block the caller already knows to be finalized.
+///
+/// If the justification does not expose the number of the block it finalizes the proof can't be
+/// deemed stale and is reported as [`FinalityProofValidity::Valid`].
+pub fn check_finality_proof_validity<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	known_finalized: Header::Number,
+) -> FinalityProofValidity<Header>
+where
+	J: ProvableJustification<Header>,
+{
+	match check_finality_proof_and_justification::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		&Default::default(),
+	) {
+		Ok((proof, justification)) => match justification.target() {
+			Some((number, _)) if number <= known_finalized =>
+				FinalityProofValidity::ValidButStale(proof),
+			_ => FinalityProofValidity::Valid(proof),
+		},
+		Err(err) => FinalityProofValidity::Invalid(err),
+	}
+}
+
+/// The authorities added and removed by an authority set change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthoritySetDelta {
+	/// The authorities of the enacted set that are not in the prior set.
+	pub added: sp_finality_grandpa::AuthorityList,
+	/// The authorities of the prior set that are not in the enacted set.
+	pub removed: sp_finality_grandpa::AuthorityList,
+}
+
+impl AuthoritySetDelta {
+	/// Compute the difference between the prior and the enacted authority lists. Authorities are
+	/// compared by id, the weights are those of the list they are taken from.
+	pub fn between(
+		prior: &sp_finality_grandpa::AuthorityList,
+		enacted: &sp_finality_grandpa::AuthorityList,
+	) -> Self {
+		fn missing_from(
+			list: &sp_finality_grandpa::AuthorityList,
+			other: &sp_finality_grandpa::AuthorityList,
+		) -> sp_finality_grandpa::AuthorityList {
+			list.iter()
+				.filter(|(id, _)| !other.iter().any(|(other_id, _)| other_id == id))
+				.cloned()
+				.collect()
+		}
+
+		AuthoritySetDelta {
+			added: missing_from(enacted, prior),
+			removed: missing_from(prior, enacted),
+		}
+	}
+}
+
+/// Check GRANDPA proof-of-finality for the given block, returning the authorities added and
+/// removed by the authority set change F signals, if any, with respect to the authorities the
+/// proof was checked against.
+pub fn check_finality_proof_with_delta<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+) -> ClientResult<(FinalityProof<Header>, Option<AuthoritySetDelta>)>
+where
+	J: ProvableJustification<Header>,
+{
+	let (proof, _) = check_finality_proof_and_justification::<Header, J>(
+		current_set_id,
+		current_authorities.clone(),
+		remote_proof,
+		&Default::default(),
+	)?;
+
+	let delta = match proof.signalled_set_change(&Default::default()) {
+		Some(ProofSetChange::Scheduled(change)) | Some(ProofSetChange::Forced(_, change)) =>
+			Some(AuthoritySetDelta::between(&current_authorities, &change.next_authorities)),
+		None => None,
+	};
+
+	Ok((proof, delta))
+}
+
+/// Check GRANDPA proof-of-finality for the given block, returning the authorities whose
+/// precommits in the justification were counted towards the finality threshold, e.g. for
+/// auditing.
+///
+/// Fails if the justification doesn't expose its signers.
+pub fn check_finality_proof_with_signers<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+) -> ClientResult<(FinalityProof<Header>, Vec<AuthorityId>)>
+where
+	J: ProvableJustification<Header>,
+{
+	let (proof, justification) = check_finality_proof_and_justification::<Header, J>(
+		current_set_id,
+		current_authorities.clone(),
+		remote_proof,
+		&Default::default(),
+	)?;
+
+	let signers = justification.counted_signers(&current_authorities).ok_or_else(|| {
+		ClientError::BadJustification("justification does not expose its signers".into())
+	})?;
+
+	Ok((proof, signers))
+}
+
+/// Check a minimal GRANDPA proof-of-finality that carries no headers, for callers that already
+/// know the chain up to some tip and are given the header of the justified block F separately.
+///
+/// The justified header must match the proof, and it must chain back to the caller's `known_tip`
+/// through the headers returned by the `ancestry` oracle (which is queried by hash for the
+/// headers in the range (tip; F)).
+pub fn check_finality_proof_with_justified_header<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	justified_header: &Header,
+	known_tip: Header::Hash,
+	ancestry: impl Fn(&Header::Hash) -> Option<Header>,
+) -> ClientResult<FinalityProof<Header>>
+where
+	J: ProvableJustification<Header>,
+{
+	let (proof, justification) = check_finality_proof_and_justification::<Header, J>(
+		current_set_id,
+		current_authorities,
+		remote_proof,
+		&Default::default(),
+	)?;
+
+	if !proof.unknown_headers.is_empty() {
+		return Err(ClientError::BadJustification(
+			"expected a finality proof without headers".into(),
+		));
+	}
+
+	if proof.block != justified_header.hash() {
+		return Err(ClientError::BadJustification(
+			"justified header does not match finality proof".into(),
+		));
+	}
+
+	if let Some(target) = justification.target() {
+		if target != (*justified_header.number(), justified_header.hash()) {
+			return Err(ClientError::BadJustification(
+				"justification target does not match justified header".into(),
+			));
+		}
+	}
+
+	if justified_header.hash() == known_tip {
+		return Ok(proof);
+	}
+
+	let mut current_number = *justified_header.number();
+	let mut parent_hash = *justified_header.parent_hash();
+	while parent_hash != known_tip {
+		let parent = ancestry(&parent_hash)
+			.filter(|parent| *parent.number() + One::one() == current_number)
+			.ok_or_else(|| {
+				ClientError::BadJustification(
+					"justified header does not chain to the known tip".into(),
+				)
+			})?;
+
+		current_number = *parent.number();
+		parent_hash = *parent.parent_hash();
+	}
+
+	Ok(proof)
+}
+
+/// The state of a [`PartialVerifier`].
+#[derive(Debug)]
+pub enum PartialVerification {
+	/// Some of the ancestry between the justified block and the known tip is still missing.
+	Pending,
+	/// The justified block chains to the known tip, the proof is valid.
+	Valid,
+	/// The proof can never become valid, e.g. because the justified block is not a descendant of
+	/// the known tip.
+	Invalid(ClientError),
+}
+
+/// Verifies a GRANDPA proof-of-finality incrementally, for clients that sync headers
+/// independently of the proof.
+///
+/// The justification is verified up front, while checking that the justified block chains to the
+/// caller's known tip is deferred until the needed ancestry is available in the header store.
+pub struct PartialVerifier<Header: HeaderT, J> {
+	proof: FinalityProof<Header>,
+	justification: J,
+	known_tip: (Header::Number, Header::Hash),
+}
+
+impl<Header: HeaderT, J> PartialVerifier<Header, J>
+where
+	J: ProvableJustification<Header>,
+{
+	/// Decode the given proof and verify its justification, failing if it isn't valid for the
+	/// given authority set. `known_tip` is the number and hash of the latest block known to the
+	/// caller, which the justified block must descend from.
+	pub fn new(
+		current_set_id: u64,
+		current_authorities: sp_finality_grandpa::AuthorityList,
+		remote_proof: Vec<u8>,
+		known_tip: (Header::Number, Header::Hash),
+	) -> ClientResult<Self> {
+		let (proof, justification) = check_finality_proof_and_justification::<Header, J>(
+			current_set_id,
+			current_authorities,
+			remote_proof,
+			&Default::default(),
+		)?;
+
+		Ok(PartialVerifier { proof, justification, known_tip })
+	}
+
+	/// The finality proof being verified.
+	pub fn proof(&self) -> &FinalityProof<Header> {
+		&self.proof
+	}
+
+	/// Try to complete the verification by walking back from the justified block to the known
+	/// tip through the headers currently available in the header store, which is queried by hash.
+	pub fn try_complete(
+		&self,
+		header_store: impl Fn(&Header::Hash) -> Option<Header>,
+	) -> PartialVerification {
+		let (tip_number, tip_hash) = self.known_tip;
+		let invalid = |msg: &str| {
+			PartialVerification::Invalid(ClientError::BadJustification(msg.into()))
+		};
+
+		let mut expected_number = self.justification.target().map(|(number, _)| number);
+		let mut current_hash = self.proof.block;
+		loop {
+			if current_hash == tip_hash {
+				return PartialVerification::Valid;
+			}
+
+			let header = match header_store(&current_hash) {
+				Some(header) => header,
+				None => return PartialVerification::Pending,
+			};
+
+			if header.hash() != current_hash {
+				return invalid("header store returned a header with an unexpected hash");
+			}
+
+			if expected_number.map_or(false, |number| number != *header.number()) {
+				return invalid("justified header does not match finality proof");
+			}
+
+			if *header.number() <= tip_number {
+				return invalid("justified header does not chain to the known tip");
+			}
+
+			expected_number = Some(*header.number() - One::one());
+			current_hash = *header.parent_hash();
+		}
+	}
+}
+
+fn check_finality_proof_and_justification<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	remote_proof: Vec<u8>,
+	config: &FinalityProofCheckConfig,
+) -> ClientResult<(FinalityProof<Header>, J)>
+where
+	J: ProvableJustification<Header>,
+{
+	if let Some(max_headers) = config.max_headers {
+		ensure_max_headers::<Header>(&remote_proof, max_headers)?;
+	}
+
+	let proof = FinalityProof::<Header>::decode(&mut &remote_proof[..])
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	check_justification::<Header, J>(current_set_id, current_authorities, proof, config)
+}
+
+/// Ensure the encoded proof includes at most `max_headers` headers, by only decoding the length
+/// prefix of its headers.
+fn ensure_max_headers<Header: HeaderT>(
+	remote_proof: &[u8],
+	max_headers: usize,
+) -> ClientResult<()> {
+	let input = &mut &remote_proof[..];
+	let headers = Header::Hash::decode(input)
+		.and_then(|_| Vec::<u8>::decode(input))
+		.and_then(|_| Compact::<u32>::decode(input))
+		.map_err(|_| ClientError::BadJustification("failed to decode finality proof".into()))?;
+
+	if headers.0 as usize > max_headers {
+		return Err(ClientError::BadJustification(format!(
+			"finality proof includes {} headers, at most {} are allowed",
+			headers.0,
+			max_headers,
+		)));
+	}
+
+	Ok(())
+}
+
+fn check_justification<Header: HeaderT, J>(
+	current_set_id: u64,
+	current_authorities: sp_finality_grandpa::AuthorityList,
+	proof: FinalityProof<Header>,
+	config: &FinalityProofCheckConfig,
+) -> ClientResult<(FinalityProof<Header>, J)>
+where
+	J: ProvableJustification<Header>,
+{
+	let set_id = config.verified_set_id(current_set_id)?;
+
+	let is_chain = proof
+		.unknown_headers
+		.windows(2)
+		.all(|headers| *headers[1].parent_hash() == headers[0].hash());
+	if !is_chain {
+		return Err(ClientError::BrokenHeaderChain);
+	}
+
+	if let Some(max_justification_bytes) = config.max_justification_bytes {
+		if proof.justification.len() > max_justification_bytes {
+			return Err(ClientError::BadJustification(format!(
+				"justification of {} bytes exceeds the limit of {} bytes",
+				proof.justification.len(),
+				max_justification_bytes,
+			)));
+		}
+	}
+
+	let justification: J = Decode::decode(&mut &proof.justification[..])
+		.map_err(|_| ClientError::JustificationDecode)?;
+	// Voting starts at round 1, round 0 is the genesis round in which nothing is voted on. Block
+	// import doesn't check the round, only proofs are rejected.
+	if justification.round() == Some(0) {
+		return Err(ClientError::BadJustification(
+			"invalid round 0 in grandpa justification".into(),
+		));
+	}
+	if config.reject_duplicate_signers {
+		justification.ensure_unique_signers()?;
+	}
+	if config.verify_signatures_in_parallel {
+		justification.verify_parallel(set_id, &current_authorities)?;
+	} else {
+		justification.verify(set_id, &current_authorities)?;
+	}
+
+	// If the proof includes the header of the justified block, see
+	// `FinalityProofConfig::include_justified_header`, it is the last one and its number must
+	// match the target of the justification. Otherwise the headers stop below it.
+	if let (Some((target_number, _)), Some(last_header)) =
+		(justification.target(), proof.unknown_headers.last())
+	{
+		let consistent = if last_header.hash() == proof.block {
+			*last_header.number() == target_number
+		} else {
+			*last_header.number() < target_number
+		};
+		if !consistent {
+			return Err(ClientError::BadJustification(
+				"justification target number does not match the proof headers".into(),
+			));
+		}
+	}
+
+	if config.reject_set_changes {
+		let signals_change = proof
+			.unknown_headers
+			.last()
+			.filter(|header| header.hash() == proof.block)
+			.map(|header| find_set_change_with_layout(header, config.legacy_digest).is_some());
+
+		match signals_change {
+			Some(false) => {},
+			Some(true) => {
+				return Err(ClientError::UnexpectedSetChange);
+			},
+			None => {
+				return Err(ClientError::BadJustification(
+					"the proof doesn't include the header of the justified block".into(),
+				));
+			},
+		}
+	}
+
+	if config.validate_forced_changes {
+		ensure_forced_changes_consistent(&proof.unknown_headers)?;
+	}
+
+	Ok((proof, justification))
+}
+
+/// Ensure that the forced authority set changes signalled by the given chain of headers are
+/// consistent with the positions of the headers.
+///
+/// A forced change signalled at block S with the given delay is enacted at block S + delay, and
+/// its median last finalized block must precede S. Since the chain is finalized by the set the
+/// change replaces, it must not extend past the block enacting the change.
+fn ensure_forced_changes_consistent<Header: HeaderT>(headers: &[Header]) -> ClientResult<()> {
+	let id = OpaqueDigestItemId::Consensus(&GRANDPA_ENGINE_ID);
+	let last_number = match headers.last() {
+		Some(header) => *header.number(),
+		None => return Ok(()),
+	};
+
+	for header in headers {
+		let forced_change = header.digest().convert_first(|l| {
+			l.try_to(id).and_then(|log: ConsensusLog<Header::Number>| match log {
+				ConsensusLog::ForcedChange(median, change) => Some((median, change)),
+				_ => None,
+			})
+		});
+		let (median, change) = match forced_change {
+			Some(forced_change) => forced_change,
+			None => continue,
+		};
+
+		if median >= *header.number() {
+			return Err(ClientError::BadJustification(format!(
+				"forced change signalled at #{} with median last finalized block #{}",
+				header.number(),
+				median,
+			)));
+		}
+
+		let enacted_at = header.number().saturating_add(change.delay);
+		if last_number > enacted_at {
+			return Err(ClientError::BadJustification(format!(
+				"proof extends to #{} past the forced change signalled at #{} enacted at #{}",
+				last_number,
+				header.number(),
+				enacted_at,
+			)));
+		}
+	}
+
+	Ok(())
+}
+
+/// Find the standard or forced authority set change signalled by the given header.
+fn find_set_change<Header: HeaderT>(header: &Header) -> Option<ProofSetChange<Header::Number>> {
+	find_set_change_with_layout(header, false)
+}
+
+/// Find the authority set change signalled by the given header, parsing its digest according to
+/// the legacy layout if `legacy_digest` is set, see [`FinalityProofCheckConfig::legacy_digest`].
+fn find_set_change_with_layout<Header: HeaderT>(
+	header: &Header,
+	legacy_digest: bool,
+) -> Option<ProofSetChange<Header::Number>> {
+	let id = OpaqueDigestItemId::Consensus(&GRANDPA_ENGINE_ID);
+	if legacy_digest {
+		return header
+			.digest()
+			.convert_first(|l| l.try_to::<ScheduledChange<Header::Number>>(id))
+			.map(ProofSetChange::Scheduled);
+	}
+
+	let filter_log = |log: ConsensusLog<Header::Number>| match log {
+		ConsensusLog::ScheduledChange(change) => Some(ProofSetChange::Scheduled(change)),
+		ConsensusLog::ForcedChange(median, change) => Some(ProofSetChange::Forced(median, change)),
+		_ => None,
+	};
+
+	header.digest().convert_first(|l| l.try_to(id).and_then(filter_log))
+}
+
+fn apply_set_id_offset(set_id: u64, offset: i64) -> ClientResult<u64> {
+	let set_id = if offset >= 0 {
+		set_id.checked_add(offset as u64)
+	} else {
+		set_id.checked_sub(offset.wrapping_neg() as u64)
+	};
+
+	set_id.ok_or_else(|| ClientError::BadJustification("set id offset out of range".into()))
+}
+
+/// Justification used to prove block finality.
+pub trait ProvableJustification<Header: HeaderT>: Encode + Decode {
+	/// Verify justification with respect to authorities set and authorities set id.
+	fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()>;
+
+	/// Verify justification like [`Self::verify`], checking its signatures in parallel if
+	/// supported. It must accept exactly the justifications accepted by [`Self::verify`].
+	fn verify_parallel(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+		self.verify(set_id, authorities)
+	}
+
+	/// The number and hash of the block this justification finalizes, if it is known.
+	fn target(&self) -> Option<(Header::Number, Header::Hash)> {
+		None
+	}
+
+	/// The round in which this justification was made, if it is known.
+	fn round(&self) -> Option<u64> {
+		None
+	}
+
+	/// The total weight of the given authorities that signed this justification, if it is known.
+	fn signed_weight(&self, _authorities: &[(AuthorityId, u64)]) -> Option<u64> {
+		None
+	}
+
+	/// The given authorities that signed this justification, in the order of their signatures, if
+	/// they are known.
+	fn counted_signers(&self, _authorities: &[(AuthorityId, u64)]) -> Option<Vec<AuthorityId>> {
+		None
+	}
+
+	/// Ensure that no authority signed more than one of the precommits of this justification.
+	/// Justifications which don't expose their signers are rejected, since they can't be checked.
+	fn ensure_unique_signers(&self) -> ClientResult<()> {
+		Err(ClientError::BadJustification("the signers of the justification are unknown".into()))
+	}
+
+	/// Decode and verify justification.
+	fn decode_and_verify(
+		justification: &EncodedJustification,
+		set_id: u64,
+		authorities: &[(AuthorityId, u64)],
+	) -> ClientResult<Self> {
+		let justification =
+			Self::decode(&mut &**justification).map_err(|_| ClientError::JustificationDecode)?;
+		justification.verify(set_id, authorities)?;
+		Ok(justification)
+	}
+}
+
+impl<Block: BlockT> ProvableJustification<Block::Header> for GrandpaJustification<Block>
+where
+	NumberFor<Block>: BlockNumberOps,
+{
+	fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+		let authorities = VoterSet::new(authorities.iter().cloned()).ok_or(
+			ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet),
+		)?;
+
+		GrandpaJustification::verify_with_voter_set(self, set_id, &authorities)
+	}
+
+	fn verify_parallel(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+		let authorities = VoterSet::new(authorities.iter().cloned()).ok_or(
+			ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet),
+		)?;
+
+		GrandpaJustification::verify_with_voter_set_parallel(self, set_id, &authorities)
+	}
+
+	fn target(&self) -> Option<(NumberFor<Block>, Block::Hash)> {
+		Some(GrandpaJustification::target(self))
+	}
+
+	fn round(&self) -> Option<u64> {
+		Some(GrandpaJustification::round(self))
+	}
+
+	fn ensure_unique_signers(&self) -> ClientResult<()> {
+		GrandpaJustification::ensure_unique_signers(self)
+	}
+
+	fn signed_weight(&self, authorities: &[(AuthorityId, u64)]) -> Option<u64> {
+		Some(counted_authorities(self, authorities).into_iter().map(|(_, weight)| *weight).sum())
+	}
+
+	fn counted_signers(&self, authorities: &[(AuthorityId, u64)]) -> Option<Vec<AuthorityId>> {
+		Some(counted_authorities(self, authorities).into_iter().map(|(id, _)| id.clone()).collect())
+	}
+}
+
+/// The authorities that signed a precommit of the given justification, in the order of their first
+/// precommit. An authority that signed several precommits is only counted once.
+fn counted_authorities<'a, Block: BlockT>(
+	justification: &GrandpaJustification<Block>,
+	authorities: &'a [(AuthorityId, u64)],
+) -> Vec<&'a (AuthorityId, u64)> {
+	let authorities = authorities
+		.iter()
+		.map(|authority| (&authority.0, authority))
+		.collect::<HashMap<_, _>>();
+
+	let mut counted = HashSet::new();
+	justification.commit.precommits
+		.iter()
+		.filter_map(|signed| authorities.get(&signed.id).copied())
+		.filter(|authority| counted.insert(&authority.0))
+		.collect()
+}
+
+/// A signature scheme supporting the aggregation of the signatures of several authorities into a
+/// single one, e.g. BLS.
+#[cfg(feature = "aggregated-justifications")]
+pub trait AggregateSignatureScheme {
+	/// Verify that the given aggregated signature was made over the message by all the signers.
+	fn verify_aggregate(signers: &[AuthorityId], message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// A GRANDPA justification whose precommit signatures are aggregated into a single signature,
+/// with a bitfield identifying the signers.
+#[cfg(feature = "aggregated-justifications")]
+#[derive(Encode, Decode)]
+pub struct AggregatedJustification<Header: HeaderT, S> {
+	/// The round in which the target block was finalized.
+	pub round: u64,
+	/// The hash of the finalized block.
+	pub target_hash: Header::Hash,
+	/// The number of the finalized block.
+	pub target_number: Header::Number,
+	/// The aggregated signature of the signers over their precommit for the target block.
+	pub signature: Vec<u8>,
+	/// The signers, bit `i` (least significant bit first) being set if the `i`-th authority of
+	/// the set signed.
+	pub signers: Vec<u8>,
+	#[codec(skip)]
+	_scheme: std::marker::PhantomData<S>,
+}
+
+#[cfg(feature = "aggregated-justifications")]
+impl<Header: HeaderT, S> AggregatedJustification<Header, S> {
+	/// Create a new aggregated justification.
+	pub fn new(
+		round: u64,
+		target: (Header::Number, Header::Hash),
+		signature: Vec<u8>,
+		signers: Vec<u8>,
+	) -> Self {
+		AggregatedJustification {
+			round,
+			target_hash: target.1,
+			target_number: target.0,
+			signature,
+			signers,
+			_scheme: Default::default(),
+		}
+	}
+
+	fn signers<'a>(
+		&self,
+		authorities: &'a [(AuthorityId, u64)],
+	) -> ClientResult<Vec<&'a (AuthorityId, u64)>> {
+		let signed =
+			|i: usize| self.signers.get(i / 8).map_or(false, |byte| byte & (1 << (i % 8)) != 0);
+		if self.signers.len() != (authorities.len() + 7) / 8 ||
+			(authorities.len()..self.signers.len() * 8).any(signed)
+		{
+			return Err(ClientError::BadJustification(
+				"signers bitfield doesn't match the authority set".into(),
+			));
+		}
+
+		Ok(authorities.iter().enumerate().filter(|(i, _)| signed(*i)).map(|(_, a)| a).collect())
+	}
+}
+
+#[cfg(feature = "aggregated-justifications")]
+impl<Header: HeaderT, S> ProvableJustification<Header> for AggregatedJustification<Header, S>
+where
+	S: AggregateSignatureScheme,
+{
+	fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+		let signers = self.signers(authorities)?;
+
+		let total_weight = authorities.iter().map(|(_, weight)| *weight).sum();
+		let signed_weight: u64 = signers.iter().map(|(_, weight)| *weight).sum();
+		if signed_weight < supermajority_threshold(total_weight) {
+			return Err(ClientError::BadJustification(
+				"aggregated justification is not signed by a supermajority".into(),
+			));
+		}
+
+		let message = finality_grandpa::Message::Precommit(finality_grandpa::Precommit {
+			target_hash: self.target_hash,
+			target_number: self.target_number,
+		});
+		let payload = sp_finality_grandpa::localized_payload(self.round, set_id, &message);
+		let signers = signers.into_iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+		if !S::verify_aggregate(&signers, &payload, &self.signature) {
+			return Err(ClientError::BadJustification("invalid aggregated signature".into()));
+		}
+
+		Ok(())
+	}
+
+	fn target(&self) -> Option<(Header::Number, Header::Hash)> {
+		Some((self.target_number, self.target_hash))
+	}
+
+	fn signed_weight(&self, authorities: &[(AuthorityId, u64)]) -> Option<u64> {
+		self.signers(authorities)
+			.ok()
+			.map(|signers| signers.iter().map(|(_, weight)| *weight).sum())
+	}
+
+	fn counted_signers(&self, authorities: &[(AuthorityId, u64)]) -> Option<Vec<AuthorityId>> {
+		self.signers(authorities)
+			.ok()
+			.map(|signers| signers.into_iter().map(|(id, _)| id.clone()).collect())
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::authorities::{AuthoritySet, AuthoritySetChanges};
+	use crate::justification::tests::{keyring_authorities, signed_justification};
+	use fork_tree::ForkTree;
+	use sp_core::crypto::Public;
+	use sp_runtime::Justifications;
+	use sp_finality_grandpa::AuthorityList;
+	use sc_client_api::{AuxStore, NewBlockState};
+	use sc_client_api::in_mem::{Backend as InMemoryBackend, Blockchain as InMemoryBlockchain};
+	use std::collections::HashMap;
+	use std::sync::atomic::{AtomicU64, Ordering};
+	use substrate_test_runtime_client::runtime::{Block, Header, H256};
+
+	pub(crate) type FinalityProof = super::FinalityProof<Header>;
+
+	#[derive(Debug, PartialEq, Encode, Decode)]
+	pub struct TestJustification(pub (u64, AuthorityList), pub Vec<u8>);
+
+	impl ProvableJustification<Header> for TestJustification {
 		fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
 			if (self.0).0 != set_id || (self.0).1 != authorities {
 				return Err(ClientError::BadJustification("test".into()));
 			}
 
-			Ok(())
+			Ok(())
+		}
+	}
+
+	#[derive(Debug, PartialEq, Encode, Decode)]
+	pub struct TestWeightedJustification(TestJustification, u64);
+
+	impl ProvableJustification<Header> for TestWeightedJustification {
+		fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+			self.0.verify(set_id, authorities)
+		}
+
+		fn signed_weight(&self, _authorities: &[(AuthorityId, u64)]) -> Option<u64> {
+			Some(self.1)
+		}
+	}
+
+	#[derive(Debug, PartialEq, Encode, Decode)]
+	pub struct TestBlockJustification(TestJustification, u64, H256);
+
+	impl ProvableJustification<Header> for TestBlockJustification {
+		fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
+			self.0.verify(set_id, authorities)
+		}
+
+		fn target(&self) -> Option<(u64, H256)> {
+			Some((self.1, self.2))
+		}
+	}
+
+	pub(crate) fn header(number: u64) -> Header {
+		let parent_hash = match number {
+			0 => Default::default(),
+			_ => header(number - 1).hash(),
+		};
+		Header::new(
+			number,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			parent_hash,
+			Default::default(),
+		)
+	}
+
+	fn test_backend() -> Arc<InMemoryBackend<Block>> {
+		let backend = Arc::new(InMemoryBackend::<Block>::new());
+		insert_test_blocks(backend.blockchain());
+		backend
+	}
+
+	fn test_provider(
+		backend: Arc<InMemoryBackend<Block>>,
+		authority_set_changes: AuthoritySetChanges<u64>,
+	) -> FinalityProofProvider<InMemoryBackend<Block>, Block> {
+		let authority_set = AuthoritySet::new(
+			vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)],
+			0,
+			ForkTree::new(),
+			Vec::new(),
+			authority_set_changes,
+		)
+		.unwrap();
+
+		FinalityProofProvider::new(backend, Some(authority_set.into()))
+	}
+
+	/// Build authority set changes from the given set ids and last blocks of the sets.
+	fn set_changes(changes: &[(u64, u64)]) -> AuthoritySetChanges<u64> {
+		changes.to_vec().into()
+	}
+
+	/// Finalize the headers following the test blocks up to `last`, storing a GRANDPA
+	/// justification encoding the block number at the `justified` blocks.
+	fn finalize_test_blocks(blockchain: &InMemoryBlockchain<Block>, last: u64, justified: &[u64]) {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		for number in 4..=last {
+			let justifications = if justified.contains(&number) {
+				Some(Justifications::from((ID, vec![number as u8])))
+			} else {
+				None
+			};
+			blockchain
+				.insert(
+					header(number).hash(),
+					header(number),
+					justifications,
+					None,
+					NewBlockState::Final,
+				)
+				.unwrap();
+		}
+	}
+
+	fn insert_test_blocks(blockchain: &InMemoryBlockchain<Block>) {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let just0 = Some(Justifications::from((ID, vec![0])));
+		let just1 = Some(Justifications::from((ID, vec![1])));
+		let just2 = None;
+		let just3 = Some(Justifications::from((ID, vec![3])));
+		blockchain.insert(header(0).hash(), header(0), just0, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(1).hash(), header(1), just1, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(2).hash(), header(2), just2, None, NewBlockState::Best).unwrap();
+		blockchain.insert(header(3).hash(), header(3), just3, None, NewBlockState::Final).unwrap();
+	}
+
+	#[test]
+	fn finality_proof_fails_if_no_more_last_finalized_blocks() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let just1 = Some(Justifications::from((ID, vec![1])));
+		let just2 = Some(Justifications::from((ID, vec![2])));
+		blockchain.insert(header(4).hash(), header(4), just1, None, NewBlockState::Best).unwrap();
+		blockchain.insert(header(5).hash(), header(5), just2, None, NewBlockState::Best).unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 5)]);
+
+		// The last finalized block is 3, so we cannot provide further justifications.
+		let proof_of_4 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			*header(4).number(),
+			&Default::default(),
+		);
+		assert!(matches!(proof_of_4, Err(FinalityProofError::BlockNotYetFinalized)));
+	}
+
+	#[test]
+	fn finality_proof_is_none_if_no_justification_known() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		blockchain
+			.insert(header(4).hash(), header(4), None, None, NewBlockState::Final)
+			.unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 4)]);
+
+		// Block 4 is finalized without justification
+		// => we can't prove finality of 3
+		let proof_of_3 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			*header(3).number(),
+			&Default::default(),
+		)
+		.unwrap();
+		assert_eq!(proof_of_3, None);
+	}
+
+	#[test]
+	fn finality_proof_check_fails_when_proof_decode_fails() {
+		// When we can't decode proof from Vec<u8>
+		check_finality_proof::<_, TestJustification>(
+			1,
+			vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)],
+			vec![42],
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_check_fails_when_proof_is_empty() {
+		// When decoded proof has zero length
+		check_finality_proof::<_, TestJustification>(
+			1,
+			vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)],
+			Vec::<TestJustification>::new().encode(),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_check_works() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: Vec::new(),
+		};
+		let proof = check_finality_proof::<_, TestJustification>(
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+	}
+
+	#[test]
+	fn finality_proof_check_returns_verified_set_id() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((4, auth.clone()), vec![7]).encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		let config = FinalityProofCheckConfig { set_id_offset: 2, ..Default::default() };
+		let proof = check_finality_proof_with_config::<_, TestJustification>(
+			2,
+			auth.clone(),
+			finality_proof.encode(),
+			&config,
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+		assert_eq!(config.verified_set_id(2).unwrap(), 4);
+		assert_eq!(FinalityProofCheckConfig::default().verified_set_id(4).unwrap(), 4);
+
+		// The justification was not signed by set 3.
+		check_finality_proof_with_config::<Header, TestJustification>(
+			1,
+			auth,
+			finality_proof.encode(),
+			&config,
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_using_authority_set_changes_fails_with_undefined_start() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let grandpa_just4 = TestJustification((0, auth.clone()), vec![4]).encode();
+		let grandpa_just7 = TestJustification((1, auth.clone()), vec![7]).encode();
+		let just4 = Some(Justifications::from((ID, grandpa_just4)));
+		let just7 = Some(Justifications::from((ID, grandpa_just7)));
+		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(6).hash(), header(6), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(7).hash(), header(7), just7, None, NewBlockState::Final).unwrap();
+
+		// We have stored the correct block number for the relevant set, but as we are missing the
+		// block for the preceding set the start is not well-defined.
+		let authority_set_changes = set_changes(&[(1, 7)]);
+
+		let proof_of_5 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			*header(5).number(),
+			&Default::default(),
+		);
+		assert!(matches!(proof_of_5, Err(FinalityProofError::BlockNotInAuthoritySetChanges)));
+	}
+
+	#[test]
+	fn finality_proof_using_authority_set_changes_works() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let grandpa_just4 = TestJustification((0, auth.clone()), vec![4]).encode();
+		let grandpa_just7 = TestJustification((1, auth.clone()), vec![7]).encode();
+		let just4 = Some(Justifications::from((ID, grandpa_just4)));
+		let just7 = Some(Justifications::from((ID, grandpa_just7.clone())));
+		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final) .unwrap();
+		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final) .unwrap();
+		blockchain.insert(header(6).hash(), header(6), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(7).hash(), header(7), just7, None, NewBlockState::Final).unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 4), (1, 7)]);
+
+		let proof_of_5: FinalityProof = Decode::decode(
+			&mut &prove_finality::<_, _, TestJustification>(
+				&*backend,
+				authority_set_changes,
+				*header(5).number(),
+				&Default::default(),
+			)
+			.unwrap()
+			.unwrap()[..],
+		)
+		.unwrap();
+		assert_eq!(
+			proof_of_5,
+			FinalityProof {
+				block: header(7).hash(),
+				justification: grandpa_just7,
+				unknown_headers: vec![header(6)],
+			}
+		);
+	}
+
+	#[test]
+	fn finality_proof_fails_if_gap_exceeds_max_gap() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 7, &[7]);
+
+		let authority_set_changes = set_changes(&[(0, 7)]);
+
+		// Proving block 1 requires the justification at block 7, i.e. a gap of 6 blocks.
+		let config = FinalityProofConfig { max_gap: Some(5), ..Default::default() };
+		let proof_of_1 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes.clone(),
+			1,
+			&config,
+		);
+		assert!(matches!(proof_of_1, Err(FinalityProofError::ProofTooLarge)));
+
+		let config = FinalityProofConfig { max_gap: Some(6), ..Default::default() };
+		let proof_of_1 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			1,
+			&config,
+		);
+		assert!(matches!(proof_of_1, Ok(Some(_))));
+	}
+
+	#[test]
+	fn finality_proof_can_be_served_for_alternative_finality_source() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		const ALT_ENGINE_ID: ConsensusEngineId = *b"ALTF";
+
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let alt_just5 = TestJustification((0, auth.clone()), vec![5]).encode();
+		let mut just5 = Justifications::from((ALT_ENGINE_ID, alt_just5.clone()));
+		just5.append((ID, vec![42]));
+		finalize_test_blocks(blockchain, 4, &[]);
+		blockchain
+			.insert(header(5).hash(), header(5), Some(just5), None, NewBlockState::Final)
+			.unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 5)]);
+
+		let config = FinalityProofConfig {
+			finality_source: FinalitySource::Other(ALT_ENGINE_ID),
+			..Default::default()
+		};
+		let proof_of_4 = prove_finality::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			4,
+			&config,
+		)
+		.unwrap()
+		.unwrap();
+
+		let proof = check_finality_proof::<Header, TestJustification>(0, auth, proof_of_4).unwrap();
+		assert_eq!(proof.block, header(5).hash());
+		assert_eq!(proof.justification, alt_just5);
+
+		// The justifications of the alternative source are served by the other APIs as well.
+		let provider = test_provider(backend, vec![(0, 5)].into()).with_config(config);
+		assert_eq!(provider.justifications_in_range(4, 5).unwrap(), vec![(5, alt_just5.clone())]);
+		let (boundaries, warnings) = provider.all_set_boundary_justifications(5).unwrap();
+		assert_eq!(boundaries, vec![(0, 5, alt_just5)]);
+		assert!(warnings.is_empty());
+		assert!(provider.justification_coverage_bitmap(0, 0).unwrap()[0]);
+	}
+
+	#[test]
+	fn finality_proof_check_works_with_set_id_offset() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		// The proof was produced by a network whose set id numbering is ahead of ours by 2.
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((3, auth.clone()), vec![7]).encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		check_finality_proof::<_, TestJustification>(1, auth.clone(), finality_proof.encode())
+			.unwrap_err();
+
+		let config = FinalityProofCheckConfig { set_id_offset: 2, ..Default::default() };
+		let proof = check_finality_proof_with_config::<_, TestJustification>(
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+			&config,
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		let config = FinalityProofCheckConfig { set_id_offset: -2, ..Default::default() };
+		check_finality_proof_with_config::<_, TestJustification>(
+			1,
+			auth,
+			finality_proof.encode(),
+			&config,
+		)
+		.unwrap_err();
+	}
+
+	/// A minimal subscriber recording the name and explicit parent of every span created, and the
+	/// fields of every event emitted.
+	#[derive(Clone, Default)]
+	struct SpanRecorder {
+		next_id: Arc<AtomicU64>,
+		spans: Arc<Mutex<Vec<(u64, &'static str, Option<u64>)>>>,
+		events: Arc<Mutex<Vec<EventFields>>>,
+	}
+
+	/// The fields of a recorded event, formatted with their `Debug` implementation.
+	#[derive(Clone, Default)]
+	struct EventFields(HashMap<&'static str, String>);
+
+	impl tracing::field::Visit for EventFields {
+		fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+			self.0.insert(field.name(), format!("{:?}", value));
+		}
+	}
+
+	impl tracing::Subscriber for SpanRecorder {
+		fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+			true
+		}
+
+		fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+			let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+			let parent = attrs.parent().map(|parent| parent.into_u64());
+			self.spans.lock().push((id, attrs.metadata().name(), parent));
+			tracing::span::Id::from_u64(id)
+		}
+
+		fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+		fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+		fn event(&self, event: &tracing::Event<'_>) {
+			let mut fields = EventFields::default();
+			event.record(&mut fields);
+			self.events.lock().push(fields);
+		}
+
+		fn enter(&self, _: &tracing::span::Id) {}
+
+		fn exit(&self, _: &tracing::span::Id) {}
+	}
+
+	#[test]
+	fn finality_proof_span_is_child_of_given_parent() {
+		let backend = test_backend();
+		let authority_set_changes = set_changes(&[(0, 3)]);
+		let provider = test_provider(backend, authority_set_changes);
+
+		let recorder = SpanRecorder::default();
+		tracing::subscriber::with_default(recorder.clone(), || {
+			let request_span = tracing::debug_span!("finality_proof_request");
+			futures::executor::block_on(provider.prove_finality_async(1, Some(&request_span)))
+				.unwrap();
+		});
+
+		let spans = recorder.spans.lock();
+		let (request_id, _, _) = spans
+			.iter()
+			.find(|(_, name, _)| *name == "finality_proof_request")
+			.cloned()
+			.unwrap();
+		let (_, _, prove_parent) = spans
+			.iter()
+			.find(|(_, name, _)| *name == "prove_finality")
+			.cloned()
+			.unwrap();
+
+		assert_eq!(prove_parent, Some(request_id));
+	}
+
+	#[test]
+	fn authority_set_changes_lookup_emits_event_for_each_outcome() {
+		let backend = test_backend();
+
+		let lookup_outcome = |authority_set_changes: AuthoritySetChanges<u64>, block: u64| {
+			let recorder = SpanRecorder::default();
+			tracing::subscriber::with_default(recorder.clone(), || {
+				let _ = prove_finality::<_, _, TestJustification>(
+					&*backend,
+					authority_set_changes,
+					block,
+					&Default::default(),
+				);
+			});
+
+			let events = recorder.events.lock();
+			let EventFields(fields) = events
+				.iter()
+				.find(|EventFields(fields)| fields.contains_key("outcome"))
+				.cloned()
+				.unwrap();
+			(
+				fields["outcome"].clone(),
+				fields["previous_boundary"].clone(),
+				fields["next_boundary"].clone(),
+			)
+		};
+
+		let authority_set_changes = set_changes(&[(0, 1)]);
+		assert_eq!(
+			lookup_outcome(authority_set_changes.clone(), 1),
+			("Set(0, 1)".to_string(), "None".to_string(), "Some((0, 1))".to_string()),
+		);
+		assert_eq!(
+			lookup_outcome(authority_set_changes, 2),
+			("Latest".to_string(), "Some((0, 1))".to_string(), "None".to_string()),
+		);
+
+		let authority_set_changes = set_changes(&[(1, 3)]);
+		assert_eq!(
+			lookup_outcome(authority_set_changes, 2),
+			("Unknown".to_string(), "None".to_string(), "Some((1, 3))".to_string()),
+		);
+	}
+
+	#[test]
+	fn detailed_finality_proofs_report_required_set_id() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 7, &[5, 7]);
+
+		let authority_set_changes = set_changes(&[(0, 3), (1, 5), (2, 7)]);
+
+		// One fragment per authority set, each one must be verified by the set it belongs to.
+		let fragments = [2, 4, 6]
+			.iter()
+			.map(|block| {
+				prove_finality_detailed::<_, _, TestJustification>(
+					&*backend,
+					authority_set_changes.clone(),
+					*block,
+					&Default::default(),
+				)
+				.unwrap()
+				.unwrap()
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(
+			fragments.iter().map(|f| (f.proof.block, f.requires_set_id)).collect::<Vec<_>>(),
+			vec![(header(3).hash(), 0), (header(5).hash(), 1), (header(7).hash(), 2)],
+		);
+	}
+
+	#[test]
+	fn finality_proof_provider_works_from_changes_snapshot() {
+		let backend = test_backend();
+
+		let authority_set_changes = set_changes(&[(0, 3)]);
+
+		// The snapshot is persisted and loaded back independently of the authority set.
+		let snapshot = authority_set_changes.encode();
+		let authority_set_changes = AuthoritySetChanges::decode(&mut &snapshot[..]).unwrap();
+
+		let provider = FinalityProofProvider::<_, Block>::from_changes_snapshot(
+			backend,
+			authority_set_changes,
+		);
+
+		let proof_of_2: FinalityProof =
+			Decode::decode(&mut &provider.prove_finality(2).unwrap().unwrap()[..]).unwrap();
+		assert_eq!(
+			proof_of_2,
+			FinalityProof {
+				block: header(3).hash(),
+				justification: vec![3],
+				unknown_headers: Vec::new(),
+			},
+		);
+	}
+
+	#[test]
+	fn finality_proof_check_reports_stale_proofs() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(5).hash(),
+			justification: TestBlockJustification(
+				TestJustification((1, auth.clone()), vec![5]),
+				5,
+				header(5).hash(),
+			)
+			.encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		let validity = |set_id, known_finalized| {
+			check_finality_proof_validity::<Header, TestBlockJustification>(
+				set_id,
+				auth.clone(),
+				finality_proof.encode(),
+				known_finalized,
+			)
+		};
+
+		assert!(matches!(
+			validity(1, 3),
+			FinalityProofValidity::Valid(ref p) if *p == finality_proof
+		));
+		assert!(matches!(validity(1, 5), FinalityProofValidity::ValidButStale(_)));
+		assert!(matches!(validity(1, 7), FinalityProofValidity::ValidButStale(_)));
+		assert!(matches!(validity(2, 3), FinalityProofValidity::Invalid(_)));
+	}
+
+	#[test]
+	fn parallel_header_collection_preserves_ordering() {
+		let backend = test_backend();
+		finalize_test_blocks(backend.blockchain(), 63, &[63]);
+
+		let authority_set_changes = set_changes(&[(0, 63)]);
+
+		let sequential = prove_finality_detailed::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes.clone(),
+			2,
+			&Default::default(),
+		)
+		.unwrap()
+		.unwrap();
+
+		let config = FinalityProofConfig {
+			parallel_header_collection_threshold: Some(4),
+			..Default::default()
+		};
+		let parallel = prove_finality_detailed::<_, _, TestJustification>(
+			&*backend,
+			authority_set_changes,
+			2,
+			&config,
+		)
+		.unwrap()
+		.unwrap();
+
+		assert_eq!(parallel, sequential);
+		assert_eq!(parallel.proof.unknown_headers, (3..63).map(header).collect::<Vec<_>>());
+		for pair in parallel.proof.unknown_headers.windows(2) {
+			assert_eq!(*pair[1].parent_hash(), pair[0].hash());
+		}
+	}
+
+	#[test]
+	fn finality_proof_check_works_with_justified_header_only() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(5).hash(),
+			justification: TestBlockJustification(
+				TestJustification((1, auth.clone()), vec![5]),
+				5,
+				header(5).hash(),
+			)
+			.encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		// The caller knows the chain up to block 2 and is able to resolve the headers in between
+		// the known tip and the justified block.
+		let known_headers =
+			(3..5).map(|n| (header(n).hash(), header(n))).collect::<HashMap<_, _>>();
+		let proof = check_finality_proof_with_justified_header::<_, TestBlockJustification>(
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+			&header(5),
+			header(2).hash(),
+			|hash| known_headers.get(hash).cloned(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		// The justified header must chain to the known tip.
+		check_finality_proof_with_justified_header::<_, TestBlockJustification>(
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+			&header(5),
+			header(2).hash(),
+			|hash| known_headers.get(hash).filter(|h| *h.number() != 3).cloned(),
+		)
+		.unwrap_err();
+
+		// And match the justification.
+		check_finality_proof_with_justified_header::<_, TestBlockJustification>(
+			1,
+			auth,
+			finality_proof.encode(),
+			&header(4),
+			header(2).hash(),
+			|hash| known_headers.get(hash).cloned(),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_reports_age_of_stale_best_justification() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 5, &[]);
+
+		// The best justification lags behind the finalized head (#5) by two blocks.
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 3)]);
+
+		let registry = Registry::new();
+		let provider = test_provider(backend, authority_set_changes)
+			.with_prometheus_registry(&registry)
+			.unwrap();
+
+		provider.prove_finality(2).unwrap().unwrap();
+		assert_eq!(provider.metrics.as_ref().unwrap().justification_age.get(), 2);
+	}
+
+	#[test]
+	fn finality_proof_of_latest_set_uses_best_justification() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 5, &[]);
+
+		// Block 2 is in the latest authority set, its proof uses the best justification even though
+		// it lags behind the finalized head (#5).
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 1)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+
+		let proof = provider.prove_finality_detailed(2).unwrap().unwrap();
+		assert!(proof.from_best_justification);
+		assert_eq!(proof.justified_number, 3);
+		assert_eq!(proof.requires_set_id, 1);
+		assert_eq!(proof.proof.block, header(3).hash());
+
+		// Block 4 is finalized but the best justification doesn't cover it.
+		assert!(matches!(
+			provider.prove_finality_detailed(4),
+			Err(FinalityProofError::NotYetProvable(3))
+		));
+	}
+
+	#[test]
+	fn all_set_boundary_justifications_skips_missing_justifications() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[5]);
+
+		// Block 4 was finalized without a justification, e.g. by a forced change.
+		let authority_set_changes = set_changes(&[(0, 3), (1, 4), (2, 5), (3, 6)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let (justifications, warnings) = provider.all_set_boundary_justifications(5).unwrap();
+
+		assert_eq!(justifications, vec![(0, 3, vec![3]), (2, 5, vec![5])]);
+		assert_eq!(warnings.len(), 1);
+		assert!(warnings[0].contains("#4"));
+	}
+
+	#[test]
+	fn finality_proof_without_best_justification_follows_policy() {
+		let authority_set_changes = set_changes(&[(0, 1)]);
+
+		// Block 2 is in the latest authority set but no best justification was stored.
+		let prove_with_policy = |no_justification_policy| {
+			let config = FinalityProofConfig { no_justification_policy, ..Default::default() };
+			prove_finality_detailed::<_, _, TestJustification>(
+				&*test_backend(),
+				authority_set_changes.clone(),
+				2,
+				&config,
+			)
+		};
+
+		assert!(matches!(prove_with_policy(NoJustificationPolicy::ReturnNone), Ok(None)));
+		assert!(matches!(
+			prove_with_policy(NoJustificationPolicy::ReturnError),
+			Err(FinalityProofError::JustificationNotFound)
+		));
+
+		// The finalized head #3 has a justification stored.
+		let proof = prove_with_policy(NoJustificationPolicy::TryFinalizedHead).unwrap().unwrap();
+		assert_eq!(proof.proof.block, header(3).hash());
+		assert_eq!(proof.proof.justification, vec![3]);
+		assert_eq!(proof.justified_number, 3);
+		assert_eq!(proof.requires_set_id, 1);
+	}
+
+	#[test]
+	fn partial_verifier_completes_as_ancestry_becomes_available() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(5).hash(),
+			justification: TestBlockJustification(
+				TestJustification((1, auth.clone()), vec![5]),
+				5,
+				header(5).hash(),
+			)
+			.encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		let verifier = PartialVerifier::<_, TestBlockJustification>::new(
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+			(2, header(2).hash()),
+		)
+		.unwrap();
+
+		// The header store is filled in as headers are synced, starting from the justified block.
+		let mut header_store = HashMap::new();
+		for number in (3..=5).rev() {
+			assert!(matches!(
+				verifier.try_complete(|hash| header_store.get(hash).cloned()),
+				PartialVerification::Pending
+			));
+			header_store.insert(header(number).hash(), header(number));
+		}
+		assert!(matches!(
+			verifier.try_complete(|hash| header_store.get(hash).cloned()),
+			PartialVerification::Valid
+		));
+
+		// A justified block that doesn't descend from the known tip can never become valid.
+		let verifier = PartialVerifier::<_, TestBlockJustification>::new(
+			1,
+			auth,
+			finality_proof.encode(),
+			(2, H256::from_low_u64_be(42)),
+		)
+		.unwrap();
+		assert!(matches!(
+			verifier.try_complete(|hash| header_store.get(hash).cloned()),
+			PartialVerification::Invalid(_)
+		));
+	}
+
+	#[test]
+	fn finality_proof_signed_by_node_key_round_trips() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 4, &[4]);
+
+		let authority_set_changes = set_changes(&[(0, 4)]);
+
+		let (key, _) = ed25519::Pair::generate();
+		let provider = test_provider(backend, authority_set_changes).with_signing_key(key.clone());
+
+		let plain_proof = provider.prove_finality(3).unwrap().unwrap();
+		let envelope = provider.prove_finality_envelope(3).unwrap().unwrap();
+		let envelope = FinalityProofEnvelope::<H256>::decode(&mut &envelope.encode()[..]).unwrap();
+		let signed_proof = match envelope {
+			FinalityProofEnvelope::Signed(signed_proof) => signed_proof,
+			envelope => panic!("Unexpected envelope: {:?}", envelope),
+		};
+		assert_eq!(signed_proof.signer, key.public());
+		assert!(signed_proof.verify_signature());
+
+		// The signed proof is the plain proof served without a signing key.
+		let unsigned: FinalityProofEnvelope<H256> =
+			Decode::decode(&mut &signed_proof.proof[..]).unwrap();
+		assert_eq!(unsigned, FinalityProofEnvelope::Plain(plain_proof.clone()));
+		let proof: FinalityProof = Decode::decode(&mut &plain_proof[..]).unwrap();
+		assert_eq!(proof.block, header(4).hash());
+		assert_eq!(proof.justification, vec![4]);
+
+		// Tampering with the proof invalidates the signature.
+		let mut tampered = signed_proof;
+		tampered.proof.push(0);
+		assert!(!tampered.verify_signature());
+	}
+
+	#[test]
+	fn finality_proof_truncation_follows_strategy() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 8, &[5, 8]);
+
+		let authority_set_changes = set_changes(&[(0, 8)]);
+
+		// Proving block 2 with the justification at block 8 requires 5 headers.
+		let prove_with_strategy = |truncation_strategy| {
+			let config = FinalityProofConfig {
+				max_unknown_headers: 3,
+				truncation_strategy,
+				..Default::default()
+			};
+			prove_finality_detailed::<_, _, TestJustification>(
+				&*backend,
+				authority_set_changes.clone(),
+				2,
+				&config,
+			)
+		};
+
+		assert!(matches!(
+			prove_with_strategy(TruncationStrategy::Fail),
+			Err(FinalityProofError::ProofTooLarge)
+		));
+
+		let proof = prove_with_strategy(TruncationStrategy::TruncateHigh).unwrap().unwrap();
+		assert_eq!(proof.proof.block, header(8).hash());
+		assert_eq!(proof.proof.unknown_headers, vec![header(3), header(4), header(5)]);
+
+		let proof =
+			prove_with_strategy(TruncationStrategy::NearestJustifiedAncestor).unwrap().unwrap();
+		assert_eq!(proof.proof.block, header(5).hash());
+		assert_eq!(proof.proof.justification, vec![5]);
+		assert_eq!(proof.proof.unknown_headers, vec![header(3), header(4)]);
+		assert_eq!(proof.requires_set_id, 0);
+	}
+
+	#[test]
+	fn nearest_justified_ancestor_search_is_bounded() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 8, &[4, 8]);
+
+		let authority_set_changes = set_changes(&[(0, 8)]);
+
+		// The headers of a proof of block 2 are limited to (2; 6], i.e. its justified block can be
+		// at most block 7, three blocks above the justification at block 4.
+		let prove_with_lookups = |max_justification_lookups| {
+			let config = FinalityProofConfig {
+				max_unknown_headers: 4,
+				truncation_strategy: TruncationStrategy::NearestJustifiedAncestor,
+				max_justification_lookups,
+				..Default::default()
+			};
+			prove_finality_detailed::<_, _, TestJustification>(
+				&*backend,
+				authority_set_changes.clone(),
+				2,
+				&config,
+			)
+		};
+
+		assert!(matches!(prove_with_lookups(3), Err(FinalityProofError::ProofTooLarge)));
+		let proof = prove_with_lookups(4).unwrap().unwrap();
+		assert_eq!(proof.proof.block, header(4).hash());
+	}
+
+	#[test]
+	fn finality_proof_check_resolves_referenced_justification() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let justification = TestJustification((1, auth.clone()), vec![7]).encode();
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: justification.clone(),
+			unknown_headers: vec![header(1)],
+		};
+
+		let ref_proof = FinalityProofV2::with_justification_ref(finality_proof.clone());
+		let justification_store = vec![(blake2_256(&justification), justification)]
+			.into_iter()
+			.collect::<HashMap<_, _>>();
+
+		let proof = check_finality_proof_v2::<_, TestJustification>(
+			1,
+			auth.clone(),
+			ref_proof.encode(),
+			|hash| justification_store.get(hash).cloned(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		// Fails if the justification can't be resolved.
+		check_finality_proof_v2::<Header, TestJustification>(
+			1,
+			auth.clone(),
+			ref_proof.encode(),
+			|_| None,
+		)
+		.unwrap_err();
+
+		// Inline justifications don't need to be resolved.
+		let proof = check_finality_proof_v2::<_, TestJustification>(
+			1,
+			auth,
+			FinalityProofV2::from(finality_proof.clone()).encode(),
+			|_| None,
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+	}
+
+	#[test]
+	fn finality_proof_v2_encoding_is_versioned() {
+		let proof = FinalityProofV2::<Header>::from(FinalityProof {
+			block: header(2).hash(),
+			justification: vec![7],
+			unknown_headers: vec![header(1), header(2)],
+		});
+
+		let mut encoded = proof.encode();
+		assert_eq!(encoded[0], FINALITY_PROOF_V2_VERSION);
+		assert_eq!(FinalityProofV2::<Header>::decode(&mut &encoded[..]).unwrap(), proof);
+
+		encoded[0] = FINALITY_PROOF_V2_VERSION + 1;
+		assert!(FinalityProofV2::<Header>::decode(&mut &encoded[..]).is_err());
+	}
+
+	#[test]
+	fn finality_proof_v2_decoding_skips_unknown_optional_fields() {
+		let proof = FinalityProofV2::<Header>::from(FinalityProof {
+			block: header(2).hash(),
+			justification: vec![7],
+			unknown_headers: vec![header(1), header(2)],
+		});
+
+		// Replace the empty list of optional fields by one holding a field of a later version.
+		let mut encoded = proof.encode();
+		assert_eq!(encoded.pop(), Some(0));
+		vec![(u8::MAX, vec![1u8, 2, 3])].encode_to(&mut encoded);
+
+		assert_eq!(FinalityProofV2::<Header>::decode(&mut &encoded[..]).unwrap(), proof);
+	}
+
+	#[test]
+	fn explain_many_reports_each_block() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		// Block 4 was finalized without a justification.
+		let authority_set_changes = set_changes(&[(0, 4), (1, 6)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let plans = provider.explain_many(&[2, 5, 6, 7]);
+
+		assert_eq!(plans, vec![
+			ProofPlan::NoJustification { block: 2 },
+			ProofPlan::Provable { block: 5, justified: 6, requires_set_id: 1, headers: 0 },
+			ProofPlan::Provable { block: 6, justified: 6, requires_set_id: 1, headers: 0 },
+			ProofPlan::Unprovable {
+				block: 7,
+				reason: FinalityProofError::BlockNotYetFinalized.to_string(),
+			},
+		]);
+	}
+
+	#[test]
+	fn finality_proof_check_uses_declared_threshold() {
+		let auth = (0..4u8)
+			.map(|i| (AuthorityId::from_slice(&[i; 32]), 1u64))
+			.collect::<AuthorityList>();
+
+		// Signed by authorities with a total weight of 3 out of 4, the default threshold.
+		let justification =
+			TestWeightedJustification(TestJustification((1, auth.clone()), vec![7]), 3);
+		let proof_with_threshold = |threshold| {
+			FinalityProofV2::<Header> {
+				block: header(2).hash(),
+				justification: ProofJustification::Inline(justification.encode()),
+				unknown_headers: ProofHeaders::Full(Vec::new()),
+				threshold,
+				sibling_hints: Vec::new(),
+				set_change: None,
+				prerequisite_sets: None,
+				server_version: None,
+				genesis_hash: None,
+			}
+			.encode()
+		};
+		let check = |threshold| {
+			check_finality_proof_v2::<Header, TestWeightedJustification>(
+				1,
+				auth.clone(),
+				proof_with_threshold(threshold),
+				|_| None,
+			)
+		};
+
+		check(None).unwrap();
+		check(Some(3)).unwrap();
+		// The governance model requires all authorities to sign.
+		check(Some(4)).unwrap_err();
+		// Thresholds below the default supermajority are not allowed.
+		check(Some(2)).unwrap_err();
+		// Nor above the total weight of the authority set.
+		check(Some(5)).unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_sibling_hints_reveal_forks() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let fork4 = Header::new(
+			4,
+			H256::from_low_u64_be(1),
+			H256::from_low_u64_be(0),
+			header(3).hash(),
+			Default::default(),
+		);
+		blockchain.insert(fork4.hash(), fork4.clone(), None, None, NewBlockState::Normal).unwrap();
+		finalize_test_blocks(blockchain, 4, &[4]);
+
+		let authority_set_changes = set_changes(&[(0, 4)]);
+
+		let provider = test_provider(backend.clone(), authority_set_changes.clone());
+		let proof = provider.prove_finality_v2(3).unwrap().unwrap();
+		assert!(proof.sibling_hints.is_empty());
+
+		let provider = test_provider(backend, authority_set_changes).with_config(
+			FinalityProofConfig { include_sibling_hints: true, ..Default::default() },
+		);
+		let proof = provider.prove_finality_v2(3).unwrap().unwrap();
+		assert_eq!(proof.block, header(4).hash());
+		assert_eq!(proof.sibling_hints, vec![fork4.hash()]);
+		assert_eq!(FinalityProofV2::<Header>::decode(&mut &proof.encode()[..]).unwrap(), proof);
+
+		// A client that followed the fork learns that it was not finalized.
+		assert!(proof.is_known_sibling(&fork4.hash()));
+		assert!(!proof.is_known_sibling(&header(4).hash()));
+	}
+
+	#[test]
+	fn finality_proof_archive_round_trips() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let grandpa_just4 = TestJustification((0, auth.clone()), vec![4]).encode();
+		let grandpa_just6 = TestJustification((1, auth.clone()), vec![6]).encode();
+		let just4 = Some(Justifications::from((ID, grandpa_just4)));
+		let just6 = Some(Justifications::from((ID, grandpa_just6)));
+		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(6).hash(), header(6), just6, None, NewBlockState::Final).unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 4), (1, 6)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let mut archive = Vec::new();
+		assert_eq!(provider.export_archive(6, &mut archive).unwrap(), 2);
+
+		let proofs = import_archive::<Header>(&archive[..]).unwrap();
+		assert_eq!(
+			proofs.iter().map(|p| (p.proof.block, p.requires_set_id)).collect::<Vec<_>>(),
+			vec![(header(4).hash(), 0), (header(6).hash(), 1)],
+		);
+		for proof in proofs {
+			check_finality_proof::<_, TestJustification>(
+				proof.requires_set_id,
+				auth.clone(),
+				proof.proof.encode(),
+			)
+			.unwrap();
+		}
+
+		// A truncated archive is rejected.
+		assert!(import_archive::<Header>(&archive[..archive.len() - 1]).is_err());
+	}
+
+	#[test]
+	fn finality_proof_check_fails_when_target_number_mismatches_headers() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let proof_with_target = |target_number| {
+			FinalityProof {
+				block: header(5).hash(),
+				justification: TestBlockJustification(
+					TestJustification((1, auth.clone()), vec![5]),
+					target_number,
+					header(5).hash(),
+				)
+				.encode(),
+				unknown_headers: vec![header(4), header(5)],
+			}
+			.encode()
+		};
+
+		check_finality_proof::<Header, TestBlockJustification>(
+			1,
+			auth.clone(),
+			proof_with_target(5),
+		)
+		.unwrap();
+
+		// The justification claims to finalize #6 while the header chain ends at #5.
+		check_finality_proof::<Header, TestBlockJustification>(
+			1,
+			auth.clone(),
+			proof_with_target(6),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn first_valid_stops_at_first_valid_proof() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let proof_for_set = |set_id| {
+			FinalityProof {
+				block: header(2).hash(),
+				justification: TestJustification((set_id, auth.clone()), vec![7]).encode(),
+				unknown_headers: Vec::new(),
+			}
+		};
+
+		// The first peer responds with a proof for the wrong set, the second one is honest.
+		let responses = vec![proof_for_set(0).encode(), proof_for_set(1).encode()]
+			.into_iter()
+			.chain(std::iter::once_with(|| panic!("proofs after a valid one must not be checked")));
+
+		let proof = first_valid::<_, TestJustification>(responses, 1, auth.clone());
+		assert_eq!(proof, Some(proof_for_set(1)));
+
+		assert_eq!(
+			first_valid::<Header, TestJustification>(vec![proof_for_set(0).encode()], 1, auth),
+			None,
+		);
+	}
+
+	#[test]
+	fn finality_proof_for_hash_follows_the_requested_fork() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		// A competing fork at the height of block 4.
+		let fork4 = Header::new(
+			4,
+			H256::from_low_u64_be(1),
+			H256::from_low_u64_be(0),
+			header(3).hash(),
+			Default::default(),
+		);
+		blockchain.insert(fork4.hash(), fork4.clone(), None, None, NewBlockState::Normal).unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 6)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let proof = provider.prove_finality_for_hash(header(4).hash()).unwrap().unwrap();
+		assert_eq!(proof.proof.block, header(6).hash());
+		assert_eq!(proof.proof.unknown_headers, vec![header(5)]);
+
+		// The fork block has the same number, but wasn't finalized.
+		assert!(matches!(
+			provider.prove_finality_for_hash(fork4.hash()),
+			Err(FinalityProofError::BlockNotAncestorOfJustified)
+		));
+
+		// The walk from the justified block stops after the maximum number of headers.
+		let provider = provider.with_config(FinalityProofConfig {
+			max_unknown_headers: 1,
+			..Default::default()
+		});
+		assert!(matches!(
+			provider.prove_finality_for_hash(header(3).hash()),
+			Err(FinalityProofError::ProofTooLarge)
+		));
+	}
+
+	#[test]
+	fn reorg_evicts_stale_cached_proofs() {
+		use sp_blockchain::HeaderBackend;
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let authority_set_changes = set_changes(&[(0, 6)]);
+
+		let provider = test_provider(backend.clone(), authority_set_changes).with_proof_cache(8);
+		let proof_of_4: FinalityProof =
+			Decode::decode(&mut &provider.prove_finality(4).unwrap().unwrap()[..]).unwrap();
+		assert_eq!(proof_of_4.block, header(6).hash());
+		assert_eq!(provider.proof_cache.as_ref().unwrap().lock().entries.len(), 1);
+
+		// A deep reorg replaces blocks 4 to 6 with a competing fork.
+		let fork_header = |number: u64, parent_hash| {
+			Header::new(
+				number,
+				H256::from_low_u64_be(1),
+				H256::from_low_u64_be(0),
+				parent_hash,
+				Default::default(),
+			)
+		};
+		let fork4 = fork_header(4, header(3).hash());
+		let fork5 = fork_header(5, fork4.hash());
+		let fork6 = fork_header(6, fork5.hash());
+		let fork_just6 = Some(Justifications::from((ID, vec![66])));
+		for (fork, just) in vec![(fork4, None), (fork5, None), (fork6.clone(), fork_just6)] {
+			blockchain.insert(fork.hash(), fork, just, None, NewBlockState::Final).unwrap();
+		}
+
+		// The stale proof is evicted and the proof recomputed on the new fork.
+		let proof_of_4: FinalityProof =
+			Decode::decode(&mut &provider.prove_finality(4).unwrap().unwrap()[..]).unwrap();
+		assert_eq!(proof_of_4.block, fork6.hash());
+		assert_eq!(proof_of_4.justification, vec![66]);
+
+		// Proofs can also be evicted explicitly.
+		provider.note_reorg(&[fork6.hash()]);
+		assert_eq!(provider.proof_cache.as_ref().unwrap().lock().entries.len(), 1);
+		provider.note_reorg(&[blockchain.hash(4).unwrap().unwrap()]);
+		assert!(provider.proof_cache.as_ref().unwrap().lock().entries.is_empty());
+	}
+
+	#[test]
+	fn archive_can_be_verified_offline() {
+		use sp_finality_grandpa::{ConsensusLog, ScheduledChange, GRANDPA_ENGINE_ID as ID};
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let authorities = (0..4u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+
+		// Blocks 4, 6 and 8 enact an authority set change each, and are justified by the set
+		// they end.
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let mut parent_hash = header(3).hash();
+		for number in 4..=8u64 {
+			let set_id = number / 2 - 2;
+			let (digest, justification) = if number % 2 == 0 {
+				let change = ConsensusLog::ScheduledChange(ScheduledChange {
+					next_authorities: authorities[set_id as usize + 1].clone(),
+					delay: 0u64,
+				});
+				let digest = Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] };
+				let justification =
+					TestJustification((set_id, authorities[set_id as usize].clone()), vec![]);
+				(digest, Some(Justifications::from((ID, justification.encode()))))
+			} else {
+				(Default::default(), None)
+			};
+
+			let header = Header::new(
+				number,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				parent_hash,
+				digest,
+			);
+			parent_hash = header.hash();
+			blockchain
+				.insert(header.hash(), header, justification, None, NewBlockState::Final)
+				.unwrap();
+		}
+
+		let authority_set_changes = set_changes(&[(0, 4), (1, 6), (2, 8)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let mut archive = Vec::new();
+		assert_eq!(provider.export_archive(8, &mut archive).unwrap(), 3);
+
+		let verify = |archive: &[u8]| {
+			verify_archive::<Block, TestJustification>(archive, 0, authorities[0].clone())
+		};
+		let timeline = verify(&archive).unwrap();
+		assert_eq!(timeline, (0..4u64).zip(authorities.clone()).collect::<Vec<_>>());
+
+		// Tamper with the middle fragment, claiming it was signed by the genesis authorities.
+		let mut proofs = import_archive::<Header>(&archive[..]).unwrap();
+		proofs[1].proof.justification =
+			TestJustification((1, authorities[0].clone()), vec![]).encode();
+		let mut tampered = Vec::new();
+		for proof in &proofs {
+			write_archive_entry(&mut tampered, proof).unwrap();
+		}
+
+		assert!(matches!(verify(&tampered), Err(VerifyError::InvalidFragment(1, _))));
+
+		// Dropping the middle fragment makes the last one imply a jump from set 1 to set 3.
+		let mut skipping = Vec::new();
+		for proof in [&proofs[0], &proofs[2]].iter() {
+			write_archive_entry(&mut skipping, proof).unwrap();
+		}
+
+		assert!(matches!(verify(&skipping), Err(VerifyError::IllegalSetTransition(1, 1, 3))));
+
+		// Repeating the first fragment doesn't follow the set it enacted.
+		let mut repeating = Vec::new();
+		for proof in [&proofs[0], &proofs[0]].iter() {
+			write_archive_entry(&mut repeating, proof).unwrap();
+		}
+
+		assert!(matches!(verify(&repeating), Err(VerifyError::UnexpectedSetId(1))));
+	}
+
+	#[test]
+	fn set_transitions_increase_set_id_by_one() {
+		assert!(is_legal_set_transition(0, 1));
+		assert!(is_legal_set_transition(41, 42));
+		assert!(!is_legal_set_transition(1, 1));
+		assert!(!is_legal_set_transition(1, 3));
+		assert!(!is_legal_set_transition(2, 1));
+		assert!(!is_legal_set_transition(SetId::max_value(), 0));
+	}
+
+	#[test]
+	fn same_set_fragments_are_compacted() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let fragment = |set_id, justified: u64, unknown_headers| DetailedFinalityProof {
+			proof: FinalityProof {
+				block: header(justified).hash(),
+				justification: TestJustification((set_id, auth.clone()), vec![justified as u8])
+					.encode(),
+				unknown_headers,
+			},
+			requires_set_id: set_id,
+			justified_number: justified,
+			from_best_justification: false,
+		};
+
+		let fragments = vec![
+			fragment(0, 4, vec![header(3), header(4)]),
+			fragment(0, 6, vec![header(5), header(6)]),
+			fragment(1, 8, vec![header(7), header(8)]),
+		];
+
+		let compacted = DetailedFinalityProof::compact(fragments);
+		assert_eq!(compacted, vec![
+			fragment(0, 6, vec![header(3), header(4), header(5), header(6)]),
+			fragment(1, 8, vec![header(7), header(8)]),
+		]);
+
+		for fragment in compacted {
+			check_finality_proof::<_, TestJustification>(
+				fragment.requires_set_id,
+				auth.clone(),
+				fragment.proof.encode(),
+			)
+			.unwrap();
+		}
+	}
+
+	#[test]
+	fn unknown_headers_exclude_requested_block() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 7, &[7]);
+
+		let authority_set_changes = set_changes(&[(0, 7)]);
+
+		let prove = |include_justified_header| {
+			let config =
+				FinalityProofConfig { include_justified_header, ..Default::default() };
+			prove_finality_detailed::<_, _, TestJustification>(
+				&*backend,
+				authority_set_changes.clone(),
+				3,
+				&config,
+			)
+			.unwrap()
+			.unwrap()
+		};
+
+		// The headers of a proof cover the range (B; F).
+		let unknown_headers = prove(false).proof.unknown_headers;
+		assert_eq!(*unknown_headers.first().unwrap().number(), 4);
+		assert_eq!(unknown_headers, (4..7).map(header).collect::<Vec<_>>());
+
+		// Or (B; F] if configured to include the justified header.
+		let proof = prove(true);
+		let unknown_headers = proof.proof.unknown_headers;
+		assert_eq!(*unknown_headers.first().unwrap().number(), 4);
+		assert_eq!(unknown_headers.last().unwrap().hash(), proof.proof.block);
+		assert_eq!(unknown_headers, (4..=7).map(header).collect::<Vec<_>>());
+	}
+
+	#[test]
+	fn finality_proof_check_resolves_session_authorities() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((2, auth.clone()), vec![7]).encode(),
+			unknown_headers: Vec::new(),
+		};
+
+		// Sessions 10 and 11 both belong to authority set 2.
+		let resolver = |session_index| match session_index {
+			10 | 11 => Some((2, auth.clone())),
+			_ => None,
+		};
+
+		let proof = check_finality_proof_for_session::<Header, TestJustification>(
+			11,
+			resolver,
+			finality_proof.encode(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		check_finality_proof_for_session::<Header, TestJustification>(
+			12,
+			resolver,
+			finality_proof.encode(),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn provider_is_ready_once_authority_set_changes_are_populated() {
+		let backend = test_backend();
+
+		let provider = FinalityProofProvider::<_, Block>::new(backend.clone(), None);
+		assert!(!provider.is_ready());
+
+		let provider = test_provider(backend.clone(), AuthoritySetChanges::empty());
+		assert!(!provider.is_ready());
+
+		let provider = test_provider(backend, vec![(0, 1)].into());
+		assert!(provider.is_ready());
+	}
+
+	#[test]
+	fn finality_proof_check_can_reject_set_changes() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let config = FinalityProofCheckConfig { reject_set_changes: true, ..Default::default() };
+		let check = |unknown_headers: Vec<Header>| {
+			let finality_proof = FinalityProof {
+				block: unknown_headers.last().map(|h| h.hash()).unwrap_or_default(),
+				justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+				unknown_headers,
+			};
+			check_finality_proof_with_config::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				finality_proof.encode(),
+				&config,
+			)
+		};
+
+		// A justified block within the set is accepted.
+		check(vec![header(2), header(3)]).unwrap();
+
+		// A justified block signalling a set change is rejected.
+		let change = ConsensusLog::ScheduledChange(ScheduledChange {
+			next_authorities: auth.clone(),
+			delay: 0u64,
+		});
+		let digest = Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] };
+		let change_header = Header::new(
+			3,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			header(2).hash(),
+			digest,
+		);
+		let err = check(vec![header(2), change_header]).unwrap_err();
+		assert!(matches!(err, ClientError::UnexpectedSetChange));
+
+		// Without the header of the justified block the change can't be ruled out.
+		check(Vec::new()).unwrap_err();
+	}
+
+	struct CompactHeaderCodec;
+
+	// Only keeps the number and parent hash, which is enough for the headers of the test chain.
+	impl HeaderCodec<Header> for CompactHeaderCodec {
+		fn encode_header(&self, header: &Header) -> Vec<u8> {
+			let mut encoded = header.number().to_le_bytes().to_vec();
+			encoded.extend_from_slice(header.parent_hash().as_bytes());
+			encoded
+		}
+
+		fn decode_header(&self, encoded: &[u8]) -> Option<Header> {
+			if encoded.len() != 40 {
+				return None;
+			}
+
+			let mut number = [0u8; 8];
+			number.copy_from_slice(&encoded[..8]);
+			Some(Header::new(
+				u64::from_le_bytes(number),
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				H256::from_slice(&encoded[8..]),
+				Default::default(),
+			))
+		}
+	}
+
+	#[test]
+	fn finality_proof_headers_can_use_custom_codec() {
+		let backend = test_backend();
+		let expected = test_provider(backend.clone(), vec![(0, 3)].into())
+			.prove_finality(1)
+			.unwrap()
+			.unwrap();
+		let expected = FinalityProof::decode(&mut &expected[..]).unwrap();
+
+		let provider =
+			test_provider(backend, vec![(0, 3)].into()).with_header_codec(CompactHeaderCodec);
+		let proof = match provider.prove_finality_envelope(1).unwrap().unwrap() {
+			FinalityProofEnvelope::WithHeaderCodec(proof) => proof,
+			envelope => panic!("Unexpected envelope: {:?}", envelope),
+		};
+
+		// The plain proof is unaffected by the header codec.
+		assert_eq!(provider.prove_finality(1).unwrap().unwrap(), expected.encode());
+
+		// The justification keeps its canonical encoding.
+		assert_eq!(proof.justification, expected.justification);
+		assert!(proof.unknown_headers.iter().all(|encoded| encoded.len() == 40));
+		assert_eq!(proof.into_finality_proof(&CompactHeaderCodec).unwrap(), expected);
+	}
+
+	#[test]
+	fn finality_proof_range_is_capped_with_continuation() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 5, &[4, 5]);
+
+		let authority_set_changes = set_changes(&[(0, 1), (1, 3), (2, 4), (3, 5)]);
+
+		let provider = test_provider(backend, authority_set_changes);
+		let fragment_sets = |range: &FinalityProofRange<Header>| {
+			range
+				.fragments
+				.iter()
+				.map(|fragment| (fragment.requires_set_id, fragment.justified_number))
+				.collect::<Vec<_>>()
+		};
+
+		let first = provider.prove_finality_range(0, 2).unwrap();
+		assert_eq!(fragment_sets(&first), vec![(0, 1), (1, 3)]);
+		assert_eq!(first.continuation, Some(3));
+
+		let rest = provider.prove_finality_range(first.continuation.unwrap(), 2).unwrap();
+		assert_eq!(fragment_sets(&rest), vec![(2, 4), (3, 5)]);
+		assert_eq!(rest.fragments[1].proof.unknown_headers, vec![header(5)]);
+		assert_eq!(rest.fragments[1].proof.justification, vec![5]);
+		assert_eq!(rest.continuation, None);
+
+		let empty = provider.prove_finality_range(0, 0).unwrap();
+		assert!(empty.fragments.is_empty());
+		assert_eq!(empty.continuation, None);
+	}
+
+	#[test]
+	fn finality_proof_check_against_local_runtime_detects_mismatch() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(3).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2), header(3)],
+		};
+
+		let runtime = crate::tests::TestApi::new(auth.clone());
+		let proof = verify_against_local_runtime::<Block, _, TestJustification>(
+			&runtime,
+			1,
+			finality_proof.encode(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		// The local runtime disagrees with the set that signed the proof.
+		let runtime = crate::tests::TestApi::new(vec![(AuthorityId::from_slice(&[4u8; 32]), 1)]);
+		let err = verify_against_local_runtime::<Block, _, TestJustification>(
+			&runtime,
+			1,
+			finality_proof.encode(),
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("local runtime"));
+	}
+
+	// SCALE encoded finality proofs, any change to these is a breaking change of the wire format.
+	const GOLDEN_PROOF_WITHOUT_HEADERS: &str = concat!(
+		// block
+		"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+		// justification
+		"0c010203",
+		// unknown_headers
+		"00",
+	);
+	const GOLDEN_PROOF_WITH_HEADERS: &str = concat!(
+		// block
+		"bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+		// justification
+		"042a",
+		// unknown_headers: parent hash, compact number, state root, extrinsics root and digest
+		// of each header
+		"08",
+		"1111111111111111111111111111111111111111111111111111111111111111",
+		"04",
+		"2222222222222222222222222222222222222222222222222222222222222222",
+		"3333333333333333333333333333333333333333333333333333333333333333",
+		"00",
+		"4444444444444444444444444444444444444444444444444444444444444444",
+		"08",
+		"5555555555555555555555555555555555555555555555555555555555555555",
+		"6666666666666666666666666666666666666666666666666666666666666666",
+		"04000401",
+	);
+
+	#[test]
+	fn finality_proof_encoding_matches_golden_vectors() {
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let check = |golden: &str, expected: FinalityProof| {
+			let encoded = (0..golden.len())
+				.step_by(2)
+				.map(|i| u8::from_str_radix(&golden[i..i + 2], 16).unwrap())
+				.collect::<Vec<_>>();
+			let decoded = FinalityProof::decode(&mut &encoded[..]).unwrap();
+			assert_eq!(decoded, expected);
+			assert_eq!(decoded.encode(), encoded);
+		};
+
+		check(
+			GOLDEN_PROOF_WITHOUT_HEADERS,
+			FinalityProof {
+				block: H256::repeat_byte(0xaa),
+				justification: vec![1, 2, 3],
+				unknown_headers: Vec::new(),
+			},
+		);
+		check(
+			GOLDEN_PROOF_WITH_HEADERS,
+			FinalityProof {
+				block: H256::repeat_byte(0xbb),
+				justification: vec![42],
+				unknown_headers: vec![
+					Header::new(
+						1,
+						H256::repeat_byte(0x33),
+						H256::repeat_byte(0x22),
+						H256::repeat_byte(0x11),
+						Default::default(),
+					),
+					Header::new(
+						2,
+						H256::repeat_byte(0x66),
+						H256::repeat_byte(0x55),
+						H256::repeat_byte(0x44),
+						Digest { logs: vec![DigestItem::Other(vec![1])] },
+					),
+				],
+			},
+		);
+	}
+
+	#[derive(Clone)]
+	struct MockClock(Arc<Mutex<Instant>>);
+
+	impl Default for MockClock {
+		fn default() -> Self {
+			MockClock(Arc::new(Mutex::new(Instant::now())))
+		}
+	}
+
+	impl MockClock {
+		fn advance(&self, duration: Duration) {
+			*self.0.lock() += duration;
+		}
+	}
+
+	impl Clock for MockClock {
+		fn now(&self) -> Instant {
+			*self.0.lock()
+		}
+	}
+
+	#[test]
+	fn cached_proofs_older_than_max_age_are_regenerated() {
+		let backend = test_backend();
+		let store_best_justification = |round| {
+			let justification = GrandpaJustification::from_commit(
+				&Arc::new(InMemoryBlockchain::<Block>::new()),
+				round,
+				crate::Commit::<Block> {
+					target_hash: header(3).hash(),
+					target_number: 3,
+					precommits: Vec::new(),
+				},
+			)
+			.unwrap();
+			crate::aux_schema::update_best_justification(&justification, |insert| {
+				backend.insert_aux(insert, &[])
+			})
+			.unwrap();
+			justification.encode()
+		};
+
+		let clock = MockClock::default();
+		let config = FinalityProofConfig {
+			max_cached_proof_age: Some(Duration::from_secs(10)),
+			..Default::default()
+		};
+		let provider = test_provider(backend.clone(), vec![(0, 1)].into())
+			.with_config(config)
+			.with_proof_cache(8)
+			.with_clock(clock.clone());
+
+		let justification = store_best_justification(1);
+		let proof = provider.prove_finality(2).unwrap().unwrap();
+		assert_eq!(FinalityProof::decode(&mut &proof[..]).unwrap().justification, justification);
+
+		// A newer best justification is only served once the cached proof is stale.
+		let justification = store_best_justification(2);
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(provider.prove_finality(2).unwrap().unwrap(), proof);
+
+		clock.advance(Duration::from_secs(1));
+		let proof = provider.prove_finality(2).unwrap().unwrap();
+		assert_eq!(FinalityProof::decode(&mut &proof[..]).unwrap().justification, justification);
+	}
+
+	#[test]
+	fn justified_header_is_header_of_last_block_of_set() {
+		let provider = test_provider(test_backend(), vec![(0, 1), (1, 3)].into());
+
+		assert_eq!(provider.justified_header(2).unwrap(), Some(header(3)));
+		assert_eq!(provider.justified_header(1).unwrap(), Some(header(1)));
+		assert_eq!(provider.justified_header(3).unwrap(), Some(header(3)));
+		assert!(matches!(
+			provider.justified_header(4),
+			Err(FinalityProofError::BlockNotYetFinalized)
+		));
+	}
+
+	#[test]
+	fn justification_coverage_bitmap_marks_missing_justifications() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[5]);
+
+		// Blocks 2, 4 and 6 were finalized without a justification.
+		let mut authority_set_changes = AuthoritySetChanges::empty();
+		for (set_id, last_block) in (0..6).zip(1..=6) {
+			authority_set_changes.append(set_id, last_block);
+		}
+
+		let provider = test_provider(backend, authority_set_changes);
+		let bitmap = provider.justification_coverage_bitmap(1, 6).unwrap();
+		let bits = (0..bitmap.len()).map(|i| bitmap[i]).collect::<Vec<_>>();
+
+		// Set 6 is the latest set, its last block is not known yet.
+		assert_eq!(bits, vec![false, true, false, true, false, false]);
+		assert!(provider.justification_coverage_bitmap(3, 2).unwrap().is_empty());
+
+		// The range is clamped to the latest set.
+		assert_eq!(provider.justification_coverage_bitmap(1, SetId::MAX).unwrap(), bitmap);
+	}
+
+	/// Aggregates signatures by XOR-ing a hash of each signer and the message, only meant to
+	/// exercise the aggregated justification verification.
+	#[cfg(feature = "aggregated-justifications")]
+	struct TestAggregateScheme;
+
+	#[cfg(feature = "aggregated-justifications")]
+	impl TestAggregateScheme {
+		fn sign(signers: &[AuthorityId], message: &[u8]) -> Vec<u8> {
+			let mut signature = [0u8; 32];
+			for signer in signers {
+				let share = blake2_256(&(signer, message).encode());
+				signature.iter_mut().zip(share.iter()).for_each(|(s, share)| *s ^= share);
+			}
+			signature.to_vec()
+		}
+	}
+
+	#[cfg(feature = "aggregated-justifications")]
+	impl AggregateSignatureScheme for TestAggregateScheme {
+		fn verify_aggregate(signers: &[AuthorityId], message: &[u8], signature: &[u8]) -> bool {
+			Self::sign(signers, message) == signature
+		}
+	}
+
+	#[cfg(feature = "aggregated-justifications")]
+	#[test]
+	fn aggregated_justification_is_verified_against_authorities() {
+		type Justification = AggregatedJustification<Header, TestAggregateScheme>;
+
+		let authorities = (1..=3u8)
+			.map(|i| (AuthorityId::from_slice(&[i; 32]), 1u64))
+			.collect::<AuthorityList>();
+		let target = (3, header(3).hash());
+		let message = finality_grandpa::Message::Precommit(finality_grandpa::Precommit {
+			target_hash: target.1,
+			target_number: target.0,
+		});
+		let payload = sp_finality_grandpa::localized_payload(1, 0, &message);
+		let ids = authorities.iter().map(|(id, _)| id.clone()).collect::<Vec<_>>();
+
+		let aggregate = |signers: &[AuthorityId], bitfield| {
+			let signature = TestAggregateScheme::sign(signers, &payload);
+			Justification::new(1, target, signature, vec![bitfield])
+		};
+
+		let justification = aggregate(&ids, 0b111);
+		justification.verify(0, &authorities).unwrap();
+		assert_eq!(justification.signed_weight(&authorities), Some(3));
+
+		// Signed for a different set.
+		justification.verify(1, &authorities).unwrap_err();
+
+		// The signature doesn't match the signers.
+		aggregate(&ids[..2], 0b111).verify(0, &authorities).unwrap_err();
+
+		// Two out of three authorities are not a supermajority.
+		aggregate(&ids[..2], 0b011).verify(0, &authorities).unwrap_err();
+
+		// Signers outside of the authority set.
+		aggregate(&ids, 0b1111).verify(0, &authorities).unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_trust_root_verifies_proof() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		// The authorities of set 0 are the ones of the shared authority set of the test provider.
+		let authorities = (1..=2u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+
+		// Block 4 ends set 0 and signals a change to the authorities of set 1 with the given
+		// delay, block 6 ends set 1.
+		let provider = |delay: u64| {
+			let backend = test_backend();
+			let blockchain = backend.blockchain();
+			let mut parent_hash = header(3).hash();
+			for number in 4..=6u64 {
+				let digest = if number == 4 {
+					let change = ConsensusLog::ScheduledChange(ScheduledChange {
+						next_authorities: authorities[1].clone(),
+						delay,
+					});
+					Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] }
+				} else {
+					Default::default()
+				};
+				let justification = match number {
+					4 => Some(TestJustification((0, authorities[0].clone()), vec![])),
+					6 => Some(TestJustification((1, authorities[1].clone()), vec![])),
+					_ => None,
+				}
+				.map(|justification| Justifications::from((ID, justification.encode())));
+
+				let header = Header::new(
+					number,
+					H256::from_low_u64_be(0),
+					H256::from_low_u64_be(0),
+					parent_hash,
+					digest,
+				);
+				parent_hash = header.hash();
+				blockchain
+					.insert(header.hash(), header, justification, None, NewBlockState::Final)
+					.unwrap();
+			}
+
+			test_provider(backend, vec![(0, 4), (1, 6)].into())
+		};
+
+		// A delayed change signalled by block 4 isn't the one enacted there.
+		assert!(matches!(
+			provider(1).prove_finality_with_trust_root(5),
+			Err(FinalityProofError::AuthoritiesNotFound)
+		));
+
+		let provider = provider(0);
+		for (block, set_id) in vec![(2, 0), (5, 1)] {
+			let (proof, root) = provider.prove_finality_with_trust_root(block).unwrap().unwrap();
+			let expected_authorities = authorities[set_id as usize].clone();
+			assert_eq!(root, TrustRoot { set_id, authorities: expected_authorities });
+			check_finality_proof::<Header, TestJustification>(
+				root.set_id,
+				root.authorities,
+				proof.encode(),
+			)
+			.unwrap();
+		}
+	}
+
+	#[test]
+	fn finality_proof_check_resolves_hash_only_headers() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(3).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2), header(3)],
+		};
+		let hash_only_proof =
+			FinalityProofV2::from(finality_proof.clone()).with_hash_only_headers();
+		assert_eq!(
+			hash_only_proof.unknown_headers,
+			ProofHeaders::HashOnly(vec![header(2).hash(), header(3).hash()]),
+		);
+
+		let check = |header_store: &HashMap<H256, Header>| {
+			check_finality_proof_v2_with_header_store::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				hash_only_proof.encode(),
+				|_| None,
+				|hash| header_store.get(hash).cloned(),
+			)
+		};
+
+		let mut header_store =
+			(0..4).map(|n| (header(n).hash(), header(n))).collect::<HashMap<_, _>>();
+		assert_eq!(check(&header_store).unwrap(), finality_proof);
+
+		// Fails if a header is not known locally.
+		header_store.remove(&header(2).hash());
+		check(&header_store).unwrap_err();
+
+		// Fails if the headers don't form a chain.
+		let chain_gap = FinalityProofV2::<Header> {
+			unknown_headers: ProofHeaders::HashOnly(vec![header(1).hash(), header(3).hash()]),
+			..hash_only_proof.clone()
+		};
+		check_finality_proof_v2_with_header_store::<Header, TestJustification>(
+			1,
+			auth.clone(),
+			chain_gap.encode(),
+			|_| None,
+			|hash| header_store.get(hash).cloned(),
+		)
+		.unwrap_err();
+
+		// Hash only headers can't be checked without a header store.
+		check_finality_proof_v2::<Header, TestJustification>(
+			1,
+			auth.clone(),
+			hash_only_proof.encode(),
+			|_| None,
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn set_change_proof_with_followup_uses_next_set() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let authorities = (1..=2u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+
+		// Block 4 ends set 0 and block 8 ends set 1.
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		for number in 4..=8u64 {
+			let justification = match number {
+				4 => Some(TestJustification((0, authorities[0].clone()), vec![])),
+				8 => Some(TestJustification((1, authorities[1].clone()), vec![])),
+				_ => None,
+			}
+			.map(|justification| Justifications::from((ID, justification.encode())));
+			let header = header(number);
+			blockchain
+				.insert(header.hash(), header, justification, None, NewBlockState::Final)
+				.unwrap();
+		}
+
+		let provider = test_provider(backend, vec![(0, 4), (1, 8)].into());
+		let (set_change, followup) =
+			provider.prove_set_change_with_followup(0, 2).unwrap().unwrap();
+
+		assert_eq!(set_change.requires_set_id, 0);
+		assert_eq!(set_change.proof.unknown_headers, vec![header(4)]);
+		check_finality_proof::<Header, TestJustification>(
+			0,
+			authorities[0].clone(),
+			set_change.proof.encode(),
+		)
+		.unwrap();
+
+		// The follow-up proof of block 6 is signed by the new set.
+		let followup = followup.unwrap();
+		assert_eq!(followup.requires_set_id, 1);
+		assert_eq!(followup.proof.unknown_headers, vec![header(7)]);
+		let check_followup = |set_id, authorities: &AuthorityList| {
+			check_finality_proof::<Header, TestJustification>(
+				set_id,
+				authorities.clone(),
+				followup.proof.encode(),
+			)
+		};
+		check_followup(1, &authorities[1]).unwrap();
+		check_followup(0, &authorities[0]).unwrap_err();
+
+		// The follow-up doesn't go past the last block of the next set.
+		let (_, followup) = provider.prove_set_change_with_followup(0, 6).unwrap().unwrap();
+		let followup = followup.unwrap();
+		assert_eq!(followup.requires_set_id, 1);
+		assert_eq!(followup.justified_number, 8);
+		assert!(followup.proof.unknown_headers.is_empty());
+
+		// Blocks that aren't finalized yet can't be followed up on.
+		let provider = test_provider(test_backend(), vec![(0, 1)].into());
+		let (_, followup) = provider.prove_set_change_with_followup(0, 4).unwrap().unwrap();
+		assert!(followup.is_none());
+	}
+
+	#[test]
+	fn finality_proof_check_against_committed_authority_set_root() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let finality_proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2)],
+		};
+		let root = authority_set_commitment(1, &auth);
+
+		let proof = check_finality_proof_with_authority_root::<Header, TestJustification>(
+			root,
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+		)
+		.unwrap();
+		assert_eq!(proof, finality_proof);
+
+		// The proof is valid for these authorities, but they aren't the committed ones.
+		let other_auth = vec![(AuthorityId::from_slice(&[4u8; 32]), 1u64)];
+		let other_root = authority_set_commitment(1, &other_auth);
+		let err = check_finality_proof_with_authority_root::<Header, TestJustification>(
+			other_root,
+			1,
+			auth.clone(),
+			finality_proof.encode(),
+		)
+		.unwrap_err();
+		assert!(err.to_string().contains("committed authority set root"));
+
+		// The commitment covers the set id.
+		check_finality_proof_with_authority_root::<Header, TestJustification>(
+			root,
+			2,
+			auth,
+			finality_proof.encode(),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn served_proof_hashes_are_logged() {
+		let backend = test_backend();
+		let config = FinalityProofConfig { log_served_proof_hashes: true, ..Default::default() };
+		let provider = test_provider(backend, vec![(0, 3)].into()).with_config(config);
+
+		let serve = || {
+			let recorder = SpanRecorder::default();
+			let proof = tracing::subscriber::with_default(recorder.clone(), || {
+				provider.prove_finality(1).unwrap().unwrap()
+			});
+			let events = recorder.events.lock();
+			let EventFields(fields) = events
+				.iter()
+				.find(|EventFields(fields)| fields.contains_key("proof_hash"))
+				.cloned()
+				.unwrap();
+			(proof, fields["block"].clone(), fields["proof_hash"].clone())
+		};
+
+		let (proof, block, proof_hash) = serve();
+		assert_eq!(block, "1");
+		assert_eq!(proof_hash, format!("{}", HexDisplay::from(&blake2_256(&proof))));
+
+		// Identical requests are logged with the same hash.
+		assert_eq!(serve(), (proof, block, proof_hash));
+	}
+
+	#[test]
+	fn finality_proof_embedded_set_change_is_checked_against_digest() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let change = ScheduledChange {
+			next_authorities: vec![(AuthorityId::from_slice(&[4u8; 32]), 1u64)],
+			delay: 0u64,
+		};
+		let log = ConsensusLog::ScheduledChange(change.clone());
+		let change_header = Header::new(
+			3,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			header(2).hash(),
+			Digest { logs: vec![DigestItem::Consensus(ID, log.encode())] },
+		);
+		let finality_proof = FinalityProof {
+			block: change_header.hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2), change_header],
+		};
+		let check = |proof: &FinalityProofV2<Header>| {
+			check_finality_proof_v2::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof.encode(),
+				|_| None,
+			)
+		};
+
+		let proof = FinalityProofV2::from(finality_proof.clone()).with_embedded_set_change();
+		assert_eq!(proof.set_change, Some(ProofSetChange::Scheduled(change.clone())));
+		assert_eq!(check(&proof).unwrap(), finality_proof);
+
+		// A tampered change is rejected.
+		let mut tampered_change = change;
+		tampered_change.next_authorities = auth.clone();
+		let tampered = FinalityProofV2 {
+			set_change: Some(ProofSetChange::Scheduled(tampered_change)),
+			..proof.clone()
+		};
+		check(&tampered).unwrap_err();
+
+		// As is a change embedded in a proof whose justified block doesn't signal one.
+		let mut no_change = FinalityProofV2::from(FinalityProof {
+			block: header(3).hash(),
+			unknown_headers: vec![header(2), header(3)],
+			..finality_proof
+		});
+		assert_eq!(no_change.clone().with_embedded_set_change().set_change, None);
+		no_change.set_change = proof.set_change;
+		check(&no_change).unwrap_err();
+	}
+
+	#[test]
+	fn batch_verification_reports_each_item() {
+		let auth1 = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let auth2 = vec![(AuthorityId::from_slice(&[2u8; 32]), 1u64)];
+		let proof = |set_id, auth: &AuthorityList| FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((set_id, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2)],
+		};
+
+		let results = verify_batch::<Header, TestJustification>(
+			vec![
+				(proof(1, &auth1).encode(), 1, auth1.clone()),
+				(proof(5, &auth2).encode(), 5, auth2.clone()),
+				// Signed by another set.
+				(proof(1, &auth1).encode(), 1, auth2.clone()),
+				(vec![42], 5, auth2.clone()),
+			],
+			VerificationBudget::default(),
+		);
+
+		assert_eq!(results.len(), 4);
+		assert_eq!(results[0].as_ref().unwrap(), &proof(1, &auth1));
+		assert_eq!(results[1].as_ref().unwrap(), &proof(5, &auth2));
+		assert!(matches!(results[2], Err(VerifyError::InvalidFragment(2, _))));
+		assert!(matches!(results[3], Err(VerifyError::InvalidFragment(3, _))));
+	}
+
+	#[test]
+	fn sparse_finality_proof_omits_known_headers() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let just6 = TestJustification((0, auth.clone()), vec![]).encode();
+		let just6 = Some(Justifications::from((ID, just6)));
+		blockchain.insert(header(4).hash(), header(4), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final).unwrap();
+		blockchain.insert(header(6).hash(), header(6), just6, None, NewBlockState::Final).unwrap();
+
+		let provider = test_provider(backend, vec![(0, 6)].into()).with_config(FinalityProofConfig {
+			include_justified_header: true,
+			..Default::default()
+		});
+		let full_proof = provider.prove_finality_detailed(1).unwrap().unwrap().proof;
+		assert_eq!(full_proof.unknown_headers, (2..=6).map(header).collect::<Vec<_>>());
+
+		let known = vec![3, 5].into_iter().collect::<BTreeSet<_>>();
+		let sparse_proof = provider.prove_finality_sparse(1, &known).unwrap().unwrap();
+		assert_eq!(sparse_proof.unknown_headers, vec![header(2), header(4), header(6)]);
+
+		let check = |known_headers: &BTreeMap<u64, Header>| {
+			check_sparse_finality_proof::<Header, TestJustification>(
+				0,
+				auth.clone(),
+				1,
+				known_headers,
+				sparse_proof.encode(),
+			)
+		};
+
+		let mut known_headers = known.iter().map(|n| (*n, header(*n))).collect::<BTreeMap<_, _>>();
+		assert_eq!(check(&known_headers).unwrap(), full_proof);
+
+		// The caller must actually know the headers it left out.
+		known_headers.remove(&5);
+		check(&known_headers).unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_ignores_non_canonical_best_justification() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let just4 = Some(Justifications::from((GRANDPA_ENGINE_ID, vec![4])));
+		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final).unwrap();
+
+		// The best justification targets a block #3 that isn't the canonical one.
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: H256::from_low_u64_be(42),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 1)]);
+
+		let provider = test_provider(backend.clone(), authority_set_changes.clone())
+			.with_config(FinalityProofConfig {
+				no_justification_policy: NoJustificationPolicy::ReturnError,
+				..Default::default()
+			});
+		assert!(matches!(
+			provider.prove_finality_detailed(2),
+			Err(FinalityProofError::JustificationNotFound)
+		));
+
+		// Without a usable best justification the proof can fall back to the finalized head.
+		let provider = test_provider(backend, authority_set_changes)
+			.with_config(FinalityProofConfig {
+				no_justification_policy: NoJustificationPolicy::TryFinalizedHead,
+				..Default::default()
+			});
+		let proof = provider.prove_finality_detailed(2).unwrap().unwrap();
+		assert_eq!(proof.justified_number, 4);
+		assert_eq!(proof.proof.justification, vec![4]);
+		assert!(proof.from_best_justification);
+	}
+
+	#[test]
+	fn finality_proof_check_rejects_too_many_headers() {
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let check = |proof: Vec<u8>, max_headers| {
+			check_finality_proof_with_config::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof,
+				&FinalityProofCheckConfig { max_headers: Some(max_headers), ..Default::default() },
+			)
+		};
+
+		let proof = FinalityProof {
+			block: header(3).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2), header(3)],
+		};
+		assert_eq!(check(proof.encode(), 2).unwrap(), proof);
+		assert!(check(proof.encode(), 1).unwrap_err().to_string().contains("at most 1"));
+
+		// Only the length prefix of the headers is read, the headers themselves are missing.
+		let mut truncated = (header(3).hash(), proof.justification.clone()).encode();
+		truncated.extend(Compact(1_000_000u32).encode());
+		assert!(check(truncated, 2).unwrap_err().to_string().contains("at most 2"));
+	}
+
+	#[test]
+	fn justified_block_for_returns_covering_set_and_justified_block() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[5]);
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 3), (2, 5)].into());
+		assert_eq!(provider.justified_block_for(0), Some((0, 1)));
+		assert_eq!(provider.justified_block_for(1), Some((0, 1)));
+		assert_eq!(provider.justified_block_for(2), Some((1, 3)));
+		assert_eq!(provider.justified_block_for(3), Some((1, 3)));
+		assert_eq!(provider.justified_block_for(4), Some((2, 5)));
+		assert_eq!(provider.justified_block_for(5), Some((2, 5)));
+		// Block 6 is the finalized head of the latest set, and there is no best justification.
+		assert_eq!(provider.justified_block_for(6), None);
+	}
+
+	#[test]
+	fn finality_proof_checked_against_authority_iterator() {
+		let auth = (0..4u8)
+			.map(|i| (AuthorityId::from_slice(&[i; 32]), 1u64))
+			.collect::<AuthorityList>();
+		let proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2)],
+		};
+
+		let from_iter = check_finality_proof::<Header, TestJustification>(
+			1,
+			authority_list((0..4u8).map(|i| (AuthorityId::from_slice(&[i; 32]), 1u64))),
+			proof.encode(),
+		)
+		.unwrap();
+		let from_list =
+			check_finality_proof::<Header, TestJustification>(1, auth, proof.encode()).unwrap();
+		assert_eq!(from_iter, from_list);
+
+		check_finality_proof::<Header, TestJustification>(
+			1,
+			authority_list((0..3u8).map(|i| (AuthorityId::from_slice(&[i; 32]), 1u64))),
+			proof.encode(),
+		)
+		.unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_checked_against_raw_authorities() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((1, auth), vec![7]).encode(),
+			unknown_headers: vec![header(2)],
+		};
+		let check = |raw_authorities: Vec<([u8; 32], u64)>| {
+			check_finality_proof::<Header, TestJustification>(
+				1,
+				authority_list(raw_authorities),
+				proof.encode(),
+			)
+		};
+
+		assert_eq!(check(vec![([3u8; 32], 1)]).unwrap(), proof);
+		check(vec![([4u8; 32], 1)]).unwrap_err();
+	}
+
+	#[test]
+	fn finality_proof_from_peer_head_omits_headers_below_common_ancestor() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		// The peer followed a fork of block 4, up to block 8.
+		let mut fork = vec![header(4)];
+		for number in 5..=8 {
+			let header = Header::new(
+				number,
+				H256::from_low_u64_be(1),
+				H256::from_low_u64_be(0),
+				fork.last().unwrap().hash(),
+				Default::default(),
+			);
+			blockchain
+				.insert(header.hash(), header.clone(), None, None, NewBlockState::Normal)
+				.unwrap();
+			fork.push(header);
+		}
+
+		let provider = test_provider(backend.clone(), vec![(0, 1), (1, 6)].into());
+		let headers = |peer_head_hash| {
+			let proof = provider.prove_finality_from_peer_head(2, peer_head_hash).unwrap().unwrap();
+			assert_eq!(proof.proof.block, header(6).hash());
+			proof.proof.unknown_headers
+		};
+
+		assert_eq!(headers(fork[1].hash()), vec![header(5)]);
+		assert_eq!(headers(fork[4].hash()), vec![header(5)]);
+		// A peer on the canonical chain below the requested block needs all the headers.
+		assert_eq!(headers(header(1).hash()), vec![header(3), header(4), header(5)]);
+		// As does a peer whose head is unknown.
+		assert_eq!(headers(H256::repeat_byte(42)).len(), 3);
+		// A peer that knows the justified block needs none.
+		assert!(headers(header(6).hash()).is_empty());
+
+		// A head further than the maximum number of headers from a finalized block is treated as
+		// unknown.
+		let bounded = test_provider(backend, vec![(0, 1), (1, 6)].into())
+			.with_config(FinalityProofConfig { max_unknown_headers: 3, ..Default::default() });
+		let proof = bounded.prove_finality_from_peer_head(2, fork[4].hash()).unwrap().unwrap();
+		assert_eq!(proof.proof.unknown_headers, vec![header(3), header(4), header(5)]);
+	}
+
+	#[test]
+	fn best_commit_targets_justified_block() {
+		let backend = test_backend();
+		let provider = test_provider(backend.clone(), vec![(0, 1)].into());
+		assert!(provider.best_commit().is_none());
+
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let commit = provider.best_commit().unwrap();
+		assert_eq!((commit.target_number, commit.target_hash), (3, header(3).hash()));
+		assert_eq!(commit, justification.commit);
+	}
+
+	#[test]
+	fn gap_to_proof_counts_blocks_from_known_finalized_to_justified_block() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 6)].into());
+
+		// Block 4 is proven by the justification of block 6.
+		assert_eq!(provider.gap_to_proof(4, 1), Some(5));
+		assert_eq!(provider.gap_to_proof(4, 3), Some(3));
+		assert_eq!(provider.gap_to_proof(4, 6), Some(0));
+		assert_eq!(provider.gap_to_proof(7, 3), None);
+	}
+
+	#[test]
+	fn only_cached_proofs_of_latest_set_expire() {
+		let backend = test_backend();
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let clock = MockClock::default();
+		let config = FinalityProofConfig {
+			max_cached_proof_age: Some(Duration::from_secs(10)),
+			..Default::default()
+		};
+		let provider = test_provider(backend, vec![(0, 1)].into())
+			.with_config(config)
+			.with_proof_cache(8)
+			.with_clock(clock.clone());
+
+		// Block 1 is proven by the boundary justification of set 0, block 2 by the best
+		// justification of the latest set.
+		provider.prove_finality(1).unwrap().unwrap();
+		provider.prove_finality(2).unwrap().unwrap();
+
+		let cache = provider.proof_cache.as_ref().unwrap();
+		let cached = |number| cache.lock().entries.contains_key(&number);
+		let get = |number| {
+			cache.lock().get(number, header(number).hash(), clock.now()).map(|(proof, _)| proof)
+		};
+
+		clock.advance(Duration::from_secs(11));
+		assert!(cached(1) && cached(2));
+		assert!(get(1).is_some());
+		assert!(get(2).is_none());
+		assert!(cached(1) && !cached(2));
+	}
+
+	#[test]
+	fn parallel_verification_of_justification_signatures() {
+		use sp_keyring::Ed25519Keyring::{Alice, Bob, Charlie};
+
+		let authorities = keyring_authorities(&[Alice, Bob, Charlie]);
+		let proof = |justification: &GrandpaJustification<Block>| {
+			FinalityProof {
+				block: header(3).hash(),
+				justification: justification.encode(),
+				unknown_headers: vec![header(3)],
+			}
+			.encode()
+		};
+		let check = |proof: Vec<u8>, verify_signatures_in_parallel| {
+			check_finality_proof_with_config::<Header, GrandpaJustification<Block>>(
+				0,
+				authorities.clone(),
+				proof,
+				&FinalityProofCheckConfig { verify_signatures_in_parallel, ..Default::default() },
+			)
+		};
+
+		let mut justification = signed_justification(1, &header(3), &[Alice, Bob, Charlie]);
+		let valid = proof(&justification);
+		assert_eq!(check(valid.clone(), true).unwrap(), check(valid, false).unwrap());
+
+		// Bob signed a different payload.
+		justification.commit.precommits[1].signature = Bob.sign(b"invalid").into();
+		let invalid = proof(&justification);
+		assert!(check(invalid.clone(), true).unwrap_err().to_string().contains("precommit #1"));
+		assert!(check(invalid, false).unwrap_err().to_string().contains("precommit #1"));
+	}
+
+	#[test]
+	fn authority_set_changes_are_rebuilt_from_finalized_chain() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		// Block 4 enacts set 1 immediately, block 5 schedules set 2 to be enacted at block 6 and
+		// block 7 schedules set 3 to be enacted at the not yet finalized block 9. The blocks
+		// enacting a change are justified.
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let mut parent_hash = header(3).hash();
+		for number in 4..=8u64 {
+			let delay = match number {
+				4 => Some(0),
+				5 => Some(1),
+				7 => Some(2),
+				_ => None,
+			};
+			let logs = delay
+				.map(|delay| {
+					let change = ConsensusLog::ScheduledChange(ScheduledChange {
+						next_authorities: vec![(AuthorityId::from_slice(&[2; 32]), 1)],
+						delay,
+					});
+					DigestItem::Consensus(ID, change.encode())
+				})
+				.into_iter()
+				.collect();
+			let header = Header::new(
+				number,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				parent_hash,
+				Digest { logs },
+			);
+			parent_hash = header.hash();
+			let justifications = match number {
+				4 | 6 => Some(Justifications::from((ID, vec![number as u8]))),
+				_ => None,
+			};
+			blockchain
+				.insert(header.hash(), header, justifications, None, NewBlockState::Final)
+				.unwrap();
+		}
+
+		let expected: AuthoritySetChanges<u64> = vec![(0, 4), (1, 6)].into();
+		let provider = test_provider(backend, AuthoritySetChanges::empty());
+		assert_eq!(provider.rebuild_authority_set_changes().unwrap(), expected);
+
+		// A set boundary without a justification can't be trusted.
+		let backend = test_backend();
+		let change = ConsensusLog::ScheduledChange(ScheduledChange {
+			next_authorities: vec![(AuthorityId::from_slice(&[2; 32]), 1)],
+			delay: 0u64,
+		});
+		let header = Header::new(
+			4,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			header(3).hash(),
+			Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] },
+		);
+		backend
+			.blockchain()
+			.insert(header.hash(), header, None, None, NewBlockState::Final)
+			.unwrap();
+		let provider = test_provider(backend, AuthoritySetChanges::empty());
+		assert!(matches!(
+			provider.rebuild_authority_set_changes(),
+			Err(FinalityProofError::JustificationNotFound)
+		));
+	}
+
+	/// Generates an archive while it's being read, holding a single fragment at a time.
+	struct GeneratedArchive<I> {
+		fragments: I,
+		buffer: Vec<u8>,
+		position: usize,
+		fragments_read: usize,
+		bytes_read: usize,
+		max_buffered: usize,
+	}
+
+	impl<I: Iterator<Item = DetailedFinalityProof<Header>>> std::io::Read for GeneratedArchive<I> {
+		fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+			if self.position == self.buffer.len() {
+				let fragment = match self.fragments.next() {
+					Some(fragment) => fragment,
+					None => return Ok(0),
+				};
+				self.buffer.clear();
+				self.position = 0;
+				write_archive_entry(&mut self.buffer, &fragment)?;
+				self.fragments_read += 1;
+				self.max_buffered = self.max_buffered.max(self.buffer.len());
+			}
+
+			let len = buf.len().min(self.buffer.len() - self.position);
+			buf[..len].copy_from_slice(&self.buffer[self.position..self.position + len]);
+			self.position += len;
+			self.bytes_read += len;
+			Ok(len)
+		}
+	}
+
+	#[test]
+	fn large_archive_is_verified_while_streaming() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		const FRAGMENTS: u64 = 10_000;
+
+		let authorities = |set_id: u64| {
+			let mut id = [0u8; 32];
+			id[..8].copy_from_slice(&set_id.to_le_bytes());
+			vec![(AuthorityId::from_slice(&id), 1u64)]
+		};
+		// The fragment ending set `set_id`, signed by the set `signed_by`.
+		let fragment = move |set_id: u64, signed_by: u64| {
+			let change = ConsensusLog::ScheduledChange(ScheduledChange {
+				next_authorities: authorities(set_id + 1),
+				delay: 0u64,
+			});
+			let header = Header::new(
+				set_id + 1,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				Default::default(),
+				Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] },
+			);
+			DetailedFinalityProof {
+				proof: FinalityProof {
+					block: header.hash(),
+					justification: TestJustification((set_id, authorities(signed_by)), vec![])
+						.encode(),
+					unknown_headers: vec![header],
+				},
+				requires_set_id: set_id,
+				justified_number: set_id + 1,
+				from_best_justification: false,
+			}
+		};
+		let archive = |fragments| GeneratedArchive {
+			fragments,
+			buffer: Vec::new(),
+			position: 0,
+			fragments_read: 0,
+			bytes_read: 0,
+			max_buffered: 0,
+		};
+
+		let mut valid = archive((0..FRAGMENTS).map(|set_id| fragment(set_id, set_id)));
+		let current =
+			verify_archive_streaming::<Block, TestJustification>(&mut valid, 0, authorities(0))
+				.unwrap();
+		assert_eq!(current, (FRAGMENTS, authorities(FRAGMENTS)));
+		assert_eq!(valid.fragments_read, FRAGMENTS as usize);
+		// Only a single fragment is held in memory at a time, a tiny fraction of the archive.
+		assert!(valid.max_buffered * 1000 < valid.bytes_read);
+
+		// Verification stops at the first invalid fragment, without reading the rest.
+		let mut invalid = archive((0..FRAGMENTS).map(|set_id| match set_id {
+			10 => fragment(set_id, 0),
+			_ => fragment(set_id, set_id),
+		}));
+		assert!(matches!(
+			verify_archive_streaming::<Block, TestJustification>(&mut invalid, 0, authorities(0)),
+			Err(VerifyError::InvalidFragment(10, _)),
+		));
+		assert_eq!(invalid.fragments_read, 11);
+	}
+
+	#[test]
+	fn finality_proof_v2_states_prerequisite_set_changes() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 3), (2, 6)].into());
+		let prerequisite_sets = |block, trusted_set_id| {
+			provider
+				.prove_finality_v2_for_trusted_set(block, trusted_set_id)
+				.unwrap()
+				.unwrap()
+				.prerequisite_sets
+		};
+
+		// Block 5 is proven by set 2.
+		assert_eq!(prerequisite_sets(5, 0), Some(2));
+		assert_eq!(prerequisite_sets(5, 1), Some(1));
+		assert_eq!(prerequisite_sets(5, 2), Some(0));
+		// Block 2 is proven by set 1.
+		assert_eq!(prerequisite_sets(2, 0), Some(1));
+		// A client trusting a later set doesn't need any set change to be verified.
+		assert_eq!(prerequisite_sets(2, 2), Some(0));
+
+		let proof = provider.prove_finality_v2_for_trusted_set(5, 0).unwrap().unwrap();
+		assert_eq!(FinalityProofV2::<Header>::decode(&mut &proof.encode()[..]).unwrap(), proof);
+
+		// Without a trusted set the count is unknown.
+		assert_eq!(provider.prove_finality_v2(5).unwrap().unwrap().prerequisite_sets, None);
+	}
+
+	#[test]
+	fn finality_proof_check_fetches_authorities_asynchronously() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let proof = |requires_set_id| DetailedFinalityProof {
+			proof: FinalityProof {
+				block: header(3).hash(),
+				justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+				unknown_headers: vec![header(2), header(3)],
+			},
+			requires_set_id,
+			justified_number: 3,
+			from_best_justification: false,
+		};
+
+		let fetched = Mutex::new(Vec::new());
+		let fetch_authorities = |set_id| {
+			fetched.lock().push(set_id);
+			let auth = auth.clone();
+			async move {
+				futures_timer::Delay::new(Duration::from_millis(10)).await;
+				if set_id == 1 {
+					Some(auth)
+				} else {
+					None
+				}
+			}
+		};
+		let check = |proof: DetailedFinalityProof<Header>| {
+			let check = check_finality_proof_async::<Header, TestJustification, _, _>(
+				proof.encode(),
+				&fetch_authorities,
+			);
+			futures::executor::block_on(check)
+		};
+
+		assert_eq!(check(proof(1)).unwrap(), proof(1).proof);
+		// The authorities of set 2 are unknown.
+		check(proof(2)).unwrap_err();
+		assert_eq!(*fetched.lock(), vec![1, 2]);
+	}
+
+	#[test]
+	fn finality_status_of_finalized_and_unfinalized_blocks() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		blockchain.insert(header(4).hash(), header(4), None, None, NewBlockState::Normal).unwrap();
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 3)].into());
+		let status = |finalized, covering_block| FinalityStatus { finalized, covering_block };
+
+		assert_eq!(provider.finality_status(1), status(true, Some(1)));
+		assert_eq!(provider.finality_status(2), status(true, Some(3)));
+		assert_eq!(provider.finality_status(3), status(true, Some(3)));
+		// Block 4 is imported but not finalized, and belongs to the latest set.
+		assert_eq!(provider.finality_status(4), status(false, None));
+		assert_eq!(provider.finality_status(5), status(false, None));
+	}
+
+	#[test]
+	fn legacy_digest_set_changes_are_parsed_in_legacy_mode() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let next_authorities = vec![(AuthorityId::from_slice(&[2u8; 32]), 1u64)];
+		let change = ScheduledChange { next_authorities, delay: 0u64 };
+		let proof_with_log = |log: Vec<u8>| {
+			let header = Header::new(
+				3,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				header(2).hash(),
+				Digest { logs: vec![DigestItem::Consensus(ID, log)] },
+			);
+			FinalityProof {
+				block: header.hash(),
+				justification: Vec::new(),
+				unknown_headers: vec![header],
+			}
+		};
+		let current = FinalityProofCheckConfig::default();
+		let legacy = FinalityProofCheckConfig { legacy_digest: true, ..Default::default() };
+
+		// Older versions logged the bare scheduled change.
+		let legacy_proof = proof_with_log(change.encode());
+		assert_eq!(
+			legacy_proof.signalled_set_change(&legacy),
+			Some(ProofSetChange::Scheduled(change.clone())),
+		);
+		assert_eq!(legacy_proof.signalled_set_change(&current), None);
+
+		let current_proof = proof_with_log(ConsensusLog::ScheduledChange(change.clone()).encode());
+		assert_eq!(
+			current_proof.signalled_set_change(&current),
+			Some(ProofSetChange::Scheduled(change)),
+		);
+		assert_eq!(current_proof.signalled_set_change(&legacy), None);
+	}
+
+	#[test]
+	fn finality_proof_for_peer_omits_headers_known_to_peer() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let provider = test_provider(backend, vec![(0, 6)].into());
+		let headers = |requested, peer_known_finalized| {
+			let proof = provider.prove_finality_for_peer(requested, peer_known_finalized);
+			let proof = proof.unwrap().unwrap();
+			assert_eq!(proof.proof.block, header(6).hash());
+			proof.proof.unknown_headers
+		};
+
+		// The peer already knows the headers up to its finalized block.
+		assert_eq!(headers(1, 4), vec![header(5)]);
+		assert_eq!(headers(1, 6), Vec::new());
+		// Headers are never collected below the requested block.
+		assert_eq!(headers(1, 0), (2..6).map(header).collect::<Vec<_>>());
+		assert_eq!(headers(4, 2), vec![header(5)]);
+
+		// The peer's finalized block must be below the justified block.
+		assert!(matches!(
+			provider.prove_finality_for_peer(1, 7),
+			Err(FinalityProofError::BlockNotAncestorOfJustified)
+		));
+	}
+
+	#[test]
+	fn verification_cost_counts_signatures_and_headers() {
+		use sp_keyring::Ed25519Keyring::{Alice, Bob, Charlie};
+
+		// The signatures aren't verified when estimating the cost.
+		let justification = signed_justification(1, &header(3), &[Alice, Bob, Charlie]);
+		let proof = FinalityProof {
+			block: header(3).hash(),
+			justification: justification.encode(),
+			unknown_headers: vec![header(2), header(3)],
+		};
+
+		let encoded = proof.encode();
+		assert_eq!(
+			estimate_verification_cost::<Block>(&encoded).unwrap(),
+			VerificationCost { signatures: 3, headers: 2, ancestry_headers: 0 },
+		);
+		estimate_verification_cost::<Block>(&encoded[..encoded.len() - 1]).unwrap_err();
+	}
+
+	#[test]
+	fn multi_finality_proof_shares_justifications() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 6)].into());
+
+		// Blocks 2, 3 and 5 are all proven by the justification of block 6.
+		let proof = provider.prove_finality_multi(&[2, 3, 5]).unwrap();
+		assert_eq!(proof.justifications, vec![vec![6]]);
+		for (index, block) in vec![2, 3, 5].into_iter().enumerate() {
+			assert_eq!(proof.proofs[index].as_ref().unwrap().justification_index, 0);
+			assert_eq!(
+				proof.proof(index),
+				Some(provider.prove_finality_detailed(block).unwrap().unwrap().proof),
+			);
+		}
+
+		// Block 0 is proven by the justification of block 1.
+		let proof = provider.prove_finality_multi(&[0, 2]).unwrap();
+		assert_eq!(proof.justifications, vec![vec![1], vec![6]]);
+		assert_eq!(proof.proofs[1].as_ref().unwrap().justification_index, 1);
+	}
+
+	#[test]
+	fn finality_proof_report_states_reason_code() {
+		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
+		let proof = |signed_by: &AuthorityList, unknown_headers| {
+			FinalityProof {
+				block: header(3).hash(),
+				justification: TestJustification((1, signed_by.clone()), vec![7]).encode(),
+				unknown_headers,
+			}
+			.encode()
+		};
+		let report = |proof| {
+			check_finality_proof_report::<Header, TestJustification>(1, auth.clone(), proof)
+		};
+
+		let accepted = report(proof(&auth, vec![header(2), header(3)]));
+		assert!(accepted.accepted);
+		assert_eq!(accepted.reason, ReasonCode::Accepted);
+
+		let rejected = report(vec![42]);
+		assert!(!rejected.accepted);
+		assert_eq!(rejected.reason, ReasonCode::DecodeFailure);
+
+		// Signed by other authorities.
+		let other_auth = vec![(AuthorityId::from_slice(&[4u8; 32]), 1u64)];
+		let rejected = report(proof(&other_auth, vec![header(2), header(3)]));
+		assert!(!rejected.accepted);
+		assert_eq!(rejected.reason, ReasonCode::InvalidJustification);
+
+		// Header #2 is missing, which the plain check rejects as well.
+		let broken = proof(&auth, vec![header(1), header(3)]);
+		let rejected = report(broken.clone());
+		assert!(!rejected.accepted);
+		assert_eq!(rejected.reason, ReasonCode::BrokenHeaderChain);
+		assert!(matches!(
+			check_finality_proof::<Header, TestJustification>(1, auth.clone(), broken),
+			Err(ClientError::BrokenHeaderChain)
+		));
+	}
+
+	#[test]
+	fn verify_batch_matches_individual_checks() {
+		let auth = |i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)];
+		let items = (0..32u8)
+			.map(|i| {
+				// Every fourth proof is signed by the wrong authorities.
+				let signed_by = if i % 4 == 0 { auth(i + 1) } else { auth(i) };
+				let proof = FinalityProof {
+					block: header(3).hash(),
+					justification: TestJustification((i as u64, signed_by), vec![i]).encode(),
+					unknown_headers: vec![header(2), header(3)],
+				};
+				(proof.encode(), i as u64, auth(i))
+			})
+			.collect::<Vec<_>>();
+
+		let individual = items
+			.iter()
+			.cloned()
+			.map(|(proof, set_id, authorities)| {
+				check_finality_proof::<Header, TestJustification>(set_id, authorities, proof).ok()
+			})
+			.collect::<Vec<_>>();
+		let batch = verify_batch::<Header, TestJustification>(items, Default::default())
+			.into_iter()
+			.map(Result::ok)
+			.collect::<Vec<_>>();
+
+		assert_eq!(batch, individual);
+		assert_eq!(batch.iter().filter(|result| result.is_none()).count(), 8);
+	}
+
+	#[test]
+	fn self_contained_sequence_derives_authorities_from_digests() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let authorities = (0..4u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+
+		// The fragment ending set `set_id`, whose justified block enacts the next set.
+		let fragment = |set_id: usize, log: Option<ConsensusLog<u64>>| {
+			let logs = log.map(|log| DigestItem::Consensus(ID, log.encode())).into_iter().collect();
+			let header = Header::new(
+				set_id as u64 + 1,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				Default::default(),
+				Digest { logs },
+			);
+			FinalityProof {
+				block: header.hash(),
+				justification: TestJustification(
+					(set_id as u64, authorities[set_id].clone()),
+					vec![],
+				)
+				.encode(),
+				unknown_headers: vec![header],
+			}
+		};
+		let change = |set_id: usize| ScheduledChange {
+			next_authorities: authorities[set_id + 1].clone(),
+			delay: 0u64,
+		};
+
+		// Set 1 is enacted by a forced change, the others by standard changes.
+		let fragments = vec![
+			fragment(0, Some(ConsensusLog::ScheduledChange(change(0)))),
+			fragment(1, Some(ConsensusLog::ForcedChange(1, change(1)))),
+			fragment(2, Some(ConsensusLog::ScheduledChange(change(2)))),
+		];
+		let verify = |fragments| {
+			verify_sequence_self_contained::<Header, TestJustification>(
+				fragments,
+				0,
+				authorities[0].clone(),
+			)
+		};
+
+		let timeline = verify(fragments.clone()).unwrap();
+		assert_eq!(timeline, (0..4u64).zip(authorities.clone()).collect::<Vec<_>>());
+
+		// The authorities of set 2 can't be derived if set 1 doesn't signal them.
+		let mut broken = fragments;
+		broken[1] = fragment(1, None);
+		assert!(matches!(verify(broken), Err(VerifyError::MissingAuthoritySetChange(1))));
+	}
+
+	#[test]
+	fn finality_proof_of_finalized_head() {
+		let backend = test_backend();
+
+		// The finalized head is the last block of set 1.
+		let provider = test_provider(backend.clone(), vec![(0, 1), (1, 3)].into());
+		let proof = provider.prove_finality_detailed(3).unwrap().unwrap();
+		assert_eq!(proof.requires_set_id, 1);
+		assert_eq!(proof.justified_number, 3);
+		assert_eq!(proof.proof.block, header(3).hash());
+		assert_eq!(proof.proof.justification, vec![3]);
+		assert!(proof.proof.unknown_headers.is_empty());
+		assert!(!proof.from_best_justification);
+
+		// The finalized head belongs to the latest set, so it's proven by the best justification.
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let provider = test_provider(backend, vec![(0, 1)].into());
+		let proof = provider.prove_finality_detailed(3).unwrap().unwrap();
+		assert_eq!(proof.requires_set_id, 1);
+		assert_eq!(proof.justified_number, 3);
+		assert_eq!(proof.proof.justification, justification.encode());
+		assert!(proof.proof.unknown_headers.is_empty());
+		assert!(proof.from_best_justification);
+
+		assert!(matches!(
+			provider.prove_finality_detailed(4),
+			Err(FinalityProofError::BlockNotYetFinalized)
+		));
+	}
+
+	#[test]
+	fn finality_proof_v2_declares_server_version() {
+		let provider = test_provider(test_backend(), vec![(0, 1), (1, 3)].into());
+
+		let proof = provider.prove_finality_v2(2).unwrap().unwrap();
+		assert_eq!(proof.server_version.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+
+		let decoded = FinalityProofV2::<Header>::decode(&mut &proof.encode()[..]).unwrap();
+		assert_eq!(decoded, proof);
+
+		// Proofs converted locally don't declare a version.
+		let proof = provider.prove_finality_detailed(2).unwrap().unwrap().proof;
+		assert_eq!(FinalityProofV2::<Header>::from(proof).server_version, None);
+	}
+
+	#[test]
+	fn finality_proof_reports_authority_set_delta() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let authority = |i: u8| (AuthorityId::from_slice(&[i; 32]), 1u64);
+		let prior = vec![authority(1), authority(2), authority(3)];
+		let enacted = vec![authority(1), authority(3), authority(4)];
+
+		let change = ConsensusLog::ScheduledChange(ScheduledChange {
+			next_authorities: enacted.clone(),
+			delay: 0u64,
+		});
+		let change_header = Header::new(
+			2,
+			H256::from_low_u64_be(0),
+			H256::from_low_u64_be(0),
+			header(1).hash(),
+			Digest { logs: vec![DigestItem::Consensus(ID, change.encode())] },
+		);
+		let proof = |justified: Header| FinalityProof {
+			block: justified.hash(),
+			justification: TestJustification((1, prior.clone()), vec![7]).encode(),
+			unknown_headers: vec![justified],
+		};
+		let check = |proof: FinalityProof<Header>| {
+			check_finality_proof_with_delta::<Header, TestJustification>(
+				1,
+				prior.clone(),
+				proof.encode(),
+			)
+			.unwrap()
+		};
+
+		let (_, delta) = check(proof(change_header));
+		assert_eq!(
+			delta,
+			Some(AuthoritySetDelta { added: vec![authority(4)], removed: vec![authority(2)] }),
+		);
+
+		// No delta is reported if F doesn't signal a change.
+		let (_, delta) = check(proof(header(2)));
+		assert_eq!(delta, None);
+	}
+
+	#[test]
+	fn finality_proof_of_uncovered_block_follows_policy() {
+		use crate::notification::GrandpaJustificationStream;
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+
+		let backend = test_backend();
+		let just4 = Some(Justifications::from((ID, vec![4])));
+		backend
+			.blockchain()
+			.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final)
+			.unwrap();
+
+		let store_best_justification = |number| {
+			let justification = GrandpaJustification::from_commit(
+				&Arc::new(InMemoryBlockchain::<Block>::new()),
+				1,
+				crate::Commit::<Block> {
+					target_hash: header(number).hash(),
+					target_number: number,
+					precommits: Vec::new(),
+				},
+			)
+			.unwrap();
+			crate::aux_schema::update_best_justification(&justification, |insert| {
+				backend.insert_aux(insert, &[])
+			})
+			.unwrap();
+			justification
+		};
+		store_best_justification(3);
+
+		// Block 4 is finalized but the best justification only covers block 3.
+		let (sender, stream) = GrandpaJustificationStream::channel();
+		let provider = |uncovered_block_policy| {
+			test_provider(backend.clone(), vec![(0, 1)].into())
+				.with_config(FinalityProofConfig { uncovered_block_policy, ..Default::default() })
+				.with_justification_stream(stream.clone())
+		};
+
+		let provider_returning = provider(UncoveredBlockPolicy::ReturnNotYetProvable);
+		assert!(matches!(
+			futures::executor::block_on(provider_returning.prove_finality_async(4, None)),
+			Err(FinalityProofError::NotYetProvable(3))
+		));
+
+		// Waits for the justification of block 4 to be notified.
+		let provider_waiting = provider(UncoveredBlockPolicy::Wait);
+		let (proof, ()) = futures::executor::block_on(futures::future::join(
+			provider_waiting.prove_finality_async(4, None),
+			async {
+				let justification = store_best_justification(4);
+				sender.notify(|| Ok(justification)).unwrap();
+			},
+		));
+		let proof = FinalityProof::<Header>::decode(&mut &proof.unwrap().unwrap()[..]).unwrap();
+		assert_eq!(proof.block, header(4).hash());
+		assert!(proof.unknown_headers.is_empty());
+	}
+
+	#[test]
+	fn finality_proof_with_minimized_quorum_verifies() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_keyring::Ed25519Keyring::{Alice, Bob, Charlie, Dave};
+
+		let authorities = keyring_authorities(&[Alice, Bob, Charlie, Dave]);
+
+		// All four authorities signed, while three of them are enough.
+		let full = signed_justification(1, &header(3), &[Alice, Bob, Charlie, Dave]);
+		let minimized = full.minimize_quorum(&authorities).unwrap();
+
+		let backend = Arc::new(InMemoryBackend::<Block>::new());
+		for number in 0..=3 {
+			let justifications = if number == 3 {
+				Some(Justifications::from((ID, full.encode())))
+			} else {
+				None
+			};
+			let header = header(number);
+			backend
+				.blockchain()
+				.insert(header.hash(), header, justifications, None, NewBlockState::Final)
+				.unwrap();
+		}
+
+		let authority_set = AuthoritySet::new(
+			authorities.clone(),
+			0,
+			ForkTree::new(),
+			Vec::new(),
+			set_changes(&[(0, 3)]),
+		)
+		.unwrap();
+		let provider = FinalityProofProvider::new(backend, Some(authority_set.into()))
+			.with_config(FinalityProofConfig { minimize_quorum: true, ..Default::default() });
+
+		let proof = provider.prove_finality(2).unwrap().unwrap();
+		let proof = check_finality_proof::<Header, GrandpaJustification<Block>>(
+			0,
+			authorities,
+			proof,
+		)
+		.unwrap();
+		assert_eq!(proof.justification, minimized.encode());
+	}
+
+	#[test]
+	fn finality_proof_counts_duplicate_signers_once() {
+		use sp_keyring::Ed25519Keyring::{self, Alice, Bob, Charlie, Dave};
+
+		let authorities = keyring_authorities(&[Alice, Bob, Charlie, Dave]);
+		let justification =
+			|signers: &[Ed25519Keyring]| signed_justification(1, &header(3), signers);
+		let check_with_config = |justification: &GrandpaJustification<Block>, config| {
+			let proof = FinalityProof {
+				block: header(3).hash(),
+				justification: justification.encode(),
+				unknown_headers: vec![header(3)],
+			};
+			check_finality_proof_with_config::<Header, GrandpaJustification<Block>>(
+				0,
+				authorities.clone(),
+				proof.encode(),
+				&config,
+			)
+		};
+		let check = |justification| check_with_config(justification, Default::default());
+		let reject_duplicates =
+			FinalityProofCheckConfig { reject_duplicate_signers: true, ..Default::default() };
+
+		let unique = justification(&[Alice, Bob, Charlie]);
+		check(&unique).unwrap();
+		check_with_config(&unique, reject_duplicates.clone()).unwrap();
+
+		// Charlie's weight is only counted once, the justification is still valid.
+		let duplicated = justification(&[Alice, Bob, Charlie, Charlie]);
+		let weight = ProvableJustification::<Header>::signed_weight(&duplicated, &authorities);
+		assert_eq!(weight, Some(3));
+		let signers = ProvableJustification::<Header>::counted_signers(&duplicated, &authorities);
+		let expected = [Alice, Bob, Charlie]
+			.iter()
+			.map(|keyring| keyring.public().into())
+			.collect::<Vec<AuthorityId>>();
+		assert_eq!(signers, Some(expected));
+		check(&duplicated).unwrap();
+
+		// Unless duplicate signers are rejected.
+		let error = check_with_config(&duplicated, reject_duplicates).unwrap_err().to_string();
+		assert!(error.contains("duplicate precommit #3"));
+
+		// Repeating Bob's precommit doesn't make up for the missing third signer.
+		let duplicated = justification(&[Alice, Bob, Bob]);
+		let weight = ProvableJustification::<Header>::signed_weight(&duplicated, &authorities);
+		assert_eq!(weight, Some(2));
+		check(&duplicated).unwrap_err();
+	}
+
+	#[test]
+	fn header_chain_is_validated_with_the_header_hasher() {
+		use sp_runtime::traits::{BlakeTwo256, Hash as _, Keccak256};
+
+		type KeccakHeader = sp_runtime::generic::Header<u64, Keccak256>;
+
+		#[derive(Encode, Decode)]
+		struct KeccakJustification(u64);
+
+		impl ProvableJustification<KeccakHeader> for KeccakJustification {
+			fn verify(&self, set_id: u64, _: &[(AuthorityId, u64)]) -> ClientResult<()> {
+				if self.0 != set_id {
+					return Err(ClientError::BadJustification("test".into()));
+				}
+				Ok(())
+			}
+		}
+
+		let header = |number, parent_hash| {
+			KeccakHeader::new(
+				number,
+				H256::from_low_u64_be(0),
+				H256::from_low_u64_be(0),
+				parent_hash,
+				Default::default(),
+			)
+		};
+		let header1 = header(1, Default::default());
+		let header2 = header(2, header1.hash());
+		assert_eq!(header1.hash(), Keccak256::hash_of(&header1));
+		assert_ne!(header1.hash(), BlakeTwo256::hash_of(&header1));
+
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let check = |unknown_headers: Vec<KeccakHeader>| {
+			let proof = FinalityProof {
+				block: unknown_headers.last().unwrap().hash(),
+				justification: KeccakJustification(1).encode(),
+				unknown_headers,
+			};
+			check_finality_proof::<KeccakHeader, KeccakJustification>(
+				1,
+				auth.clone(),
+				proof.encode(),
+			)
+		};
+
+		check(vec![header1.clone(), header2]).unwrap();
+
+		// A parent link computed with another hasher doesn't chain.
+		let blake2_child = header(2, BlakeTwo256::hash_of(&header1));
+		assert!(matches!(
+			check(vec![header1, blake2_child]),
+			Err(ClientError::BrokenHeaderChain)
+		));
+	}
+
+	#[test]
+	fn requests_are_validated_against_limits() {
+		let backend = test_backend();
+		let provider = |config| {
+			test_provider(backend.clone(), vec![(0, 3)].into()).with_config(config)
+		};
+		let valid = |justified| RequestValidation::Valid { justified };
+
+		let unlimited = provider(Default::default());
+		assert_eq!(unlimited.validate_request(0).unwrap(), valid(3));
+		assert_eq!(
+			unlimited.validate_request(4).unwrap(),
+			RequestValidation::NotYetFinalized { finalized: 3 },
+		);
+
+		// Blocks of the latest set are proven by the best justification, if any.
+		let latest = test_provider(backend.clone(), vec![(0, 1)].into());
+		assert_eq!(latest.validate_request(2).unwrap(), RequestValidation::NoJustification);
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(2).hash(),
+				target_number: 2,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+		assert_eq!(latest.validate_request(2).unwrap(), valid(2));
+		assert_eq!(
+			latest.validate_request(3).unwrap(),
+			RequestValidation::NotYetProvable { provable: 2 },
+		);
+
+		// The changes don't start from the genesis set.
+		let uncovered = test_provider(backend.clone(), vec![(1, 3)].into());
+		assert_eq!(uncovered.validate_request(2).unwrap(), RequestValidation::NotCovered);
+
+		let max_gap = provider(FinalityProofConfig { max_gap: Some(2), ..Default::default() });
+		assert_eq!(max_gap.validate_request(1).unwrap(), valid(3));
+		assert_eq!(
+			max_gap.validate_request(0).unwrap(),
+			RequestValidation::ExceedsMaxGap { gap: 3, max_gap: 2 },
+		);
+
+		let max_headers = |truncation_strategy| {
+			provider(FinalityProofConfig {
+				max_unknown_headers: 1,
+				truncation_strategy,
+				..Default::default()
+			})
+		};
+		assert_eq!(
+			max_headers(TruncationStrategy::Fail).validate_request(0).unwrap(),
+			RequestValidation::ExceedsMaxHeaders { headers: 2, max_headers: 1 },
+		);
+		let truncating = max_headers(TruncationStrategy::TruncateHigh);
+		assert_eq!(truncating.validate_request(0).unwrap(), valid(3));
+
+		let budget = header(3).encoded_size();
+		let max_size =
+			provider(FinalityProofConfig { max_proof_size: Some(budget), ..Default::default() });
+		assert_eq!(max_size.validate_request(1).unwrap(), valid(3));
+		assert_eq!(
+			max_size.validate_request(0).unwrap(),
+			RequestValidation::ExceedsByteBudget { estimated_size: 2 * budget, budget },
+		);
+
+		// The budget is enforced when generating the proof as well.
+		assert_eq!(max_size.prove_finality(1).unwrap(), unlimited.prove_finality(1).unwrap());
+		assert!(matches!(max_size.prove_finality(0), Err(FinalityProofError::ProofTooLarge)));
+	}
+
+	/// The bootstrap fragments justifying the given blocks, each signed by the given set of the
+	/// authority sets and enacting the next set if there is one. The headers of each fragment
+	/// extend the block justified by the previous one, starting from the genesis block.
+	fn bootstrap_fragments(
+		authorities: &[AuthorityList],
+		justified: &[(usize, u64)],
+	) -> Vec<DetailedFinalityProof<Header>> {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::{generic::DigestItem, Digest};
+
+		let mut parent = header(0);
+		let mut fragments = Vec::new();
+		for (set_id, number) in justified.iter().cloned() {
+			let mut unknown_headers = Vec::new();
+			for current in *parent.number() + 1..=number {
+				let logs = authorities
+					.get(set_id + 1)
+					.filter(|_| current == number)
+					.map(|next_authorities| {
+						let change = ConsensusLog::ScheduledChange(ScheduledChange {
+							next_authorities: next_authorities.clone(),
+							delay: 0u64,
+						});
+						DigestItem::Consensus(ID, change.encode())
+					})
+					.into_iter()
+					.collect();
+				parent = Header::new(
+					current,
+					H256::from_low_u64_be(0),
+					H256::from_low_u64_be(0),
+					parent.hash(),
+					Digest { logs },
+				);
+				unknown_headers.push(parent.clone());
+			}
+
+			let justification =
+				TestJustification((set_id as u64, authorities[set_id].clone()), vec![]);
+			fragments.push(DetailedFinalityProof {
+				proof: FinalityProof {
+					block: parent.hash(),
+					justification: TestBlockJustification(justification, number, parent.hash())
+						.encode(),
+					unknown_headers,
+				},
+				requires_set_id: set_id as u64,
+				justified_number: number,
+				from_best_justification: false,
+			});
+		}
+
+		fragments
+	}
+
+	#[test]
+	fn bootstrap_is_verified_atomically() {
+		let authorities = (0..3u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+		let genesis_state =
+			TrustedState::<Header>::genesis(0, authorities[0].clone(), 0, header(0).hash());
+		let verify = |fragments| {
+			verify_and_commit::<Header, TestBlockJustification>(fragments, genesis_state.clone())
+		};
+
+		let fragments = bootstrap_fragments(&authorities, &[(0, 4), (1, 8), (2, 10)]);
+		let state = verify(fragments.clone()).unwrap();
+		let expected_hash = authorities
+			.iter()
+			.enumerate()
+			.fold(Default::default(), |hash, (set_id, authorities)| {
+				rolling_authority_hash(hash, set_id as u64, authorities)
+			});
+		assert_eq!(state, TrustedState {
+			set_id: 2,
+			authorities: authorities[2].clone(),
+			finalized_number: 10,
+			finalized_hash: fragments[2].proof.block,
+			rolling_authority_hash: expected_hash,
+		});
+
+		// The finalized block is the verified target of the justification, whatever number the
+		// peer claims.
+		let mut misnumbered = fragments.clone();
+		misnumbered[2].justified_number = 100;
+		assert_eq!(verify(misnumbered).unwrap(), state);
+
+		// Tampering with the middle fragment fails the whole bootstrap.
+		let mut tampered = fragments.clone();
+		let justification = TestJustification((1, authorities[0].clone()), vec![]);
+		tampered[1].proof.justification =
+			TestBlockJustification(justification, 8, fragments[1].proof.block).encode();
+		assert!(matches!(verify(tampered), Err(VerifyError::InvalidFragment(1, _))));
+
+		// The middle fragment must extend the block finalized by the first one.
+		let mut detached = fragments.clone();
+		detached[1].proof.unknown_headers.remove(0);
+		assert!(matches!(verify(detached), Err(VerifyError::NotDescendant(1))));
+
+		// Fragments can't follow one that doesn't enact a change.
+		let trailing = bootstrap_fragments(&authorities, &[(0, 4), (1, 8), (2, 10), (2, 12)]);
+		assert!(matches!(verify(trailing), Err(VerifyError::MissingAuthoritySetChange(2))));
+	}
+
+	/// A clock of which each reading takes a second, simulating a slow backend.
+	#[derive(Clone, Default)]
+	struct TickingClock(MockClock);
+
+	impl Clock for TickingClock {
+		fn now(&self) -> Instant {
+			let now = self.0.now();
+			self.0.advance(Duration::from_secs(1));
+			now
+		}
+	}
+
+	#[test]
+	fn batched_finality_proof_is_completed_by_continuations() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 8, &[8]);
+
+		let provider = |config| {
+			test_provider(backend.clone(), vec![(0, 1), (1, 8)].into())
+				.with_config(config)
+				.with_clock(TickingClock::default())
+		};
+		let unlimited = provider(Default::default());
+		let budget = Duration::from_secs(2);
+
+		// Chunks of one and two headers are collected within the budget.
+		let batched = unlimited.prove_finality_batched(2, budget).unwrap().unwrap();
+		assert_eq!(batched.proof.unknown_headers, vec![header(3), header(4), header(5)]);
+		let continuation = batched.continuation.unwrap();
+		assert_eq!(continuation.next, 6);
+
+		let batch = unlimited.continue_proof(continuation.clone(), budget).unwrap();
+		assert_eq!(batch.headers, vec![header(6), header(7)]);
+		assert_eq!(batch.continuation, None);
+
+		// The batches add up to the full proof.
+		let mut proof = batched.proof;
+		proof.unknown_headers.extend(batch.headers);
+		assert_eq!(proof, unlimited.prove_finality_detailed(2).unwrap().unwrap().proof);
+
+		// A continuation for a justified block that isn't canonical is rejected.
+		let stale =
+			ProofContinuation { justified_hash: H256::repeat_byte(42), ..continuation.clone() };
+		assert!(matches!(
+			unlimited.continue_proof(stale, budget),
+			Err(FinalityProofError::BlockNotAncestorOfJustified)
+		));
+
+		// Nor can a continuation extend the proof past F.
+		let forged = ProofContinuation { last_header: 8, ..continuation };
+		let batch = unlimited.continue_proof(forged, Duration::from_secs(3600)).unwrap();
+		assert_eq!(batch.headers, vec![header(6), header(7)]);
+
+		// The limits of the configuration apply to batched proofs as well.
+		let truncated = provider(FinalityProofConfig {
+			max_unknown_headers: 2,
+			truncation_strategy: TruncationStrategy::TruncateHigh,
+			..Default::default()
+		});
+		let batched = truncated.prove_finality_batched(2, Duration::from_secs(3600));
+		let batched = batched.unwrap().unwrap();
+		assert_eq!(batched.proof.unknown_headers, vec![header(3), header(4)]);
+		assert_eq!(batched.continuation, None);
+
+		let max_size = |max_proof_size| {
+			provider(FinalityProofConfig { max_proof_size, ..Default::default() })
+				.prove_finality_batched(2, budget)
+		};
+		let budget_of = |headers| Some(headers * header(8).encoded_size());
+		max_size(budget_of(5)).unwrap().unwrap();
+		assert!(matches!(max_size(budget_of(4)), Err(FinalityProofError::ProofTooLarge)));
+
+		let max_gap = provider(FinalityProofConfig { max_gap: Some(5), ..Default::default() });
+		assert!(matches!(
+			max_gap.prove_finality_batched(2, budget),
+			Err(FinalityProofError::ProofTooLarge)
+		));
+	}
+
+	#[test]
+	fn finality_proof_with_round_zero_justification_is_rejected() {
+		use sp_keyring::Ed25519Keyring::Alice;
+
+		let check = |round| {
+			let proof = FinalityProof {
+				block: header(3).hash(),
+				justification: signed_justification(round, &header(3), &[Alice]).encode(),
+				unknown_headers: vec![header(3)],
+			};
+			check_finality_proof::<Header, GrandpaJustification<Block>>(
+				0,
+				keyring_authorities(&[Alice]),
+				proof.encode(),
+			)
+		};
+
+		check(1).unwrap();
+		// The signature is valid for round 0, but no commit is made in that round.
+		assert!(check(0).unwrap_err().to_string().contains("invalid round 0"));
+	}
+
+	#[test]
+	fn finality_proof_of_another_chain_is_rejected() {
+		let backend = test_backend();
+		let genesis_hash = backend.blockchain().info().genesis_hash;
+		let provider = |embed_genesis_hash| {
+			test_provider(backend.clone(), vec![(0, 1), (1, 3)].into()).with_config(
+				FinalityProofConfig { embed_genesis_hash, ..Default::default() },
+			)
+		};
+
+		let proof = provider(true).prove_finality_v2(2).unwrap().unwrap();
+		assert_eq!(proof.genesis_hash, Some(genesis_hash));
+		assert_eq!(provider(false).prove_finality_v2(2).unwrap().unwrap().genesis_hash, None);
+
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let justification = TestJustification((1, auth.clone()), vec![7]).encode();
+		let proof =
+			FinalityProofV2 { justification: ProofJustification::Inline(justification), ..proof };
+		let check = |proof: &FinalityProofV2<Header>, genesis_hash| {
+			check_finality_proof_v2_for_chain::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof.encode(),
+				|_| None,
+				genesis_hash,
+			)
+		};
+
+		check(&proof, genesis_hash).unwrap();
+		let error = check(&proof, H256::repeat_byte(42)).unwrap_err();
+		assert!(error.to_string().contains("while expecting chain"));
+
+		// Proofs without a genesis hash can't be attributed to the chain.
+		check(&FinalityProofV2 { genesis_hash: None, ..proof.clone() }, genesis_hash).unwrap_err();
+	}
+
+	#[test]
+	fn resumed_finality_proof_continues_after_last_received_header() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let fork5 = Header::new(
+			5,
+			H256::from_low_u64_be(1),
+			H256::from_low_u64_be(0),
+			header(4).hash(),
+			Default::default(),
+		);
+		blockchain.insert(fork5.hash(), fork5.clone(), None, None, NewBlockState::Normal).unwrap();
+
+		let provider = test_provider(backend, vec![(0, 6)].into());
+		let full = provider.prove_finality(1).unwrap().unwrap();
+		let full = FinalityProof::<Header>::decode(&mut &full[..]).unwrap();
+		assert_eq!(full.unknown_headers, (2..6).map(header).collect::<Vec<_>>());
+
+		// The transfer got interrupted after the first two headers.
+		let received = &full.unknown_headers[..2];
+		let resumed = provider.resume_proof(1, received[1].hash()).unwrap().unwrap();
+		assert_eq!(resumed.proof.block, full.block);
+		assert_eq!(resumed.proof.justification, full.justification);
+		assert_eq!(
+			[received, &resumed.proof.unknown_headers[..]].concat(),
+			full.unknown_headers,
+		);
+
+		// Nothing is left to send once the justified block was received.
+		let resumed = provider.resume_proof(1, header(6).hash()).unwrap().unwrap();
+		assert!(resumed.proof.unknown_headers.is_empty());
+
+		// The last received header must be an ancestor of the justified block.
+		for hash in vec![fork5.hash(), H256::repeat_byte(42)] {
+			assert!(matches!(
+				provider.resume_proof(1, hash),
+				Err(FinalityProofError::BlockNotAncestorOfJustified)
+			));
 		}
 	}
 
-	#[derive(Debug, PartialEq, Encode, Decode)]
-	pub struct TestBlockJustification(TestJustification, u64, H256);
+	#[test]
+	fn rolling_authority_hash_covers_every_enacted_set() {
+		let authorities = (0..3u8)
+			.map(|i| vec![(AuthorityId::from_slice(&[i; 32]), 1u64)])
+			.collect::<Vec<AuthorityList>>();
+		let verify = |authorities: &[AuthorityList]| {
+			let fragments = bootstrap_fragments(authorities, &[(0, 4), (1, 8), (2, 10)]);
+			let genesis_state =
+				TrustedState::<Header>::genesis(0, authorities[0].clone(), 0, header(0).hash());
+			verify_and_commit::<Header, TestBlockJustification>(fragments, genesis_state)
+				.unwrap()
+				.rolling_authority_hash
+		};
 
-	impl ProvableJustification<Header> for TestBlockJustification {
-		fn verify(&self, set_id: u64, authorities: &[(AuthorityId, u64)]) -> ClientResult<()> {
-			self.0.verify(set_id, authorities)
+		let rolling_hash = verify(&authorities);
+		assert_eq!(verify(&authorities), rolling_hash);
+
+		// Changing any of the sets, including the genesis one, changes the rolling hash.
+		for set_id in 0..authorities.len() {
+			let mut changed = authorities.clone();
+			changed[set_id][0].1 = 2;
+			assert_ne!(verify(&changed), rolling_hash);
 		}
 	}
 
-	fn header(number: u64) -> Header {
-		let parent_hash = match number {
-			0 => Default::default(),
-			_ => header(number - 1).hash(),
-		};
-		Header::new(
-			number,
-			H256::from_low_u64_be(0),
-			H256::from_low_u64_be(0),
-			parent_hash,
-			Default::default(),
-		)
+	#[test]
+	fn finality_proof_by_deadline_stops_at_nearest_justified_block() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[5, 6]);
+
+		let clock = MockClock::default();
+		let provider = test_provider(backend, vec![(0, 1), (1, 6)].into())
+			.with_clock(TickingClock(clock.clone()));
+		let prove = |deadline| provider.prove_finality_by_deadline(2, deadline).unwrap().unwrap();
+
+		// Collecting each header takes a second, the deadline passes after block 5 is reached.
+		let proof = prove(clock.now() + Duration::from_millis(1500));
+		assert_eq!(proof.justified_number, 5);
+		assert_eq!(proof.requires_set_id, 1);
+		assert_eq!(proof.proof.block, header(5).hash());
+		assert_eq!(proof.proof.justification, vec![5]);
+		assert_eq!(proof.proof.unknown_headers, vec![header(3), header(4)]);
+
+		// A passed deadline still yields a proof, up to the first justified block.
+		let proof = prove(clock.now());
+		assert_eq!(proof.proof.justification, vec![3]);
+		assert!(proof.proof.unknown_headers.is_empty());
+
+		// With enough time the full proof is collected.
+		let proof = prove(clock.now() + Duration::from_secs(3600));
+		assert_eq!(proof, provider.prove_finality_detailed(2).unwrap().unwrap());
 	}
 
-	fn test_blockchain() -> InMemoryBlockchain<Block> {
-		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
-		let blockchain = InMemoryBlockchain::<Block>::new();
-		let just0 = Some(Justifications::from((ID, vec![0])));
-		let just1 = Some(Justifications::from((ID, vec![1])));
-		let just2 = None;
-		let just3 = Some(Justifications::from((ID, vec![3])));
-		blockchain.insert(header(0).hash(), header(0), just0, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(1).hash(), header(1), just1, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(2).hash(), header(2), just2, None, NewBlockState::Best).unwrap();
-		blockchain.insert(header(3).hash(), header(3), just3, None, NewBlockState::Final).unwrap();
-		blockchain
+	#[test]
+	fn justifications_in_range_skips_unjustified_blocks() {
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		finalize_test_blocks(blockchain, 6, &[6]);
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 6)].into());
+		let in_range = |from, to| provider.justifications_in_range(from, to).unwrap();
+
+		assert_eq!(in_range(0, 6), vec![(0, vec![0]), (1, vec![1]), (3, vec![3]), (6, vec![6])]);
+		assert_eq!(in_range(2, 5), vec![(3, vec![3])]);
+		assert!(in_range(4, 5).is_empty());
+		// Blocks above the last finalized one are never listed.
+		assert_eq!(in_range(6, 10), vec![(6, vec![6])]);
+
+		// The range is limited after leaving out the blocks above the last finalized one.
+		let limited = provider.with_config(FinalityProofConfig {
+			max_justification_range: 3,
+			..Default::default()
+		});
+		assert_eq!(limited.justifications_in_range(4, 100).unwrap(), vec![(6, vec![6])]);
+		assert!(matches!(
+			limited.justifications_in_range(3, 6),
+			Err(FinalityProofError::RangeTooLarge)
+		));
 	}
 
 	#[test]
-	fn finality_proof_fails_if_no_more_last_finalized_blocks() {
-		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
-		let blockchain = test_blockchain();
-		let just1 = Some(Justifications::from((ID, vec![1])));
-		let just2 = Some(Justifications::from((ID, vec![2])));
-		blockchain.insert(header(4).hash(), header(4), just1, None, NewBlockState::Best).unwrap();
-		blockchain.insert(header(5).hash(), header(5), just2, None, NewBlockState::Best).unwrap();
+	fn finality_proof_reports_counted_signers() {
+		use sp_keyring::Ed25519Keyring::{Alice, Bob, Charlie, Dave};
 
-		let mut authority_set_changes = AuthoritySetChanges::empty();
-		authority_set_changes.append(0, 5);
+		let authorities = keyring_authorities(&[Alice, Bob, Charlie, Dave]);
 
-		// The last finalized block is 3, so we cannot provide further justifications.
-		let proof_of_4 = prove_finality::<_, _, TestJustification>(
-			&blockchain,
-			authority_set_changes,
-			*header(4).number(),
+		// Bob didn't sign.
+		let signing = [Charlie, Alice, Dave];
+		let proof = FinalityProof {
+			block: header(3).hash(),
+			justification: signed_justification(1, &header(3), &signing).encode(),
+			unknown_headers: vec![header(3)],
+		};
+
+		let (checked, signers) =
+			check_finality_proof_with_signers::<Header, GrandpaJustification<Block>>(
+				0,
+				authorities,
+				proof.encode(),
+			)
+			.unwrap();
+		assert_eq!(checked, proof);
+		assert_eq!(
+			signers,
+			signing.iter().map(|keyring| keyring.public().into()).collect::<Vec<AuthorityId>>(),
 		);
-		assert!(matches!(proof_of_4, Err(FinalityProofError::BlockNotYetFinalized)));
+
+		// Justifications which don't expose their signers can't be audited.
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let proof = FinalityProof {
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			..proof
+		};
+		check_finality_proof_with_signers::<Header, TestJustification>(1, auth, proof.encode())
+			.unwrap_err();
 	}
 
 	#[test]
-	fn finality_proof_is_none_if_no_justification_known() {
-		let blockchain = test_blockchain();
-		blockchain
-			.insert(header(4).hash(), header(4), None, None, NewBlockState::Final)
+	fn finality_proof_bundles_extrinsic_inclusion_proof() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		use sp_runtime::traits::Hash as _;
+		use substrate_test_runtime_client::runtime::Extrinsic;
+
+		let extrinsics = (0..4u8).map(|i| Extrinsic::IncludeData(vec![i; 8])).collect::<Vec<_>>();
+		let header4 = Header::new(
+			4,
+			<Header as HeaderT>::Hashing::ordered_trie_root(
+				extrinsics.iter().map(Encode::encode).collect(),
+			),
+			H256::from_low_u64_be(0),
+			header(3).hash(),
+			Default::default(),
+		);
+
+		let backend = test_backend();
+		let just4 = Some(Justifications::from((ID, vec![4])));
+		let body = Some(extrinsics.clone());
+		backend
+			.blockchain()
+			.insert(header4.hash(), header4.clone(), just4, body, NewBlockState::Final)
 			.unwrap();
 
-		let mut authority_set_changes = AuthoritySetChanges::empty();
-		authority_set_changes.append(0, 4);
+		let provider = test_provider(backend, vec![(0, 1), (1, 4)].into()).with_config(
+			FinalityProofConfig { include_justified_header: true, ..Default::default() },
+		);
+		let bundle = provider.prove_finality_with_extrinsic(2, 2).unwrap().unwrap();
+		assert_eq!(bundle.proof, provider.prove_finality_detailed(2).unwrap().unwrap());
+		assert_eq!(bundle.proof.proof.unknown_headers.last(), Some(&header4));
+		assert_eq!(bundle.extrinsic.extrinsic, extrinsics[2].encode());
+		assert!(bundle.extrinsic.verify(&header4));
 
-		// Block 4 is finalized without justification
-		// => we can't prove finality of 3
-		let proof_of_3 = prove_finality::<_, _, TestJustification>(
-			&blockchain,
-			authority_set_changes,
-			*header(3).number(),
-		)
-		.unwrap();
-		assert_eq!(proof_of_3, None);
+		// Tampering with the extrinsic or its index is detected.
+		let mut tampered = bundle.extrinsic.clone();
+		tampered.extrinsic = extrinsics[1].encode();
+		assert!(!tampered.verify(&header4));
+		let tampered = ExtrinsicInclusionProof { index: 1, ..bundle.extrinsic.clone() };
+		assert!(!tampered.verify(&header4));
+		// As is checking it against another block.
+		assert!(!bundle.extrinsic.verify(&header(3)));
+
+		// The extrinsic must exist.
+		assert!(matches!(
+			provider.prove_finality_with_extrinsic(2, 4),
+			Err(FinalityProofError::Client(ClientError::UnknownBlock(_)))
+		));
 	}
 
 	#[test]
-	fn finality_proof_check_fails_when_proof_decode_fails() {
-		// When we can't decode proof from Vec<u8>
-		check_finality_proof::<_, TestJustification>(
+	fn finality_proof_check_ignores_authority_metadata() {
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let proof = FinalityProof {
+			block: header(2).hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![header(2)],
+		};
+
+		// E.g. the network identity of each authority.
+		let with_metadata = auth
+			.iter()
+			.map(|(id, weight)| (id.clone(), *weight, String::from("12D3KooW")))
+			.collect::<Vec<_>>();
+		let checked = check_finality_proof::<Header, TestJustification>(
 			1,
-			vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)],
-			vec![42],
+			authority_list(&with_metadata),
+			proof.encode(),
 		)
-		.unwrap_err();
-	}
+		.unwrap();
+		assert_eq!(checked, proof);
+		assert_eq!(
+			check_finality_proof::<Header, TestJustification>(1, auth, proof.encode()).unwrap(),
+			checked,
+		);
 
-	#[test]
-	fn finality_proof_check_fails_when_proof_is_empty() {
-		// When decoded proof has zero length
-		check_finality_proof::<_, TestJustification>(
+		// Only the ids and weights are checked.
+		let other_weight = vec![(AuthorityId::from_slice(&[1u8; 32]), 2u64, ())];
+		check_finality_proof::<Header, TestJustification>(
 			1,
-			vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)],
-			Vec::<TestJustification>::new().encode(),
+			authority_list(&other_weight),
+			proof.encode(),
 		)
 		.unwrap_err();
 	}
 
 	#[test]
-	fn finality_proof_check_works() {
-		let auth = vec![(AuthorityId::from_slice(&[3u8; 32]), 1u64)];
-		let finality_proof = FinalityProof {
-			block: header(2).hash(),
-			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
-			unknown_headers: Vec::new(),
+	fn verify_batch_defers_items_beyond_budget() {
+		let auth = |i: u8| (0..3).map(move |j| (AuthorityId::from_slice(&[i * 3 + j; 32]), 1u64));
+		let items = (0..5u8)
+			.map(|i| {
+				let authorities = auth(i).collect::<AuthorityList>();
+				let proof = FinalityProof {
+					block: header(3).hash(),
+					justification: TestJustification((i as u64, authorities.clone()), vec![i])
+						.encode(),
+					unknown_headers: vec![header(2), header(3)],
+				};
+				(proof.encode(), i as u64, authorities)
+			})
+			.collect::<Vec<_>>();
+		let verify = |max_signature_checks| {
+			verify_batch::<Header, TestJustification>(
+				items.clone(),
+				VerificationBudget { max_signature_checks },
+			)
 		};
-		let proof = check_finality_proof::<_, TestJustification>(
-			1,
-			auth.clone(),
-			finality_proof.encode(),
-		)
-		.unwrap();
-		assert_eq!(proof, finality_proof);
+
+		// Each item takes up to three signature checks, only two of them fit.
+		let results = verify(Some(8));
+		assert_eq!(results.len(), 5);
+		assert!(results[..2].iter().all(Result::is_ok));
+		for (index, result) in results.iter().enumerate().skip(2) {
+			assert!(matches!(result, Err(VerifyError::Deferred(i)) if *i == index));
+		}
+
+		assert!(verify(Some(15)).iter().all(Result::is_ok));
+		assert!(verify(None).iter().all(Result::is_ok));
+		assert!(matches!(verify(Some(2))[0], Err(VerifyError::Deferred(0))));
 	}
 
 	#[test]
-	fn finality_proof_using_authority_set_changes_fails_with_undefined_start() {
+	fn finality_proof_is_returned_with_its_content_hash() {
+		let provider = test_provider(test_backend(), vec![(0, 1), (1, 3)].into());
+
+		let (proof, hash) = provider.prove_finality_with_content_hash(2).unwrap().unwrap();
+		assert_eq!(proof, provider.prove_finality(2).unwrap().unwrap());
+		assert_eq!(hash, blake2_256(&proof));
+
+		// A transfer corrupting the proof is detected.
+		let mut received = proof;
+		received[0] ^= 1;
+		assert_ne!(blake2_256(&received), hash);
+	}
+
+	#[test]
+	fn forced_change_delay_is_validated_against_headers() {
 		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
-		let blockchain = test_blockchain();
+		use sp_runtime::{generic::DigestItem, Digest};
+
 		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
-		let grandpa_just4 = TestJustification((0, auth.clone()), vec![4]).encode();
-		let grandpa_just7 = TestJustification((1, auth.clone()), vec![7]).encode();
-		let just4 = Some(Justifications::from((ID, grandpa_just4)));
-		let just7 = Some(Justifications::from((ID, grandpa_just7)));
-		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(6).hash(), header(6), None, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(7).hash(), header(7), just7, None, NewBlockState::Final).unwrap();
 
-		// We have stored the correct block number for the relevant set, but as we are missing the
-		// block for the preceding set the start is not well-defined.
-		let mut authority_set_changes = AuthoritySetChanges::empty();
-		authority_set_changes.append(1, 7);
+		// A chain of headers 1..=5, block 2 signalling a forced change.
+		let proof = |median: u64, delay: u64| {
+			let change = ConsensusLog::ForcedChange(median, ScheduledChange {
+				next_authorities: vec![(AuthorityId::from_slice(&[2u8; 32]), 1u64)],
+				delay,
+			});
+			let mut headers = vec![header(1)];
+			for number in 2..=5 {
+				let logs = if number == 2 {
+					vec![DigestItem::Consensus(ID, change.encode())]
+				} else {
+					Vec::new()
+				};
+				let parent_hash = headers.last().unwrap().hash();
+				headers.push(Header::new(
+					number,
+					H256::from_low_u64_be(0),
+					H256::from_low_u64_be(0),
+					parent_hash,
+					Digest { logs },
+				));
+			}
 
-		let proof_of_5 = prove_finality::<_, _, TestJustification>(
-			&blockchain,
-			authority_set_changes,
-			*header(5).number(),
-		);
-		assert!(matches!(proof_of_5, Err(FinalityProofError::BlockNotInAuthoritySetChanges)));
+			FinalityProof {
+				block: headers[4].hash(),
+				justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+				unknown_headers: headers,
+			}
+			.encode()
+		};
+		let check = |proof, validate_forced_changes| {
+			check_finality_proof_with_config::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof,
+				&FinalityProofCheckConfig { validate_forced_changes, ..Default::default() },
+			)
+		};
+
+		// The change is enacted at block 5, the justified block.
+		check(proof(1, 3), true).unwrap();
+		check(proof(1, 10), true).unwrap();
+
+		// The change is enacted at block 3, after which the set can't finalize blocks anymore.
+		let error = check(proof(1, 1), true).unwrap_err();
+		assert!(error.to_string().contains("enacted at #3"));
+		// The median last finalized block must precede the signal.
+		check(proof(2, 3), true).unwrap_err();
+
+		// The check is opt-in.
+		check(proof(1, 1), false).unwrap();
 	}
 
 	#[test]
-	fn finality_proof_using_authority_set_changes_works() {
+	fn finality_proof_by_state_root_proves_finalized_block() {
 		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
-		let blockchain = test_blockchain();
+
+		let state_root = H256::repeat_byte(4);
+		let header4 = Header::new(
+			4,
+			H256::from_low_u64_be(0),
+			state_root,
+			header(3).hash(),
+			Default::default(),
+		);
+
+		let backend = test_backend();
+		let just4 = Some(Justifications::from((ID, vec![4])));
+		backend
+			.blockchain()
+			.insert(header4.hash(), header4.clone(), just4, None, NewBlockState::Final)
+			.unwrap();
+
+		let provider = test_provider(backend, vec![(0, 1), (1, 4)].into());
+		let proof = provider.prove_finality_by_state_root(state_root).unwrap().unwrap();
+		assert_eq!(proof.justified_number, 4);
+		assert_eq!(proof.proof.block, header4.hash());
+		assert!(proof.proof.unknown_headers.is_empty());
+
+		// The other blocks share a state root, the highest of them is proven.
+		let proof = provider.prove_finality_by_state_root(H256::from_low_u64_be(0));
+		let proof = proof.unwrap().unwrap().proof;
+		assert_eq!(proof.block, header4.hash());
+		assert!(proof.unknown_headers.is_empty());
+
+		assert!(matches!(
+			provider.prove_finality_by_state_root(H256::repeat_byte(42)),
+			Err(FinalityProofError::StateRootNotFound)
+		));
+
+		// Only the configured number of headers is searched.
+		let provider = provider.with_config(FinalityProofConfig {
+			max_state_root_search_depth: 1,
+			..Default::default()
+		});
+		provider.prove_finality_by_state_root(state_root).unwrap().unwrap();
+		assert!(matches!(
+			provider.prove_finality_by_state_root(H256::from_low_u64_be(0)),
+			Err(FinalityProofError::StateRootNotFound)
+		));
+	}
+
+	#[test]
+	fn finality_proof_of_non_canonical_block_is_rejected() {
 		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
-		let grandpa_just4 = TestJustification((0, auth.clone()), vec![4]).encode();
-		let grandpa_just7 = TestJustification((1, auth.clone()), vec![7]).encode();
-		let just4 = Some(Justifications::from((ID, grandpa_just4)));
-		let just7 = Some(Justifications::from((ID, grandpa_just7.clone())));
-		blockchain.insert(header(4).hash(), header(4), just4, None, NewBlockState::Final) .unwrap();
-		blockchain.insert(header(5).hash(), header(5), None, None, NewBlockState::Final) .unwrap();
-		blockchain.insert(header(6).hash(), header(6), None, None, NewBlockState::Final).unwrap();
-		blockchain.insert(header(7).hash(), header(7), just7, None, NewBlockState::Final).unwrap();
+		let fork3 = Header::new(
+			3,
+			H256::from_low_u64_be(1),
+			H256::from_low_u64_be(0),
+			header(2).hash(),
+			Default::default(),
+		);
+		let proof = |justified: &Header| FinalityProof {
+			block: justified.hash(),
+			justification: TestJustification((1, auth.clone()), vec![7]).encode(),
+			unknown_headers: vec![justified.clone()],
+		};
 
-		let mut authority_set_changes = AuthoritySetChanges::empty();
-		authority_set_changes.append(0, 4);
-		authority_set_changes.append(1, 7);
+		// The caller follows the chain of `header`.
+		let canonical = (0..=3).map(|n| header(n).hash()).collect::<HashSet<_>>();
+		let check = |proof: FinalityProof| {
+			check_finality_proof_canonical::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof.encode(),
+				|hash| canonical.contains(hash),
+			)
+		};
 
-		let proof_of_5: FinalityProof = Decode::decode(
-			&mut &prove_finality::<_, _, TestJustification>(
-				&blockchain,
-				authority_set_changes,
-				*header(5).number(),
+		assert_eq!(check(proof(&header(3))).unwrap(), proof(&header(3)));
+
+		let error = check(proof(&fork3)).unwrap_err();
+		assert!(matches!(error, ClientError::NonCanonical(_)));
+	}
+
+	#[test]
+	fn prebuilt_boundary_proofs_are_served_from_cache() {
+		let report = |cached, failed| BoundaryCacheReport { cached, failed };
+		let prebuilt = |backend, max_sets| {
+			let provider = test_provider(backend, vec![(0, 1), (1, 3)].into()).with_proof_cache(8);
+			assert_eq!(
+				provider.prebuild_boundary_cache(max_sets),
+				report(max_sets.unwrap_or(2), 0),
+			);
+			provider
+		};
+
+		let backend = test_backend();
+		let provider = prebuilt(backend.clone(), None);
+		let cache = provider.proof_cache.as_ref().unwrap();
+		assert_eq!(cache.lock().entries.keys().cloned().collect::<Vec<_>>(), vec![1, 3]);
+
+		// The proofs are persisted.
+		for number in vec![1, 3] {
+			let persisted = crate::aux_schema::load_boundary_proof::<Block, _>(&*backend, number)
+				.unwrap()
+				.unwrap();
+			assert_eq!(persisted.0, header(number).hash());
+			assert_eq!(persisted.2, cache.lock().entries[&number].proof);
+		}
+
+		// Requests are served from the cache, as shown by tampering with the cached proofs.
+		for number in vec![1, 3] {
+			cache.lock().entries.get_mut(&number).unwrap().proof = vec![42];
+			assert_eq!(provider.prove_finality(number).unwrap().unwrap(), vec![42]);
+		}
+
+		// The persisted proofs are loaded on the next startup, as shown by tampering with them.
+		let tampered = (header(1).hash(), (1, header(1).hash()), vec![42]);
+		crate::aux_schema::write_boundary_proof::<Block, _>(&*backend, 1, &tampered).unwrap();
+		let restarted = prebuilt(backend.clone(), None);
+		assert_eq!(restarted.prove_finality(1).unwrap().unwrap(), vec![42]);
+
+		// Unless they were persisted for another block at that height.
+		let stale = (header(2).hash(), (1, header(1).hash()), vec![42]);
+		crate::aux_schema::write_boundary_proof::<Block, _>(&*backend, 1, &stale).unwrap();
+		let restarted = prebuilt(backend, None);
+		assert_ne!(restarted.prove_finality(1).unwrap().unwrap(), vec![42]);
+
+		let provider = prebuilt(test_backend(), Some(1));
+		let cache = provider.proof_cache.as_ref().unwrap();
+		assert_eq!(cache.lock().entries.keys().cloned().collect::<Vec<_>>(), vec![1]);
+
+		// No more proofs than the cache can hold are built.
+		let small = test_provider(test_backend(), vec![(0, 1), (1, 3)].into()).with_proof_cache(1);
+		assert_eq!(small.prebuild_boundary_cache(None), report(1, 0));
+		let cache = small.proof_cache.as_ref().unwrap();
+		assert_eq!(cache.lock().entries.keys().cloned().collect::<Vec<_>>(), vec![1]);
+
+		// Proofs evicted by the ones already cached aren't reported.
+		small.prove_finality(2).unwrap().unwrap();
+		cache.lock().capacity = 2;
+		assert_eq!(small.prebuild_boundary_cache(None), report(1, 0));
+		assert_eq!(cache.lock().entries.keys().cloned().collect::<Vec<_>>(), vec![2, 3]);
+
+		// Boundaries whose proof can't be built are skipped, the others are still built.
+		let unfinalized = test_provider(test_backend(), vec![(0, 1), (1, 3), (2, 5)].into())
+			.with_proof_cache(8);
+		assert_eq!(unfinalized.prebuild_boundary_cache(None), report(2, 1));
+		let cache = unfinalized.proof_cache.as_ref().unwrap();
+		assert_eq!(cache.lock().entries.keys().cloned().collect::<Vec<_>>(), vec![1, 3]);
+
+		// Without a cache nothing is built.
+		let uncached = test_provider(test_backend(), vec![(0, 1), (1, 3)].into());
+		assert_eq!(uncached.prebuild_boundary_cache(None), report(0, 0));
+	}
+
+	#[test]
+	fn oversized_justification_is_rejected_before_decoding() {
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let proof = |justification: Vec<u8>| {
+			FinalityProof {
+				block: header(2).hash(),
+				justification,
+				unknown_headers: vec![header(2)],
+			}
+			.encode()
+		};
+		let check = |proof, max_justification_bytes| {
+			check_finality_proof_with_config::<Header, TestJustification>(
+				1,
+				auth.clone(),
+				proof,
+				&FinalityProofCheckConfig { max_justification_bytes, ..Default::default() },
 			)
-			.unwrap()
-			.unwrap()[..],
+		};
+
+		let justification = TestJustification((1, auth.clone()), vec![7; 256]).encode();
+		let size = justification.len();
+		check(proof(justification.clone()), Some(size)).unwrap();
+		check(proof(justification.clone()), None).unwrap();
+		let error = check(proof(justification), Some(size - 1)).unwrap_err();
+		assert!(error.to_string().contains("exceeds the limit"));
+
+		// An oversized blob is rejected without being decoded.
+		let error = check(proof(vec![0xff; 1024]), Some(size)).unwrap_err();
+		assert!(error.to_string().contains("exceeds the limit"));
+		let error = check(proof(vec![0xff; 1024]), None).unwrap_err();
+		assert!(matches!(error, ClientError::JustificationDecode));
+	}
+
+	#[test]
+	fn truncated_finality_proof_round_trips() {
+		use sp_finality_grandpa::GRANDPA_ENGINE_ID as ID;
+		let backend = test_backend();
+		let blockchain = backend.blockchain();
+		let auth = vec![(AuthorityId::from_slice(&[1u8; 32]), 1u64)];
+		let just6 = TestJustification((0, auth.clone()), vec![6]);
+		let just6 = TestBlockJustification(just6, 6, header(6).hash()).encode();
+		let just6 = Some(Justifications::from((ID, just6)));
+		finalize_test_blocks(blockchain, 5, &[]);
+		blockchain.insert(header(6).hash(), header(6), just6, None, NewBlockState::Final).unwrap();
+
+		let authority_set_changes = set_changes(&[(0, 6)]);
+
+		// Proving block 2 requires the headers (2; 6), the default strategy drops the highest.
+		let config = FinalityProofConfig { max_unknown_headers: 2, ..Default::default() };
+		let proof = prove_finality::<_, _, TestBlockJustification>(
+			&*backend,
+			authority_set_changes,
+			2,
+			&config,
 		)
+		.unwrap()
 		.unwrap();
-		assert_eq!(
-			proof_of_5,
-			FinalityProof {
-				block: header(7).hash(),
-				justification: grandpa_just7,
-				unknown_headers: vec![header(6)],
-			}
-		);
+
+		let checked =
+			check_finality_proof::<Header, TestBlockJustification>(0, auth, proof).unwrap();
+		assert_eq!(checked.block, header(6).hash());
+		assert_eq!(checked.unknown_headers, vec![header(3), header(4)]);
+	}
+
+	#[test]
+	fn proof_cache_is_maintained_from_client_notifications() {
+		let backend = test_backend();
+		let justification = GrandpaJustification::from_commit(
+			&Arc::new(InMemoryBlockchain::<Block>::new()),
+			1,
+			crate::Commit::<Block> {
+				target_hash: header(3).hash(),
+				target_number: 3,
+				precommits: Vec::new(),
+			},
+		)
+		.unwrap();
+		crate::aux_schema::update_best_justification(&justification, |insert| {
+			backend.insert_aux(insert, &[])
+		})
+		.unwrap();
+
+		let provider = test_provider(backend, vec![(0, 1)].into()).with_proof_cache(8);
+		let cache = provider.proof_cache.as_ref().unwrap();
+		let cached = || cache.lock().entries.keys().cloned().collect::<Vec<_>>();
+
+		// Block 1 is proven by the boundary justification of set 0, block 2 by the best
+		// justification at block 3.
+		for number in 1..3 {
+			provider.prove_finality(number).unwrap().unwrap();
+		}
+		assert_eq!(cached(), vec![1, 2]);
+
+		// Retracting the justified block evicts the proofs it justifies.
+		provider.note_reorg(&[header(3).hash()]);
+		assert_eq!(cached(), vec![1]);
+
+		for number in 1..3 {
+			provider.prove_finality(number).unwrap().unwrap();
+		}
+
+		// Imports without a reorg don't evict anything, finality evicts the proofs using the best
+		// justification.
+		let import = BlockImportNotification {
+			hash: header(3).hash(),
+			origin: sp_consensus::BlockOrigin::Own,
+			header: header(3),
+			is_new_best: true,
+			tree_route: None,
+		};
+		let finality = FinalityNotification { hash: header(3).hash(), header: header(3) };
+		futures::executor::block_on(provider.maintain_proof_cache(
+			futures::stream::iter(vec![import]),
+			futures::stream::empty(),
+		));
+		assert_eq!(cached(), vec![1, 2]);
+		futures::executor::block_on(provider.maintain_proof_cache(
+			futures::stream::empty(),
+			futures::stream::iter(vec![finality]),
+		));
+		assert_eq!(cached(), vec![1]);
 	}
 }
@@ -42,7 +42,7 @@ use crate::{AuthorityList, Commit, Error};
 pub struct GrandpaJustification<Block: BlockT> {
 	round: u64,
 	pub(crate) commit: Commit<Block>,
-	votes_ancestries: Vec<Block::Header>,
+	pub(crate) votes_ancestries: Vec<Block::Header>,
 }
 
 impl<Block: BlockT> GrandpaJustification<Block> {
@@ -130,6 +130,35 @@ impl<Block: BlockT> GrandpaJustification<Block> {
 		set_id: u64,
 		voters: &VoterSet<AuthorityId>,
 	) -> Result<(), ClientError>
+	where
+		NumberFor<Block>: finality_grandpa::BlockNumberOps,
+	{
+		self.verify_with_voter_set_and_signatures(set_id, voters, false)
+	}
+
+	/// Validate the commit and the votes' ancestry proofs, verifying the signatures of the
+	/// precommits in parallel on the thread pool if the `parallel` feature is enabled.
+	///
+	/// Each signature is still verified on its own, exactly like [`Self::verify_with_voter_set`]
+	/// does, so both accept the same justifications, and the error reports the first invalid
+	/// precommit.
+	pub(crate) fn verify_with_voter_set_parallel(
+		&self,
+		set_id: u64,
+		voters: &VoterSet<AuthorityId>,
+	) -> Result<(), ClientError>
+	where
+		NumberFor<Block>: finality_grandpa::BlockNumberOps,
+	{
+		self.verify_with_voter_set_and_signatures(set_id, voters, true)
+	}
+
+	fn verify_with_voter_set_and_signatures(
+		&self,
+		set_id: u64,
+		voters: &VoterSet<AuthorityId>,
+		parallel_signatures: bool,
+	) -> Result<(), ClientError>
 	where
 		NumberFor<Block>: finality_grandpa::BlockNumberOps,
 	{
@@ -149,21 +178,21 @@ impl<Block: BlockT> GrandpaJustification<Block> {
 			}
 		}
 
-		let mut buf = Vec::new();
+		let invalid_signature = if parallel_signatures {
+			self.find_invalid_signature(set_id)
+		} else {
+			self.find_invalid_signature_sequentially(set_id)
+		};
+		if let Some(index) = invalid_signature {
+			return Err(ClientError::BadJustification(format!(
+				"invalid signature for precommit #{} by {:?} in grandpa justification",
+				index,
+				self.commit.precommits[index].id,
+			)));
+		}
+
 		let mut visited_hashes = HashSet::new();
 		for signed in self.commit.precommits.iter() {
-			if !sp_finality_grandpa::check_message_signature_with_buffer(
-				&finality_grandpa::Message::Precommit(signed.precommit.clone()),
-				&signed.id,
-				&signed.signature,
-				self.round,
-				set_id,
-				&mut buf,
-			) {
-				return Err(ClientError::BadJustification(
-					"invalid signature for precommit in grandpa justification".to_string()));
-			}
-
 			if self.commit.target_hash == signed.precommit.target_hash {
 				continue;
 			}
@@ -196,10 +225,137 @@ impl<Block: BlockT> GrandpaJustification<Block> {
 		Ok(())
 	}
 
+	/// Find the index of the first precommit with an invalid signature, checking the signatures one
+	/// by one and stopping at the first invalid one.
+	fn find_invalid_signature_sequentially(&self, set_id: u64) -> Option<usize> {
+		let mut buf = Vec::new();
+		self.commit.precommits.iter().position(|signed| {
+			!sp_finality_grandpa::check_message_signature_with_buffer(
+				&finality_grandpa::Message::Precommit(signed.precommit.clone()),
+				&signed.id,
+				&signed.signature,
+				self.round,
+				set_id,
+				&mut buf,
+			)
+		})
+	}
+
+	/// Find the index of the first precommit with an invalid signature, checking all the signatures
+	/// in parallel.
+	#[cfg(feature = "parallel")]
+	fn find_invalid_signature(&self, set_id: u64) -> Option<usize> {
+		use rayon::prelude::*;
+
+		self.commit.precommits.par_iter().position_first(|signed| {
+			!sp_finality_grandpa::check_message_signature(
+				&finality_grandpa::Message::Precommit(signed.precommit.clone()),
+				&signed.id,
+				&signed.signature,
+				self.round,
+				set_id,
+			)
+		})
+	}
+
+	/// Without the `parallel` feature there is no thread pool to check the signatures on.
+	#[cfg(not(feature = "parallel"))]
+	fn find_invalid_signature(&self, set_id: u64) -> Option<usize> {
+		self.find_invalid_signature_sequentially(set_id)
+	}
+
+	/// Ensure that no authority signed more than one of the precommits, so that its weight can't
+	/// be counted several times.
+	pub(crate) fn ensure_unique_signers(&self) -> Result<(), ClientError> {
+		let mut signers = HashSet::new();
+		for (index, signed) in self.commit.precommits.iter().enumerate() {
+			if !signers.insert(&signed.id) {
+				return Err(ClientError::BadJustification(format!(
+					"duplicate precommit #{} by {:?} in grandpa justification",
+					index,
+					signed.id,
+				)));
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Trim the justification to the first precommits reaching the threshold of the given
+	/// authority set, keeping only the ancestry headers they need. Precommits by unknown or
+	/// repeated signers are dropped.
+	///
+	/// Fails if the precommits don't reach the threshold, the trimmed justification verifies
+	/// whenever the original one does.
+	pub fn minimize_quorum(&self, authorities: &AuthorityList) -> Result<Self, ClientError>
+	where
+		NumberFor<Block>: finality_grandpa::BlockNumberOps,
+	{
+		use finality_grandpa::Chain;
+
+		let voters = VoterSet::new(authorities.iter().cloned()).ok_or(
+			ClientError::Consensus(sp_consensus::Error::InvalidAuthoritiesSet),
+		)?;
+		let threshold = voters.threshold().get();
+		let ancestry_chain = AncestryChain::<Block>::new(&self.votes_ancestries);
+
+		let mut signers = HashSet::new();
+		let mut weight = 0;
+		let mut precommits = Vec::new();
+		let mut visited_hashes = HashSet::new();
+		for signed in self.commit.precommits.iter() {
+			if weight >= threshold {
+				break;
+			}
+
+			let signer_weight = match voters.get(&signed.id) {
+				Some(info) if signers.insert(&signed.id) => info.weight().get(),
+				_ => continue,
+			};
+
+			if self.commit.target_hash != signed.precommit.target_hash {
+				let route = ancestry_chain
+					.ancestry(self.commit.target_hash, signed.precommit.target_hash)
+					.map_err(|_| ClientError::BadJustification(
+						"invalid precommit ancestry proof in grandpa justification".to_string()))?;
+				visited_hashes.insert(signed.precommit.target_hash);
+				visited_hashes.extend(route);
+			}
+
+			weight += signer_weight;
+			precommits.push(signed.clone());
+		}
+
+		if weight < threshold {
+			return Err(ClientError::BadJustification(format!(
+				"precommits in grandpa justification have a weight of {}, below the threshold {}",
+				weight,
+				threshold,
+			)));
+		}
+
+		let votes_ancestries = self.votes_ancestries
+			.iter()
+			.filter(|header| visited_hashes.contains(&header.hash()))
+			.cloned()
+			.collect();
+
+		Ok(GrandpaJustification {
+			round: self.round,
+			commit: Commit::<Block> { precommits, ..self.commit.clone() },
+			votes_ancestries,
+		})
+	}
+
 	/// The target block number and hash that this justifications proves finality for.
 	pub fn target(&self) -> (NumberFor<Block>, Block::Hash) {
 		(self.commit.target_number, self.commit.target_hash)
 	}
+
+	/// The round in which the commit of this justification was made.
+	pub fn round(&self) -> u64 {
+		self.round
+	}
 }
 
 /// A utility trait implementing `finality_grandpa::Chain` using a given set of headers.
@@ -242,3 +398,121 @@ impl<Block: BlockT> finality_grandpa::Chain<Block::Hash, NumberFor<Block>> for A
 		Ok(route)
 	}
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::finality_proof::tests::header;
+	use sp_keyring::Ed25519Keyring::{self, Alice, Bob, Charlie, Dave};
+	use substrate_test_runtime_client::runtime::{Block, Header};
+
+	/// A justification of the given header in the given round of set 0, with a precommit for it
+	/// signed by each of the given signers.
+	pub(crate) fn signed_justification(
+		round: u64,
+		target: &Header,
+		signers: &[Ed25519Keyring],
+	) -> GrandpaJustification<Block> {
+		let precommit = finality_grandpa::Precommit {
+			target_hash: target.hash(),
+			target_number: *target.number(),
+		};
+		let message = finality_grandpa::Message::Precommit(precommit.clone());
+		let payload = sp_finality_grandpa::localized_payload(round, 0, &message);
+		let precommits = signers
+			.iter()
+			.map(|keyring| finality_grandpa::SignedPrecommit {
+				precommit: precommit.clone(),
+				signature: keyring.sign(&payload).into(),
+				id: keyring.public().into(),
+			})
+			.collect();
+
+		GrandpaJustification::from_commit(
+			&Arc::new(sc_client_api::in_mem::Blockchain::<Block>::new()),
+			round,
+			Commit::<Block> {
+				target_hash: target.hash(),
+				target_number: *target.number(),
+				precommits,
+			},
+		)
+		.unwrap()
+	}
+
+	/// The authorities of the given keyrings, each with a weight of 1.
+	pub(crate) fn keyring_authorities(keyrings: &[Ed25519Keyring]) -> AuthorityList {
+		keyrings.iter().map(|keyring| (AuthorityId::from(keyring.public()), 1)).collect()
+	}
+
+	#[test]
+	fn parallel_verification_finds_the_same_invalid_signatures() {
+		let voters = VoterSet::new(keyring_authorities(&[Alice, Bob, Charlie])).unwrap();
+		let verify = |justification: &GrandpaJustification<Block>| {
+			let sequential = justification.verify_with_voter_set(0, &voters);
+			let parallel = justification.verify_with_voter_set_parallel(0, &voters);
+			assert_eq!(sequential.is_ok(), parallel.is_ok());
+			sequential.map_err(|err| err.to_string())
+		};
+
+		let mut justification = signed_justification(1, &header(3), &[Alice, Bob, Charlie]);
+		verify(&justification).unwrap();
+
+		// Bob signed a different payload, the culprit is identified.
+		justification.commit.precommits[1].signature = Bob.sign(b"invalid").into();
+		let error = verify(&justification).unwrap_err();
+		let bob: AuthorityId = Bob.public().into();
+		assert!(error.contains(&format!("precommit #1 by {:?}", bob)));
+
+		// The first invalid signature is reported, even if it's not the only one.
+		justification.commit.precommits[2].signature = Charlie.sign(b"invalid").into();
+		let error = verify(&justification).unwrap_err();
+		assert!(error.contains("precommit #1"));
+		let charlie: AuthorityId = Charlie.public().into();
+		assert!(!error.contains(&format!("{:?}", charlie)));
+	}
+
+	#[test]
+	fn duplicate_signers_are_detected() {
+		let justification = signed_justification(1, &header(3), &[Alice, Bob, Charlie]);
+		justification.ensure_unique_signers().unwrap();
+
+		let justification = signed_justification(1, &header(3), &[Alice, Bob, Charlie, Charlie]);
+		let error = justification.ensure_unique_signers().unwrap_err().to_string();
+		assert!(error.contains("duplicate precommit #3"));
+	}
+
+	#[test]
+	fn minimized_quorum_keeps_the_first_precommits_reaching_the_threshold() {
+		use crate::finality_proof::ProvableJustification;
+
+		let authorities = keyring_authorities(&[Alice, Bob, Charlie, Dave]);
+
+		// All four authorities signed, while three of them are enough.
+		let full = signed_justification(1, &header(3), &[Alice, Bob, Charlie, Dave]);
+		let minimized = full.minimize_quorum(&authorities).unwrap();
+		assert_eq!(minimized.commit.precommits, full.commit.precommits[..3].to_vec());
+		assert_eq!(
+			ProvableJustification::<Header>::signed_weight(&minimized, &authorities),
+			Some(3),
+		);
+		minimized.verify(0, &authorities).unwrap();
+
+		// A repeated signer doesn't count towards the threshold.
+		let repeated = signed_justification(1, &header(3), &[Alice, Bob, Bob, Charlie, Dave]);
+		let minimized = repeated.minimize_quorum(&authorities).unwrap();
+		assert_eq!(minimized.commit.precommits, full.commit.precommits[..3].to_vec());
+
+		// A justification below the threshold can't be trimmed.
+		signed_justification(1, &header(3), &[Alice, Bob])
+			.minimize_quorum(&authorities)
+			.unwrap_err();
+	}
+
+	#[test]
+	fn round_and_target_are_those_of_the_commit() {
+		let justification = signed_justification(7, &header(3), &[Alice]);
+		assert_eq!(justification.round(), 7);
+		assert_eq!(justification.target(), (3, header(3).hash()));
+	}
+}
@@ -123,7 +123,30 @@ mod voting_rule;
 
 pub use authorities::{AuthoritySet, AuthoritySetChanges, SharedAuthoritySet};
 pub use aux_schema::best_justification;
-pub use finality_proof::{FinalityProof, FinalityProofProvider, FinalityProofError};
+pub use finality_proof::{
+	authority_list, authority_set_commitment, check_finality_proof, check_finality_proof_async,
+	check_finality_proof_canonical, check_finality_proof_for_session, check_finality_proof_report,
+	check_finality_proof_v2, check_finality_proof_v2_for_chain,
+	check_finality_proof_v2_with_header_store, check_finality_proof_validity,
+	check_finality_proof_with_authority_root, check_finality_proof_with_config,
+	check_finality_proof_with_delta, check_finality_proof_with_justified_header,
+	check_finality_proof_with_signers, check_sparse_finality_proof, estimate_verification_cost,
+	first_valid, import_archive, is_legal_set_transition, rolling_authority_hash,
+	verify_against_local_runtime, verify_and_commit, verify_archive, verify_archive_streaming,
+	verify_batch, verify_sequence_self_contained, AuthoritySetDelta, BatchedFinalityProof,
+	BoundaryCacheReport, Clock, CodecFinalityProof, DetailedFinalityProof, ExtrinsicInclusionProof,
+	FinalityProof, FinalityProofCheckConfig, FinalityProofConfig, FinalityProofEnvelope,
+	FinalityProofError, FinalityProofProvider, FinalityProofRange, FinalityProofV2,
+	FinalityProofValidity, FinalityProofWithExtrinsic, FinalitySource, FinalityStatus, HeaderBatch,
+	HeaderCodec, MultiFinalityProof, MultiFinalityProofEntry, NoJustificationPolicy,
+	PartialVerification, PartialVerifier, ProofContinuation, ProofHeaders, ProofJustification,
+	ProofPlan, ProofSetChange, ProvableJustification, ReasonCode, RequestValidation,
+	SignedFinalityProof, SystemClock, TruncationStrategy, TrustRoot, TrustedState,
+	UncoveredBlockPolicy, VerificationBudget, VerificationCost, VerificationReport, VerifyError,
+	WeightedAuthority,
+};
+#[cfg(feature = "aggregated-justifications")]
+pub use finality_proof::{AggregateSignatureScheme, AggregatedJustification};
 pub use notification::{GrandpaJustificationSender, GrandpaJustificationStream};
 pub use import::{find_scheduled_change, find_forced_change, GrandpaBlockImport};
 pub use justification::GrandpaJustification;
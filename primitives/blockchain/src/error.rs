@@ -135,6 +135,15 @@ pub enum Error {
 	#[error("Potential long-range attack: block not in finalized chain.")]
 	NotInFinalizedChain,
 
+	#[error("Justified block unexpectedly signals an authority set change")]
+	UnexpectedSetChange,
+
+	#[error("Finality proof headers do not form a chain")]
+	BrokenHeaderChain,
+
+	#[error("Justified block {0} is not on the canonical chain")]
+	NonCanonical(String),
+
 	#[error("Failed to get hash of block for building CHT")]
 	MissingHashRequiredForCHT,
 